@@ -0,0 +1,38 @@
+//! # Parser and Evaluation Benchmarks
+//!
+//! Run with `cargo bench`. For whole scripts, `clac --bench-input
+//! FILE` reports operations per second without criterion.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::hint::black_box;
+
+use clac::calc::Calculator;
+use clac::parser::parse;
+
+/// A line with a bit of everything: literals in several radixes,
+/// float and integer arithmetic and stack shuffling.
+const LINE: &str = "1 2 + 3 * 0xff & 1.5 * 2 swap / 10 iota + + + + + + + + +";
+
+fn bench_parse(c: &mut Criterion) {
+    c.bench_function("parse", |b| b.iter(|| parse(black_box(LINE))));
+}
+
+fn bench_eval(c: &mut Criterion) {
+    let calc = Calculator::new();
+
+    // Each line starts from a clean calculator, like the REPL that
+    // clones it before evaluating.
+    c.bench_function("eval", |b| {
+        b.iter(|| calc.clone().eval(black_box(LINE)).map(|s| s.len()))
+    });
+
+    let mut deep = Calculator::new();
+    deep.eval("10000 iota").unwrap();
+
+    c.bench_function("eval with deep stack", |b| {
+        b.iter(|| deep.clone().eval(black_box("+")).map(|s| s.len()))
+    });
+}
+
+criterion_group!(benches, bench_parse, bench_eval);
+criterion_main!(benches);
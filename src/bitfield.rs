@@ -0,0 +1,118 @@
+//! # Bitfield Decoding
+//!
+//! Hardware registers pack several fields into one integer. A field
+//! specification names the bit ranges, either inline as
+//! `name:hi:lo,...` or as a TOML file with one `name = [hi, lo]` (or
+//! `name = bit`) entry per field.
+
+/// A range of bits in a register.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: String,
+    /// The most significant bit.
+    pub hi: u32,
+    /// The least significant bit.
+    pub lo: u32,
+}
+
+impl Field {
+    fn new(name: &str, hi: i64, lo: i64) -> Result<Self, String> {
+        if !(0..64).contains(&hi) || !(0..=hi).contains(&lo) {
+            return Err(format!("Invalid bits for {}: {}:{}", name, hi, lo));
+        }
+
+        Ok(Field {
+            name: name.to_string(),
+            hi: hi as u32,
+            lo: lo as u32,
+        })
+    }
+
+    /// Extract the field from a register value.
+    pub fn extract(&self, register: i64) -> i64 {
+        let width = self.hi - self.lo + 1;
+        let shifted = (register as u64) >> self.lo;
+
+        if width == 64 {
+            shifted as i64
+        } else {
+            (shifted & ((1 << width) - 1)) as i64
+        }
+    }
+}
+
+/// Parse an inline specification like `en:0:0,mode:3:1`. A single
+/// bit may omit the low bit, e.g. `en:0`.
+pub fn parse_inline(spec: &str) -> Result<Vec<Field>, String> {
+    spec.split(',')
+        .map(|field| {
+            let error = || format!("Invalid field: {}", field);
+            let parts: Vec<&str> = field.split(':').collect();
+            let bit = |i: usize| parts[i].parse::<i64>().map_err(|_| error());
+
+            match parts.len() {
+                2 => Field::new(parts[0], bit(1)?, bit(1)?),
+                3 => Field::new(parts[0], bit(1)?, bit(2)?),
+                _ => Err(error()),
+            }
+        })
+        .collect()
+}
+
+/// Parse a TOML specification with one entry per field.
+pub fn parse_toml(spec: &str) -> Result<Vec<Field>, String> {
+    let table: toml::Table = spec.parse().map_err(|e: toml::de::Error| e.to_string())?;
+
+    table
+        .iter()
+        .map(|(name, bits)| {
+            let error = || format!("Invalid field: {} = {}", name, bits);
+            let bit = |v: &toml::Value| v.as_integer().ok_or_else(error);
+
+            match bits {
+                toml::Value::Integer(b) => Field::new(name, *b, *b),
+                toml::Value::Array(a) if a.len() == 2 => Field::new(name, bit(&a[0])?, bit(&a[1])?),
+                _ => Err(error()),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inline() {
+        let fields = parse_inline("en:0,mode:3:1,top:63:60").unwrap();
+
+        assert_eq!(fields[0], Field::new("en", 0, 0).unwrap());
+        assert_eq!(
+            fields.iter().map(|f| f.extract(-0xb)).collect::<Vec<_>>(),
+            vec![1, 2, 0xf]
+        );
+
+        assert!(parse_inline("en").is_err());
+        assert!(parse_inline("en:1:2").is_err());
+        assert!(parse_inline("en:64").is_err());
+        assert!(parse_inline("en:x:0").is_err());
+    }
+
+    #[test]
+    fn test_toml() {
+        let fields = parse_toml("mode = [3, 1]\nen = 0\nall = [63, 0]").unwrap();
+
+        assert_eq!(
+            fields.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(),
+            vec!["mode", "en", "all"]
+        );
+        assert_eq!(
+            fields.iter().map(|f| f.extract(-1)).collect::<Vec<_>>(),
+            vec![7, 1, -1]
+        );
+
+        assert!(parse_toml("mode = \"3:1\"").is_err());
+        assert!(parse_toml("mode = [3]").is_err());
+        assert!(parse_toml("mode = ").is_err());
+    }
+}
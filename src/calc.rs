@@ -4,36 +4,236 @@
 //! stack-based calculator works somewhat like a classic [HP
 //! 48](https://en.wikipedia.org/wiki/HP_48_series) calculator.
 
-use std::convert::TryInto;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-use crate::types::{Operation, Radix, Value};
+use crate::bitfield;
+use crate::clipboard;
+use crate::dispatch;
+use crate::error::Error;
+use crate::explain;
+use crate::finance::{self, Tvm, TvmError};
+use crate::format::{
+    bar_chart, byte_size, float_significant, fraction, function_plot, group_digits, hex_bytes,
+    localize, permissions, si_prefix, significant_bytes, sparkline,
+};
+use crate::money;
+use crate::net;
+use crate::numeric;
+use crate::parser::{catalog, parse, parse_spanned_with, parse_values, select_column, Span};
+use crate::stats::Statistics;
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use num_bigint::{BigInt, Sign};
+use num_traits::Zero;
+
+use crate::types::{
+    AngleMode, Category, Coerce, Limits, Locale, Operation, OverflowMode, Radix, RoundingMode,
+    TvmRegister, Value, Zone,
+};
 
 /// All errors that happen during calculation are represented by this
 /// type.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum CalculatorError {
     StackUnderflow,
+    /// A line needs this many more values than are on the stack.
+    MissingValues(usize),
     InvalidOperation,
+    /// A file could not be read or contained garbage.
+    FileError(String),
+    /// An environment variable is missing or not a number.
+    EnvError(String),
+    /// The clipboard could not be read or contained garbage.
+    ClipboardError(String),
+    UnknownVariable(String),
+    /// There is no result with this number.
+    UnknownResult(usize),
+    UnknownSnapshot(String),
+    UnknownDefinition(String),
+    /// Shifts need an amount in 0..=63, unless the overflow mode
+    /// masks it.
+    ShiftOutOfRange {
+        amount: i64,
+    },
+    /// An assertion failed. Contains the formatted values.
+    AssertionFailed(String),
+    /// Definitions ran each other too deeply, e.g. in an endless
+    /// recursion.
+    RecursionLimit,
+    /// A bitfield specification is invalid.
+    BitfieldError(String),
+    /// `explain` doesn't know the stack effect of an operation.
+    CannotExplain(String),
+    /// Money in different currencies or money and a plain number
+    /// were combined. Contains both currencies.
+    CurrencyMismatch(String),
+    /// A binary operator has no overload for the types of its
+    /// operands. Contains the operator and the types.
+    NoOverload(String),
+    /// The user interrupted a running operation.
+    Interrupted,
+    /// An operation ran longer than the timeout.
+    TimedOut(Duration),
+    /// `solve` found no sign change of the function.
+    NoRoot,
+    /// A time value of money register can't be solved for.
+    FinanceError(String),
+    /// A value or the stack grew larger than allowed. Contains what
+    /// and by how much.
+    ResourceLimit(String),
 }
 
 impl std::fmt::Display for CalculatorError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
             CalculatorError::StackUnderflow => write!(f, "Stack Underflow"),
+            CalculatorError::MissingValues(1) => write!(f, "Stack Underflow: needs 1 more value"),
+            CalculatorError::MissingValues(n) => {
+                write!(f, "Stack Underflow: needs {} more values", n)
+            }
             CalculatorError::InvalidOperation => {
                 write!(f, "Invalid operation (overflow, divide by zero, ...)")
             }
+            CalculatorError::FileError(e) => write!(f, "File error: {}", e),
+            CalculatorError::EnvError(e) => write!(f, "Environment error: {}", e),
+            CalculatorError::ClipboardError(e) => write!(f, "Clipboard error: {}", e),
+            CalculatorError::UnknownVariable(name) => write!(f, "Unknown variable: {}", name),
+            CalculatorError::UnknownResult(n) => write!(f, "Unknown result: ${}", n),
+            CalculatorError::UnknownSnapshot(name) => write!(f, "Unknown snapshot: {}", name),
+            CalculatorError::UnknownDefinition(name) => write!(f, "Unknown definition: {}", name),
+            CalculatorError::RecursionLimit => write!(f, "Definitions nested too deeply"),
+            CalculatorError::AssertionFailed(e) => write!(f, "Assertion failed: {}", e),
+            CalculatorError::ShiftOutOfRange { amount } => {
+                write!(f, "Shift amount out of range: {} (0..=63)", amount)
+            }
+            CalculatorError::BitfieldError(e) => write!(f, "Bitfield error: {}", e),
+            CalculatorError::CannotExplain(op) => write!(f, "Can't explain `{}`", op),
+            CalculatorError::CurrencyMismatch(e) => write!(f, "Currency mismatch: {}", e),
+            CalculatorError::NoOverload(e) => write!(f, "Can't apply {}", e),
+            CalculatorError::Interrupted => write!(f, "Interrupted"),
+            CalculatorError::TimedOut(timeout) => write!(f, "Timed out after {:?}", timeout),
+            CalculatorError::NoRoot => write!(f, "No root found"),
+            CalculatorError::FinanceError(e) => write!(f, "Finance error: {}", e),
+            CalculatorError::ResourceLimit(e) => write!(f, "Resource limit exceeded: {}", e),
         }
     }
 }
 
 impl std::error::Error for CalculatorError {}
 
+impl From<TvmError> for CalculatorError {
+    fn from(e: TvmError) -> Self {
+        CalculatorError::FinanceError(e.to_string())
+    }
+}
+
+/// How deeply definitions may run each other.
+const MAX_RUN_DEPTH: usize = 64;
+
+/// The number of rows of function plots.
+const PLOT_HEIGHT: usize = 12;
+
+/// The most steps of function plots.
+const MAX_PLOT_STEPS: i64 = 1000;
+
+/// The most values `range` and `iota` push.
+const MAX_RANGE_LENGTH: i64 = 100_000;
+
+/// The number of results of earlier lines that are kept.
+const MAX_RESULTS: usize = 100;
+
+/// How close integrals of definitions get to the exact value.
+const INTEGRATION_TOLERANCE: f64 = 1e-10;
+
+/// The largest denominator for displaying floats as fractions.
+const MAX_DENOMINATOR: i64 = 100_000;
+
 #[derive(Debug, Clone)]
 #[must_use]
 pub struct Calculator {
     value_stack: Vec<Value>,
+    /// The bytes the values on the stack take up, kept up to date by
+    /// [push_mut](Calculator::push_mut) and
+    /// [pop_mut](Calculator::pop_mut).
+    stack_bytes: usize,
     output_radix: Radix,
+    /// The base of bare integer literals in the input.
+    input_base: u32,
+    /// Significant digits for displaying floats or 0 for the
+    /// shortest exact representation.
+    float_precision: usize,
+    /// Whether floats are displayed as fractions.
+    fractions: bool,
+    /// Whether floats are displayed with SI prefixes. Fractions take
+    /// precedence.
+    si_prefixes: bool,
+    /// Whether the prompt shows the type of each value.
+    types: bool,
+    /// Whether unary functions keep their argument below the result.
+    keep: bool,
+    /// The column of tab-separated input to read or 0 for all.
+    column: usize,
+    /// How far apart floats may be and still compare equal.
+    tolerance: f64,
+    /// How decimal numbers are written.
+    locale: Locale,
+    /// The size of digit groups in hex and binary output or 0.
+    digit_group: usize,
+    /// The number of bits hex and binary output is padded to.
+    padding: usize,
+    /// How floats are converted to integers.
+    rounding: RoundingMode,
+    /// The unit of angles for trigonometric operations.
+    angle_mode: AngleMode,
+    /// What happens on integer overflow.
+    overflow: OverflowMode,
+    /// The time zone for Unix timestamps.
+    zone: Zone,
+    /// Whether integral floats are turned into integers after each
+    /// operation.
+    demote: bool,
+    /// The statistics registers.
+    stats: Statistics,
+    /// The time value of money registers.
+    tvm: Tvm,
+    /// Lines of text that operations want to show to the user.
+    output: Vec<String>,
+    /// Values stored with `sto`.
+    variables: BTreeMap<String, Value>,
+    /// Each evaluated line and the top of the stack after it, oldest
+    /// first.
+    results: Vec<(String, Value)>,
+    /// Copies of the stack saved with `snap`.
+    snapshots: BTreeMap<String, Vec<Value>>,
+    /// User-defined constants that are pushed by name.
+    constants: BTreeMap<String, Value>,
+    /// Operations stored with `def`.
+    definitions: BTreeMap<String, Vec<Operation>>,
+    /// How many definitions are currently running.
+    run_depth: usize,
+    /// Set from elsewhere to cancel the running operation.
+    interrupt: Option<&'static AtomicBool>,
+    /// How long a single operation may run.
+    timeout: Option<Duration>,
+    /// When the running operation exceeds the timeout.
+    deadline: Option<Instant>,
+    /// How large values and the stack may grow.
+    limits: Limits,
+    /// The last line for `explain`.
+    last_line: LastLine,
+}
+
+/// An evaluated line as `explain` needs it.
+#[derive(Debug, Clone, Default)]
+struct LastLine {
+    /// The stack before the line.
+    stack: Vec<Value>,
+    ops: Vec<Operation>,
+    /// The top of the stack after the line.
+    top: Option<Value>,
 }
 
 /// A generic type for all kinds of calculator operation
@@ -58,6 +258,63 @@ impl OpImpl for SwapImpl {
     }
 }
 
+/// Push the top of the stack again.
+#[derive(Default)]
+struct DupImpl {}
+
+impl OpImpl for DupImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let a = calc.pop_mut()?;
+
+        calc.push_mut(a.clone());
+        calc.push_mut(a);
+
+        Ok(())
+    }
+}
+
+/// Remove the top of the stack.
+#[derive(Default)]
+struct DropImpl {}
+
+impl OpImpl for DropImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.pop_mut()?;
+
+        Ok(())
+    }
+}
+
+/// Remove everything from the stack.
+#[derive(Default)]
+struct ClearImpl {}
+
+impl OpImpl for ClearImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.value_stack.clear();
+
+        Ok(())
+    }
+}
+
+/// Show the top of the stack without modifying it.
+#[derive(Default)]
+struct PrintImpl {}
+
+impl OpImpl for PrintImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let v = calc
+            .value_stack
+            .last()
+            .cloned()
+            .ok_or(CalculatorError::StackUnderflow)?;
+
+        let line = calc.format_value(&v);
+        calc.print(line);
+        Ok(())
+    }
+}
+
 /// Set the output base of the calculator.
 struct SetRadixImpl {
     radix: Radix,
@@ -77,342 +334,4415 @@ impl OpImpl for SetRadixImpl {
     }
 }
 
-/// Push a value onto the stack
-struct PushImpl {
-    value: Value,
+/// Set the base of bare integer literals in the input.
+struct SetInputBaseImpl {
+    base: u32,
 }
 
-impl From<Value> for PushImpl {
-    fn from(value: Value) -> Self {
-        PushImpl { value }
+impl OpImpl for SetInputBaseImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.input_base = self.base;
+
+        Ok(())
     }
 }
 
-impl OpImpl for PushImpl {
+/// Set the number of significant digits used to display floats.
+struct SetPrecisionImpl {
+    digits: usize,
+}
+
+impl OpImpl for SetPrecisionImpl {
     fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
-        calc.push_mut(self.value);
+        calc.set_precision(self.digits);
 
         Ok(())
     }
 }
 
-/// Bit flip a value. This autoconverts to integer.
-///
-/// TODO: It would be very nice to have something like
-/// TwoParamOpImpl, but that causes the generic trait
-/// implementations to conflict.
-#[derive(Default)]
-struct BitNotImpl {}
+/// Set the size of digit groups in hex and binary output.
+struct SetDigitGroupImpl {
+    size: usize,
+}
 
-impl OpImpl for BitNotImpl {
+impl OpImpl for SetDigitGroupImpl {
     fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
-        let a = i64::from(calc.pop_mut()?);
+        calc.set_digit_group(self.size);
 
-        calc.push_mut(Value::Integer(!a));
         Ok(())
     }
 }
 
-/// Any two parameter operation that produces a single output.
-trait TwoParamOpImpl {
-    fn compute(&self, a: Value, b: Value) -> Result<Value, CalculatorError>;
+/// Set the number of bits hex and binary output is padded to.
+struct SetPaddingImpl {
+    bits: usize,
 }
 
-impl<T: TwoParamOpImpl> OpImpl for T {
+impl OpImpl for SetPaddingImpl {
     fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
-        let b = calc.pop_mut()?;
-        let a = calc.pop_mut()?;
+        calc.set_padding(self.bits);
 
-        Ok(calc.push_mut(self.compute(a, b)?))
+        Ok(())
     }
 }
 
-/// A two parameter operation that promotes both its arguments to
-/// float, if any one of it is.
-struct TwoParamFloatPromoOpImpl {
-    int_op: Box<dyn Fn(i64, i64) -> Result<Value, CalculatorError>>,
-    float_op: Box<dyn Fn(f64, f64) -> Result<Value, CalculatorError>>,
+/// Turn on or off displaying floats as fractions.
+struct SetFractionDisplayImpl {
+    on: bool,
 }
 
-impl TwoParamFloatPromoOpImpl {
-    fn new(
-        int_op: impl Fn(i64, i64) -> Result<Value, CalculatorError> + 'static,
-        float_op: impl Fn(f64, f64) -> Result<Value, CalculatorError> + 'static,
-    ) -> Self {
-        TwoParamFloatPromoOpImpl {
-            int_op: Box::new(int_op),
-            float_op: Box::new(float_op),
-        }
+impl OpImpl for SetFractionDisplayImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.set_fraction_display(self.on);
+
+        Ok(())
     }
 }
 
-impl TwoParamOpImpl for TwoParamFloatPromoOpImpl {
-    fn compute(&self, a: Value, b: Value) -> Result<Value, CalculatorError> {
-        if a.is_float() || b.is_float() {
-            (self.float_op)(a.into(), b.into())
-        } else {
-            (self.int_op)(a.into(), b.into())
-        }
+/// Turn on or off displaying floats with SI prefixes.
+struct SetSiDisplayImpl {
+    on: bool,
+}
+
+impl OpImpl for SetSiDisplayImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.si_prefixes = self.on;
+
+        Ok(())
     }
 }
 
-/// A two parameter operation that promotes both its arguments to
-/// integers all the time.
-struct TwoParamIntPromoOpImpl {
-    int_op: Box<dyn Fn(i64, i64) -> Result<Value, CalculatorError>>,
+/// Turn on or off showing the type of each value in the prompt.
+struct SetTypeDisplayImpl {
+    on: bool,
 }
 
-impl TwoParamIntPromoOpImpl {
-    fn new(int_op: impl Fn(i64, i64) -> Result<Value, CalculatorError> + 'static) -> Self {
-        TwoParamIntPromoOpImpl {
-            int_op: Box::new(int_op),
-        }
+impl OpImpl for SetTypeDisplayImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.types = self.on;
+
+        Ok(())
     }
 }
 
-impl TwoParamOpImpl for TwoParamIntPromoOpImpl {
-    fn compute(&self, a: Value, b: Value) -> Result<Value, CalculatorError> {
-        (self.int_op)(i64::from(a), i64::from(b))
+/// Turn on or off keeping the argument of unary functions.
+struct SetKeepImpl {
+    on: bool,
+}
+
+impl OpImpl for SetKeepImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.keep = self.on;
+
+        Ok(())
     }
 }
 
-impl From<Operation> for Box<dyn OpImpl> {
-    fn from(op: Operation) -> Self {
-        match op {
-            Operation::Add => Box::new(TwoParamFloatPromoOpImpl::new(
-                |a, b| -> Result<Value, CalculatorError> {
-                    Ok(Value::Integer(
-                        a.checked_add(b).ok_or(CalculatorError::InvalidOperation)?,
-                    ))
-                },
-                |a, b| -> Result<Value, CalculatorError> { Ok(Value::Float(a + b)) },
-            )),
+/// Choose the column of tab-separated input to read.
+struct SetColumnImpl {
+    column: usize,
+}
 
-            Operation::BitAnd => Box::new(TwoParamIntPromoOpImpl::new(
-                |a, b| -> Result<Value, CalculatorError> { Ok(Value::Integer(a & b)) },
-            )),
+impl OpImpl for SetColumnImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.column = self.column;
 
-            Operation::BitNot => Box::new(BitNotImpl::default()),
+        Ok(())
+    }
+}
 
-            Operation::BitOr => Box::new(TwoParamIntPromoOpImpl::new(
-                |a, b| -> Result<Value, CalculatorError> { Ok(Value::Integer(a | b)) },
-            )),
+/// Set how far apart floats may be and still compare equal.
+struct SetToleranceImpl {
+    tolerance: f64,
+}
 
-            Operation::BitXor => Box::new(TwoParamIntPromoOpImpl::new(
-                |a, b| -> Result<Value, CalculatorError> { Ok(Value::Integer(a ^ b)) },
-            )),
+impl OpImpl for SetToleranceImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.tolerance = self.tolerance;
 
-            Operation::Divide => Box::new(TwoParamFloatPromoOpImpl::new(
-                |a, b| -> Result<Value, CalculatorError> {
-                    Ok(Value::Integer(
-                        a.checked_div(b).ok_or(CalculatorError::InvalidOperation)?,
-                    ))
-                },
-                |a, b| -> Result<Value, CalculatorError> { Ok(Value::Float(a / b)) },
-            )),
+        Ok(())
+    }
+}
 
-            Operation::LeftShift => Box::new(TwoParamIntPromoOpImpl::new(
-                |a, b| -> Result<Value, CalculatorError> {
-                    Ok(Value::Integer(
-                        a.checked_shl(
-                            b.try_into()
-                                .map_err(|_| CalculatorError::InvalidOperation)?,
-                        )
-                        .ok_or(CalculatorError::InvalidOperation)?,
-                    ))
-                },
-            )),
+/// Push a value onto the stack
+struct PushImpl {
+    value: Value,
+}
 
-            Operation::Multiply => Box::new(TwoParamFloatPromoOpImpl::new(
-                |a, b| -> Result<Value, CalculatorError> {
-                    Ok(Value::Integer(
-                        a.checked_mul(b).ok_or(CalculatorError::InvalidOperation)?,
-                    ))
-                },
-                |a, b| -> Result<Value, CalculatorError> { Ok(Value::Float(a * b)) },
-            )),
+impl From<Value> for PushImpl {
+    fn from(value: Value) -> Self {
+        PushImpl { value }
+    }
+}
 
-            Operation::SetRadix(r) => Box::new(SetRadixImpl::from(r)),
+impl OpImpl for PushImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.push_mut(self.value.clone());
 
-            Operation::Swap => Box::new(SwapImpl::default()),
+        Ok(())
+    }
+}
 
-            Operation::Subtract => Box::new(TwoParamFloatPromoOpImpl::new(
-                |a, b| -> Result<Value, CalculatorError> {
-                    Ok(Value::Integer(
-                        a.checked_sub(b).ok_or(CalculatorError::InvalidOperation)?,
-                    ))
-                },
-                |a, b| -> Result<Value, CalculatorError> { Ok(Value::Float(a - b)) },
-            )),
+/// Push all numbers contained in a file.
+struct ReadFileImpl {
+    path: String,
+}
 
-            Operation::RightShift => Box::new(TwoParamIntPromoOpImpl::new(
-                |a, b| -> Result<Value, CalculatorError> {
-                    Ok(Value::Integer(
-                        a.checked_shr(
-                            b.try_into()
-                                .map_err(|_| CalculatorError::InvalidOperation)?,
-                        )
-                        .ok_or(CalculatorError::InvalidOperation)?,
-                    ))
-                },
-            )),
+impl OpImpl for ReadFileImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let error =
+            |e: &dyn std::fmt::Display| CalculatorError::FileError(format!("{}: {}", self.path, e));
 
-            Operation::Push(v) => Box::new(PushImpl::from(v)),
+        let contents = std::fs::read_to_string(&self.path).map_err(|e| error(&e))?;
+
+        for v in
+            parse_values(&calc.select_input(&contents), calc.overflow).map_err(|e| error(&e))?
+        {
+            calc.push_mut(v);
         }
+
+        Ok(())
     }
 }
 
-impl Default for Calculator {
-    fn default() -> Self {
-        Self::new()
-    }
+/// Push the numeric value of an environment variable.
+struct PushEnvImpl {
+    name: String,
 }
 
-impl Calculator {
-    #[allow(dead_code)]
-    pub fn new() -> Self {
-        Self {
-            value_stack: vec![],
-            output_radix: Radix::Dec,
+impl OpImpl for PushEnvImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let error =
+            |e: &dyn std::fmt::Display| CalculatorError::EnvError(format!("{}: {}", self.name, e));
+
+        let contents = std::env::var(&self.name).map_err(|e| error(&e))?;
+
+        match &parse_values(&contents, calc.overflow).map_err(|e| error(&e))?[..] {
+            [v] => {
+                calc.push_mut(v.clone());
+                Ok(())
+            }
+            _ => Err(error(&"not a single number")),
         }
     }
+}
 
-    pub fn pop_mut(&mut self) -> Result<Value, CalculatorError> {
-        self.value_stack
-            .pop()
-            .ok_or(CalculatorError::StackUnderflow)
-    }
+/// Apply the numbers and operations from the clipboard.
+#[derive(Default)]
+struct PasteImpl {}
 
-    pub fn push_mut(&mut self, v: Value) {
-        self.value_stack.push(v)
-    }
+impl OpImpl for PasteImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let contents = clipboard::paste().map_err(CalculatorError::ClipboardError)?;
+        let ops = parse(&calc.select_input(&contents))
+            .map_err(|e| CalculatorError::ClipboardError(e.to_string()))?;
 
-    #[allow(dead_code)]
-    pub fn push(&self, v: Value) -> Calculator {
-        let mut new_calc = self.clone();
+        // The clipboard would still contain the same text, so this
+        // would never end.
+        if ops.contains(&Operation::Paste) {
+            return Err(CalculatorError::ClipboardError(
+                "Clipboard contains paste".to_string(),
+            ));
+        }
 
-        new_calc.push_mut(v);
-        new_calc
+        for op in ops {
+            calc.apply_mut(op)?;
+        }
+
+        Ok(())
     }
+}
 
-    /// Apply a single operation on the calculator.
-    pub fn apply_mut(&mut self, op: Operation) -> Result<(), CalculatorError> {
-        Box::<dyn OpImpl>::from(op).execute(self)
+/// Show the stack as a bar chart or sparkline without modifying it.
+struct PlotImpl {
+    sparkline: bool,
+}
+
+/// The width of the longest bar in a bar chart.
+const PLOT_WIDTH: usize = 40;
+
+impl OpImpl for PlotImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let values: Vec<f64> = calc.value_stack.iter().cloned().map(f64::from).collect();
+
+        if self.sparkline {
+            calc.print(sparkline(&values));
+        } else {
+            let labels: Vec<String> = calc
+                .value_stack
+                .iter()
+                .map(|v| calc.format_value(v))
+                .collect();
+
+            for line in bar_chart(&labels, &values, PLOT_WIDTH) {
+                calc.print(line);
+            }
+        }
+
+        Ok(())
     }
+}
 
-    /// A side-effect free version of [apply_mut] that returns a new
-    /// calculator with the result.
-    #[allow(dead_code)]
-    pub fn apply(&self, op: Operation) -> Result<Self, CalculatorError> {
-        let mut new_calc = self.clone();
+/// The distance from `a` to the next float away from zero. The
+/// largest float has no next one, so it gets the gap below instead.
+fn ulp(a: f64) -> f64 {
+    let a = a.abs();
+    let next = a.next_up();
 
-        new_calc.apply_mut(op)?;
-        Ok(new_calc)
+    if next.is_infinite() && a.is_finite() {
+        a - a.next_down()
+    } else {
+        next - a
     }
+}
 
-    pub fn set_radix(&mut self, radix: Radix) {
-        self.output_radix = radix;
+/// The largest integer whose square is at most `a`.
+fn isqrt(a: BigInt) -> Result<Value, CalculatorError> {
+    if a.sign() == Sign::Minus {
+        return Err(CalculatorError::InvalidOperation);
     }
 
-    #[allow(dead_code)]
-    pub fn stack(&self) -> &[Value] {
-        &self.value_stack
+    Ok(Value::from(a.sqrt()))
+}
+
+/// 1 if `a` is a power of two, 0 otherwise.
+fn is_power_of_two(a: BigInt) -> Result<Value, CalculatorError> {
+    let power = a.sign() == Sign::Plus && a.magnitude().count_ones() == 1;
+
+    Ok(Value::Integer(power as i64))
+}
+
+/// The binary logarithm of `a` rounded down.
+fn log2i(a: BigInt) -> Result<Value, CalculatorError> {
+    if a.sign() != Sign::Plus {
+        return Err(CalculatorError::InvalidOperation);
     }
+
+    Ok(Value::Integer(a.bits() as i64 - 1))
 }
 
-impl std::fmt::Display for Calculator {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        let tokens: Vec<String> = self
-            .value_stack
-            .iter()
-            .map(|v| match v {
-                Value::Integer(i) => match self.output_radix {
-                    Radix::Dec => format!("{}", i),
-                    Radix::Hex => format!("{:#x}", i),
-                    Radix::Bin => format!("{:#b}", i),
-                },
-                Value::Float(fl) => format!("{}", fl),
-            })
-            .collect();
+/// Whether two values differ by at most `tolerance`. Integers are
+/// compared exactly, unless there is a float involved. Amounts of money
+/// must be in the same currency.
+fn near(a: &Value, b: &Value, tolerance: f64) -> Result<bool, CalculatorError> {
+    if a.is_money() || b.is_money() {
+        let equal = dispatch::compare(a, b)? == std::cmp::Ordering::Equal;
 
-        write!(f, "{}", tokens.join(" "))
+        Ok(equal || (f64::from(a.clone()) - f64::from(b.clone())).abs() <= tolerance)
+    } else if tolerance > 0.0 || a.is_float() || b.is_float() {
+        Ok((f64::from(a.clone()) - f64::from(b.clone())).abs() <= tolerance)
+    } else {
+        Ok(BigInt::from(a.clone()) == BigInt::from(b.clone()))
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Fail unless two values are equal, or with a tolerance, near each
+/// other. Integers and floats with the same value are equal. Floats
+/// within `eps` of each other always pass.
+struct AssertImpl {
+    with_tolerance: bool,
+}
+
+impl OpImpl for AssertImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let tolerance = if self.with_tolerance {
+            f64::from(calc.pop_mut()?)
+        } else {
+            0.0
+        };
+        let b = calc.pop_mut()?;
+        let a = calc.pop_mut()?;
+
+        let equal = near(&a, &b, tolerance)?
+            || ((a.is_float() || b.is_float()) && near(&a, &b, calc.tolerance)?);
+
+        if !equal {
+            let (a, b) = (calc.format_value(&a), calc.format_value(&b));
+
+            return Err(CalculatorError::AssertionFailed(if self.with_tolerance {
+                format!("{} is not within {} of {}", a, tolerance, b)
+            } else {
+                format!("{} != {}", a, b)
+            }));
+        }
+
+        Ok(())
+    }
+}
+
+/// Compare two values like `assert-eq`, but push the result as 1 or 0.
+struct CompareImpl {
+    equal: bool,
+}
+
+impl OpImpl for CompareImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let b = calc.pop_mut()?;
+        let a = calc.pop_mut()?;
+
+        let tolerance = if a.is_float() || b.is_float() {
+            calc.tolerance
+        } else {
+            0.0
+        };
+
+        calc.push_mut(Value::Integer(
+            (near(&a, &b, tolerance)? == self.equal).into(),
+        ));
+        Ok(())
+    }
+}
+
+/// Limit a value to the range from a lower to an upper bound, or
+/// check whether it is in there.
+struct RangeCheckImpl {
+    /// Whether to push 1 or 0 instead of the limited value.
+    predicate: bool,
+}
+
+impl OpImpl for RangeCheckImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let hi = calc.pop_mut()?;
+        let lo = calc.pop_mut()?;
+        let x = calc.pop_mut()?;
+
+        if dispatch::compare(&lo, &hi)? == std::cmp::Ordering::Greater {
+            return Err(CalculatorError::InvalidOperation);
+        }
+
+        let below = dispatch::compare(&x, &lo)? == std::cmp::Ordering::Less;
+        let above = dispatch::compare(&x, &hi)? == std::cmp::Ordering::Greater;
+
+        calc.push_mut(match (self.predicate, below, above) {
+            (true, _, _) => Value::Integer((!below && !above).into()),
+            (false, true, _) => lo,
+            (false, _, true) => hi,
+            (false, false, false) => x,
+        });
+        Ok(())
+    }
+}
+
+/// Push a sequence of numbers. Without a step, this counts from 1.
+struct RangeImpl {
+    with_step: bool,
+}
+
+impl OpImpl for RangeImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let (start, stop, step) = if self.with_step {
+            let step = calc.pop_mut()?;
+            let stop = calc.pop_mut()?;
+            (calc.pop_mut()?, stop, step)
+        } else {
+            (Value::Integer(1), calc.pop_mut()?, Value::Integer(1))
+        };
+
+        let values: Vec<Value> = match (start, stop, step) {
+            (Value::Integer(start), Value::Integer(stop), Value::Integer(step)) => {
+                if step == 0 {
+                    return Err(CalculatorError::InvalidOperation);
+                }
+
+                let count = stop
+                    .checked_sub(start)
+                    .and_then(|d| d.checked_div(step))
+                    .filter(|n| *n < MAX_RANGE_LENGTH)
+                    .ok_or(CalculatorError::InvalidOperation)?
+                    + 1;
+
+                (0..count.max(0))
+                    .map(|i| Value::Integer(start + i * step))
+                    .collect()
+            }
+            (start, stop, step) => {
+                let (start, stop, step) = (f64::from(start), f64::from(stop), f64::from(step));
+                // Allow for rounding errors, so the stop value is
+                // part of the range.
+                let count = ((stop - start) / step + 1e-9).floor() + 1.0;
+
+                if !count.is_finite() || count > MAX_RANGE_LENGTH as f64 {
+                    return Err(CalculatorError::InvalidOperation);
+                }
+
+                (0..count.max(0.0) as i64)
+                    .map(|i| Value::Float(start + i as f64 * step))
+                    .collect()
+            }
+        };
+
+        calc.value_stack.extend(values);
+        Ok(())
+    }
+}
+
+/// Store operations under a name.
+struct DefineImpl {
+    name: String,
+    ops: Vec<Operation>,
+}
+
+impl OpImpl for DefineImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.definitions.insert(self.name.clone(), self.ops.clone());
+
+        Ok(())
+    }
+}
+
+/// Apply operations on a fresh stack and push the values that are
+/// left on it. Everything else, like variables, is shared.
+struct BlockImpl {
+    ops: Vec<Operation>,
+}
+
+impl OpImpl for BlockImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let outer = std::mem::take(&mut calc.value_stack);
+        let result = self
+            .ops
+            .iter()
+            .try_for_each(|op| calc.apply_mut(op.clone()));
+        let inner = std::mem::replace(&mut calc.value_stack, outer);
+
+        result?;
+        calc.value_stack.extend(inner);
+        Ok(())
+    }
+}
+
+/// Apply the operations stored under a name.
+struct RunImpl {
+    name: String,
+}
+
+impl OpImpl for RunImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.run(&self.name)
+    }
+}
+
+/// Plot a definition as a function from the top of the stack to the
+/// top of the stack.
+struct PlotFunctionImpl {
+    name: String,
+}
+
+impl OpImpl for PlotFunctionImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let steps = calc.pop_mut()?;
+        let steps = calc.to_integer(steps)?;
+        let stop = f64::from(calc.pop_mut()?);
+        let start = f64::from(calc.pop_mut()?);
+
+        if !(1..=MAX_PLOT_STEPS).contains(&steps) {
+            return Err(CalculatorError::InvalidOperation);
+        }
+        if !calc.definitions.contains_key(&self.name) {
+            return Err(CalculatorError::UnknownDefinition(self.name.clone()));
+        }
+
+        // Each point is calculated on a copy of the calculator.
+        // Points where the function fails are left out, unless it was
+        // cancelled.
+        let values = (0..=steps)
+            .map(|i| {
+                let mut scratch = calc.clone();
+                let x = start + (stop - start) * i as f64 / steps as f64;
+
+                scratch.set_stack(vec![Value::Float(x)]);
+                match scratch.run(&self.name) {
+                    Ok(()) => Ok(scratch.top().map(f64::from)),
+                    Err(e @ (CalculatorError::Interrupted | CalculatorError::TimedOut(_))) => {
+                        Err(e)
+                    }
+                    Err(_) => Ok(None),
+                }
+            })
+            .collect::<Result<Vec<Option<f64>>, CalculatorError>>()?;
+
+        for line in function_plot(&values, PLOT_HEIGHT) {
+            calc.print(line);
+        }
+        calc.print(format!(
+            "x = {} .. {}",
+            float_significant(start, 4),
+            float_significant(stop, 4)
+        ));
+
+        Ok(())
+    }
+}
+
+/// Integrate a definition as a function from the top of the stack to
+/// the top of the stack between two values.
+struct IntegrateImpl {
+    name: String,
+}
+
+impl OpImpl for IntegrateImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let b = f64::from(calc.pop_mut()?);
+        let a = f64::from(calc.pop_mut()?);
+        let integral = numeric::integrate(calc.function(&self.name)?, a, b, INTEGRATION_TOLERANCE)?;
+
+        calc.push_mut(Value::Float(integral));
+        Ok(())
+    }
+}
+
+/// Differentiate a definition as a function from the top of the stack
+/// to the top of the stack.
+struct DeriveImpl {
+    name: String,
+}
+
+impl OpImpl for DeriveImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let x = f64::from(calc.pop_mut()?);
+        let derivative = numeric::derivative(calc.function(&self.name)?, x)?;
+
+        calc.push_mut(Value::Float(derivative));
+        Ok(())
+    }
+}
+
+/// Find where a definition as a function from the top of the stack to
+/// the top of the stack is zero.
+struct SolveImpl {
+    name: String,
+    /// Whether there are two values that bracket the root instead of
+    /// a guess.
+    bracket: bool,
+}
+
+impl OpImpl for SolveImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let b = f64::from(calc.pop_mut()?);
+        let f = calc.function(&self.name)?;
+
+        let root = if self.bracket {
+            let a = f64::from(calc.pop_mut()?);
+            numeric::find_root(f, a, b, calc.tolerance)?
+        } else {
+            numeric::solve(f, b, calc.tolerance)?
+        };
+
+        calc.push_mut(Value::Float(root.ok_or(CalculatorError::NoRoot)?));
+        Ok(())
+    }
+}
+
+/// Show the fields of the value on top of the stack without
+/// modifying it.
+struct DecodeImpl {
+    /// An inline specification or a TOML file.
+    spec: String,
+}
+
+impl OpImpl for DecodeImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let fields = if self.spec.ends_with(".toml") {
+            let contents = std::fs::read_to_string(&self.spec)
+                .map_err(|e| CalculatorError::FileError(format!("{}: {}", self.spec, e)))?;
+
+            bitfield::parse_toml(&contents)
+        } else {
+            bitfield::parse_inline(&self.spec)
+        }
+        .map_err(CalculatorError::BitfieldError)?;
+
+        let v = calc
+            .value_stack
+            .last()
+            .cloned()
+            .ok_or(CalculatorError::StackUnderflow)?;
+        let register = calc.to_integer(v)?;
+        let name_width = fields.iter().map(|f| f.name.len()).max().unwrap_or(0);
+
+        for field in fields {
+            let line = format!(
+                "{:w$} [{}:{}] = {}",
+                field.name,
+                field.hi,
+                field.lo,
+                calc.format_value(&Value::Integer(field.extract(register))),
+                w = name_width
+            );
+            calc.print(line);
+        }
+
+        Ok(())
+    }
+}
+
+/// Show the value on top of the stack as file permissions without
+/// modifying it.
+#[derive(Default)]
+struct ShowPermissionsImpl {}
+
+impl OpImpl for ShowPermissionsImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let v = calc
+            .value_stack
+            .last()
+            .cloned()
+            .ok_or(CalculatorError::StackUnderflow)?;
+        let mode = calc.to_integer(v)?;
+
+        if !(0..=0o7777).contains(&mode) {
+            return Err(CalculatorError::InvalidOperation);
+        }
+
+        calc.print(format!("{} {:04o}", permissions(mode), mode));
+        Ok(())
+    }
+}
+
+/// Show the value on top of the stack as a byte size in IEC and SI
+/// units without modifying it.
+#[derive(Default)]
+struct ShowBytesImpl {}
+
+impl OpImpl for ShowBytesImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let v = calc
+            .value_stack
+            .last()
+            .cloned()
+            .ok_or(CalculatorError::StackUnderflow)?;
+        let bytes = f64::from(v);
+
+        calc.print(format!(
+            "{} ({})",
+            byte_size(bytes, 1024.0),
+            byte_size(bytes, 1000.0)
+        ));
+        Ok(())
+    }
+}
+
+/// Show the bytes of the integer on top of the stack in both byte
+/// orders without modifying it.
+#[derive(Default)]
+struct DumpImpl {}
+
+impl OpImpl for DumpImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let v = calc
+            .value_stack
+            .last()
+            .cloned()
+            .ok_or(CalculatorError::StackUnderflow)?;
+        let bytes = significant_bytes(calc.to_integer(v)?);
+
+        calc.print(format!("{} (big endian)", hex_bytes(&bytes)));
+        calc.print(format!("{} (little endian)", hex_bytes(bytes.iter().rev())));
+        Ok(())
+    }
+}
+
+/// Show the Unix timestamp on top of the stack as date and time in
+/// the current time zone without modifying it.
+#[derive(Default)]
+struct FromUnixImpl {}
+
+impl OpImpl for FromUnixImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let v = calc
+            .value_stack
+            .last()
+            .cloned()
+            .ok_or(CalculatorError::StackUnderflow)?;
+        let utc = DateTime::from_timestamp(calc.to_integer(v)?, 0)
+            .ok_or(CalculatorError::InvalidOperation)?;
+        let format = "%Y-%m-%d %H:%M:%S %Z";
+
+        calc.print(match calc.zone {
+            Zone::Local => utc.with_timezone(&Local).format(format).to_string(),
+            Zone::Named(tz) => utc.with_timezone(&tz).format(format).to_string(),
+        });
+        Ok(())
+    }
+}
+
+/// Turn year, month, day, hour, minute and second in the current time
+/// zone into a Unix timestamp.
+#[derive(Default)]
+struct ToUnixImpl {}
+
+impl OpImpl for ToUnixImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let mut fields = [0i64; 6];
+
+        for field in fields.iter_mut().rev() {
+            let v = calc.pop_mut()?;
+            *field = calc.to_integer(v)?;
+        }
+
+        let field = |i: usize| u32::try_from(fields[i]).ok();
+        let naive = i32::try_from(fields[0])
+            .ok()
+            .and_then(|year| NaiveDate::from_ymd_opt(year, field(1)?, field(2)?))
+            .and_then(|date| date.and_hms_opt(field(3)?, field(4)?, field(5)?))
+            .ok_or(CalculatorError::InvalidOperation)?;
+
+        // Times that happen twice when clocks are turned back resolve
+        // to the first one. Skipped times don't exist.
+        let timestamp = match calc.zone {
+            Zone::Local => Local
+                .from_local_datetime(&naive)
+                .earliest()
+                .map(|t| t.timestamp()),
+            Zone::Named(tz) => tz
+                .from_local_datetime(&naive)
+                .earliest()
+                .map(|t| t.timestamp()),
+        }
+        .ok_or(CalculatorError::InvalidOperation)?;
+
+        calc.push_mut(Value::Integer(timestamp));
+        Ok(())
+    }
+}
+
+/// Set the time zone for Unix timestamps.
+struct SetZoneImpl {
+    zone: Zone,
+}
+
+impl OpImpl for SetZoneImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.zone = self.zone;
+
+        Ok(())
+    }
+}
+
+/// Move the top of the stack into a variable.
+struct StoreImpl {
+    name: String,
+}
+
+impl OpImpl for StoreImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let v = calc.pop_mut()?;
+
+        calc.variables.insert(self.name.clone(), v);
+        Ok(())
+    }
+}
+
+/// Combine the top of the stack into a variable. The variable is
+/// the first operand, so `sto- name` subtracts from it.
+struct StoreWithImpl {
+    name: String,
+    op: Operation,
+}
+
+impl OpImpl for StoreWithImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let v = calc.pop_mut()?;
+        let stored = calc
+            .variables
+            .get(&self.name)
+            .ok_or_else(|| CalculatorError::UnknownVariable(self.name.clone()))?
+            .clone();
+
+        // Calculate on a scratch stack, so the operation behaves like
+        // it does on the stack, e.g. with overflow promotion.
+        let mut scratch = calc.clone();
+        scratch.set_stack(vec![stored, v]);
+        scratch.apply_mut(self.op.clone())?;
+
+        let result = scratch.pop_mut()?;
+        calc.variables.insert(self.name.clone(), result);
+        Ok(())
+    }
+}
+
+/// Push the value of a variable.
+struct RecallImpl {
+    name: String,
+}
+
+impl OpImpl for RecallImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let v = calc
+            .variables
+            .get(&self.name)
+            .ok_or_else(|| CalculatorError::UnknownVariable(self.name.clone()))?
+            .clone();
+
+        calc.push_mut(v);
+        Ok(())
+    }
+}
+
+/// Push a user-defined constant.
+struct PushConstantImpl {
+    name: String,
+}
+
+impl OpImpl for PushConstantImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let v = calc
+            .constants
+            .get(&self.name)
+            .ok_or_else(|| CalculatorError::UnknownVariable(self.name.clone()))?
+            .clone();
+
+        calc.push_mut(v);
+        Ok(())
+    }
+}
+
+/// Save a copy of the stack.
+struct SnapshotImpl {
+    name: String,
+}
+
+impl OpImpl for SnapshotImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.snapshots
+            .insert(self.name.clone(), calc.value_stack.clone());
+
+        Ok(())
+    }
+}
+
+/// Replace the stack with a saved copy. The copy is kept, so it can be
+/// restored again.
+struct RestoreImpl {
+    name: String,
+}
+
+impl OpImpl for RestoreImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.value_stack = calc
+            .snapshots
+            .get(&self.name)
+            .ok_or_else(|| CalculatorError::UnknownSnapshot(self.name.clone()))?
+            .clone();
+
+        Ok(())
+    }
+}
+
+/// Compare two snapshots value by value from the bottom of the stack
+/// and show where they differ.
+struct DiffImpl {
+    a: String,
+    b: String,
+}
+
+impl OpImpl for DiffImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let snapshot = |name: &String| {
+            calc.snapshots
+                .get(name)
+                .ok_or_else(|| CalculatorError::UnknownSnapshot(name.clone()))
+        };
+        let (a, b) = (snapshot(&self.a)?, snapshot(&self.b)?);
+
+        // Levels are counted from the bottom, because that is where
+        // two variants of a calculation usually agree.
+        let lines: Vec<String> = (0..a.len().max(b.len()))
+            .filter_map(|i| match (a.get(i), b.get(i)) {
+                (Some(x), Some(y)) if x == y => None,
+                (Some(x), Some(y)) => Some(format!(
+                    "~ {}: {} -> {}",
+                    i + 1,
+                    calc.format_value(x),
+                    calc.format_value(y)
+                )),
+                (Some(x), None) => Some(format!("- {}: {}", i + 1, calc.format_value(x))),
+                (None, Some(y)) => Some(format!("+ {}: {}", i + 1, calc.format_value(y))),
+                (None, None) => None,
+            })
+            .collect();
+
+        if lines.is_empty() {
+            calc.print(format!("{} and {} are the same", self.a, self.b));
+        }
+        for line in lines {
+            calc.print(line);
+        }
+
+        Ok(())
+    }
+}
+
+/// Push the result of an earlier line.
+struct RecallResultImpl {
+    /// 1 is the latest result.
+    index: usize,
+}
+
+impl OpImpl for RecallResultImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let v = calc
+            .results
+            .len()
+            .checked_sub(self.index)
+            .filter(|_| self.index > 0)
+            .map(|i| calc.results[i].1.clone())
+            .ok_or(CalculatorError::UnknownResult(self.index))?;
+
+        calc.push_mut(v);
+        Ok(())
+    }
+}
+
+/// Show all variables and their values.
+#[derive(Default)]
+struct ListVariablesImpl {}
+
+impl OpImpl for ListVariablesImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        for line in calc.format_variables() {
+            calc.print(line);
+        }
+
+        Ok(())
+    }
+}
+
+/// List the operations of one or all categories.
+struct CatalogImpl {
+    category: Option<Category>,
+}
+
+impl OpImpl for CatalogImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        for (category, names) in catalog() {
+            match self.category {
+                Some(c) if c == category => calc.print(names.join(" ")),
+                Some(_) => {}
+                None => calc.print(format!(
+                    "{:12} {}",
+                    format!("{}:", category),
+                    names.join(" ")
+                )),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Show the last line as an infix expression and its result.
+#[derive(Default)]
+struct ExplainImpl {}
+
+impl OpImpl for ExplainImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let last = &calc.last_line;
+        let stack = last.stack.iter().map(|v| calc.format_value(v)).collect();
+        let expressions = explain::infix(stack, &last.ops, |v| calc.format_value(v))
+            .map_err(|op| CalculatorError::CannotExplain(op.to_string()))?;
+
+        match (expressions.last(), &last.top) {
+            (Some(expression), Some(top)) => {
+                let line = format!("{} = {}", expression, calc.format_value(top));
+
+                calc.print(line);
+                Ok(())
+            }
+            _ => Err(CalculatorError::StackUnderflow),
+        }
+    }
+}
+
+/// Bit flip a value. This autoconverts to integer.
+///
+/// TODO: It would be very nice to have something like
+/// TwoParamOpImpl, but that causes the generic trait
+/// implementations to conflict.
+#[derive(Default)]
+struct BitNotImpl {}
+
+impl OpImpl for BitNotImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let v = calc.pop_mut()?;
+        let a = calc.to_integer(v)?;
+
+        calc.push_mut(Value::Integer(!a));
+        Ok(())
+    }
+}
+
+/// Convert a value to integer according to the rounding mode.
+#[derive(Default)]
+struct ToIntegerImpl {}
+
+impl OpImpl for ToIntegerImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let v = calc.pop_mut()?;
+
+        // Big integers stay as they are.
+        match v {
+            Value::Big(_) => calc.push_mut(v),
+            _ => {
+                let a = calc.to_integer(v)?;
+                calc.push_mut(Value::Integer(a))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Set how floats are converted to integers.
+struct SetRoundingImpl {
+    mode: RoundingMode,
+}
+
+impl OpImpl for SetRoundingImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.set_rounding(self.mode);
+
+        Ok(())
+    }
+}
+
+/// Choose what happens on integer overflow.
+struct SetOverflowImpl {
+    mode: OverflowMode,
+}
+
+impl OpImpl for SetOverflowImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.set_overflow(self.mode);
+
+        Ok(())
+    }
+}
+
+/// Turn on or off demoting integral floats to integers.
+struct SetDemotionImpl {
+    on: bool,
+}
+
+impl OpImpl for SetDemotionImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.set_demotion(self.on);
+
+        Ok(())
+    }
+}
+
+/// A one parameter operation that promotes its argument to integer.
+struct OneParamIntOpImpl {
+    int_op: Box<dyn Fn(i64) -> Result<Value, CalculatorError>>,
+}
+
+impl OneParamIntOpImpl {
+    fn new(int_op: impl Fn(i64) -> Result<Value, CalculatorError> + 'static) -> Self {
+        OneParamIntOpImpl {
+            int_op: Box::new(int_op),
+        }
+    }
+}
+
+impl OpImpl for OneParamIntOpImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let v = calc.pop_mut()?;
+        let a = calc.to_integer(v)?;
+
+        calc.push_mut((self.int_op)(a)?);
+        Ok(())
+    }
+}
+
+/// A one parameter operation on integers of any size, which keeps
+/// large values exact instead of going through floats.
+struct OneParamBigOpImpl {
+    big_op: fn(BigInt) -> Result<Value, CalculatorError>,
+}
+
+impl OpImpl for OneParamBigOpImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let a = match calc.pop_mut()? {
+            Value::Big(b) => b,
+            v => BigInt::from(calc.to_integer(v)?),
+        };
+
+        calc.push_mut((self.big_op)(a)?);
+        Ok(())
+    }
+}
+
+/// Check whether an address is in a subnet given by network address
+/// and prefix length.
+#[derive(Default)]
+struct InSubnetImpl {}
+
+impl OpImpl for InSubnetImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let prefix = calc.pop_mut()?;
+        let network = calc.pop_mut()?;
+        let address = calc.pop_mut()?;
+
+        let result = net::in_subnet(
+            calc.to_integer(address)?,
+            calc.to_integer(network)?,
+            calc.to_integer(prefix)?,
+        )
+        .ok_or(CalculatorError::InvalidOperation)?;
+
+        calc.push_mut(Value::Integer(result.into()));
+        Ok(())
+    }
+}
+
+/// A one parameter operation that always works on floats.
+struct OneParamFloatOpImpl {
+    float_op: Box<dyn Fn(f64) -> Result<Value, CalculatorError>>,
+}
+
+impl OneParamFloatOpImpl {
+    fn new(float_op: impl Fn(f64) -> Result<Value, CalculatorError> + 'static) -> Self {
+        OneParamFloatOpImpl {
+            float_op: Box::new(float_op),
+        }
+    }
+}
+
+impl OpImpl for OneParamFloatOpImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let a = f64::from(calc.pop_mut()?);
+
+        calc.push_mut((self.float_op)(a)?);
+        Ok(())
+    }
+}
+
+/// Add a single value or an (x, y) pair to the statistics registers.
+struct StatsAddImpl {
+    pair: bool,
+}
+
+impl OpImpl for StatsAddImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let y = if self.pair {
+            Some(f64::from(calc.pop_mut()?))
+        } else {
+            None
+        };
+        let x = f64::from(calc.pop_mut()?);
+
+        calc.stats.add(x, y);
+        Ok(())
+    }
+}
+
+/// Set a time value of money register.
+struct TvmStoreImpl {
+    register: TvmRegister,
+}
+
+impl OpImpl for TvmStoreImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let v = f64::from(calc.pop_mut()?);
+
+        calc.tvm.set(self.register, v);
+        Ok(())
+    }
+}
+
+/// Compute a time value of money register from the others.
+struct TvmSolveImpl {
+    register: TvmRegister,
+}
+
+impl OpImpl for TvmSolveImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let v = calc.tvm.solve(self.register)?;
+
+        calc.push_mut(Value::Float(v));
+        Ok(())
+    }
+}
+
+/// Replace the cash flows on the stack, the first at the bottom, by
+/// their net present value or internal rate of return.
+struct CashFlowImpl {
+    /// Whether the interest rate is on top of the cash flows.
+    npv: bool,
+}
+
+impl OpImpl for CashFlowImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let rate = if self.npv {
+            Some(f64::from(calc.pop_mut()?))
+        } else {
+            None
+        };
+
+        if calc.value_stack.is_empty() {
+            return Err(CalculatorError::StackUnderflow);
+        }
+
+        let flows: Vec<f64> = calc.value_stack.drain(..).map(f64::from).collect();
+        let v = match rate {
+            Some(rate) => finance::npv(rate, &flows),
+            None => finance::irr(&flows).ok_or(CalculatorError::NoRoot)?,
+        };
+
+        calc.push_mut(Value::Float(v));
+        Ok(())
+    }
+}
+
+/// Computes a statistic or `None`, if there is not enough data.
+type StatsReader = dyn Fn(&Statistics) -> Option<Value>;
+
+/// Push a value computed from the statistics registers.
+struct StatsReadImpl {
+    read: Box<StatsReader>,
+}
+
+impl StatsReadImpl {
+    fn new(read: impl Fn(&Statistics) -> Option<Value> + 'static) -> Self {
+        StatsReadImpl {
+            read: Box::new(read),
+        }
+    }
+}
+
+impl OpImpl for StatsReadImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        // Not enough data points for the statistic.
+        let v = (self.read)(&calc.stats).ok_or(CalculatorError::InvalidOperation)?;
+
+        calc.push_mut(v);
+        Ok(())
+    }
+}
+
+/// Push slope and intercept of the linear regression over the
+/// statistics registers.
+#[derive(Default)]
+struct LinearRegressionImpl {}
+
+impl OpImpl for LinearRegressionImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let (slope, intercept) = calc
+            .stats
+            .linear_regression()
+            .ok_or(CalculatorError::InvalidOperation)?;
+
+        calc.push_mut(Value::Float(slope));
+        calc.push_mut(Value::Float(intercept));
+        Ok(())
+    }
+}
+
+/// Estimate y for x with the linear regression over the statistics
+/// registers.
+#[derive(Default)]
+struct PredictImpl {}
+
+impl OpImpl for PredictImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let (slope, intercept) = calc
+            .stats
+            .linear_regression()
+            .ok_or(CalculatorError::InvalidOperation)?;
+        let x = f64::from(calc.pop_mut()?);
+
+        calc.push_mut(Value::Float(slope * x + intercept));
+        Ok(())
+    }
+}
+
+/// Reset the statistics registers.
+#[derive(Default)]
+struct StatsClearImpl {}
+
+impl OpImpl for StatsClearImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.stats = Statistics::default();
+
+        Ok(())
+    }
+}
+
+/// Any two parameter operation that produces a single output.
+trait TwoParamOpImpl {
+    fn compute(&self, calc: &Calculator, a: Value, b: Value) -> Result<Value, CalculatorError>;
+}
+
+impl<T: TwoParamOpImpl> OpImpl for T {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let b = calc.pop_mut()?;
+        let a = calc.pop_mut()?;
+
+        let result = self.compute(calc, a, b)?;
+
+        calc.push_mut(result);
+        Ok(())
+    }
+}
+
+/// A two parameter operation that promotes both its arguments to
+/// float, if any one of it is. With a big integer operation, integers
+/// are promoted to big integers on overflow, if the overflow mode
+/// allows it.
+struct TwoParamFloatPromoOpImpl {
+    int_op: Box<dyn Fn(i64, i64) -> Result<Value, CalculatorError>>,
+    float_op: Box<dyn Fn(f64, f64) -> Result<Value, CalculatorError>>,
+    big_op: Option<Box<BigOp>>,
+}
+
+/// An operation on big integers.
+type BigOp = dyn Fn(BigInt, BigInt) -> Result<Value, CalculatorError>;
+
+impl TwoParamFloatPromoOpImpl {
+    fn new(
+        int_op: impl Fn(i64, i64) -> Result<Value, CalculatorError> + 'static,
+        float_op: impl Fn(f64, f64) -> Result<Value, CalculatorError> + 'static,
+    ) -> Self {
+        TwoParamFloatPromoOpImpl {
+            int_op: Box::new(int_op),
+            float_op: Box::new(float_op),
+            big_op: None,
+        }
+    }
+
+    fn with_big(
+        mut self,
+        big_op: impl Fn(BigInt, BigInt) -> Result<Value, CalculatorError> + 'static,
+    ) -> Self {
+        self.big_op = Some(Box::new(big_op));
+        self
+    }
+}
+
+impl TwoParamOpImpl for TwoParamFloatPromoOpImpl {
+    fn compute(&self, calc: &Calculator, a: Value, b: Value) -> Result<Value, CalculatorError> {
+        // Both values have the same kind from here on.
+        match a.coerce_pair(b) {
+            (Value::Float(a), Value::Float(b)) => (self.float_op)(a, b),
+            (a, b) => match &self.big_op {
+                Some(big_op) if calc.overflow == OverflowMode::Promote => {
+                    if a.is_big() {
+                        big_op(a.into(), b.into())
+                    } else {
+                        (self.int_op)(i64::from(a.clone()), i64::from(b.clone()))
+                            .or_else(|_| big_op(a.into(), b.into()))
+                    }
+                }
+                // Big integers left over from the promote mode
+                // overflow like any other result that doesn't fit.
+                _ => (self.int_op)(calc.to_integer(a)?, calc.to_integer(b)?),
+            },
+        }
+    }
+}
+
+/// A binary operator that is overloaded for some types of operands.
+/// Plain numbers without an overload are promoted as usual.
+struct OverloadedImpl {
+    op: Operation,
+    numeric: TwoParamFloatPromoOpImpl,
+}
+
+impl TwoParamOpImpl for OverloadedImpl {
+    fn compute(&self, calc: &Calculator, a: Value, b: Value) -> Result<Value, CalculatorError> {
+        let (type_a, type_b) = (a.type_of(), b.type_of());
+
+        match dispatch::lookup(&self.op, type_a, type_b) {
+            Some(overload) => overload(calc, a, b),
+            None if type_a.is_number() && type_b.is_number() => self.numeric.compute(calc, a, b),
+            None => Err(CalculatorError::NoOverload(format!(
+                "`{}` to {} and {}",
+                self.op, type_a, type_b
+            ))),
+        }
+    }
+}
+
+/// A two parameter operation that promotes both its arguments to
+/// integers all the time.
+struct TwoParamIntPromoOpImpl {
+    int_op: Box<dyn Fn(i64, i64) -> Result<Value, CalculatorError>>,
+}
+
+impl TwoParamIntPromoOpImpl {
+    fn new(int_op: impl Fn(i64, i64) -> Result<Value, CalculatorError> + 'static) -> Self {
+        TwoParamIntPromoOpImpl {
+            int_op: Box::new(int_op),
+        }
+    }
+}
+
+impl TwoParamOpImpl for TwoParamIntPromoOpImpl {
+    fn compute(&self, calc: &Calculator, a: Value, b: Value) -> Result<Value, CalculatorError> {
+        (self.int_op)(calc.to_integer(a)?, calc.to_integer(b)?)
+    }
+}
+
+/// Shift an integer left or right. Right shifts are arithmetic, so
+/// they keep the sign.
+struct ShiftImpl {
+    left: bool,
+}
+
+impl TwoParamOpImpl for ShiftImpl {
+    fn compute(&self, calc: &Calculator, a: Value, b: Value) -> Result<Value, CalculatorError> {
+        let a = calc.to_integer(a)?;
+        let amount = calc.to_integer(b)?;
+
+        let bits = if calc.overflow == OverflowMode::Mask {
+            (amount & 63) as u32
+        } else {
+            u32::try_from(amount)
+                .ok()
+                .filter(|bits| *bits < i64::BITS)
+                .ok_or(CalculatorError::ShiftOutOfRange { amount })?
+        };
+
+        Ok(Value::Integer(if self.left {
+            a << bits
+        } else {
+            a >> bits
+        }))
+    }
+}
+
+/// A two parameter operation that promotes both its arguments to
+/// float all the time.
+struct TwoParamFloatOpImpl {
+    float_op: Box<dyn Fn(f64, f64) -> Result<Value, CalculatorError>>,
+}
+
+impl TwoParamFloatOpImpl {
+    fn new(float_op: impl Fn(f64, f64) -> Result<Value, CalculatorError> + 'static) -> Self {
+        TwoParamFloatOpImpl {
+            float_op: Box::new(float_op),
+        }
+    }
+}
+
+impl TwoParamOpImpl for TwoParamFloatOpImpl {
+    fn compute(&self, _calc: &Calculator, a: Value, b: Value) -> Result<Value, CalculatorError> {
+        (self.float_op)(a.into(), b.into())
+    }
+}
+
+/// The angle of the vector (x, y) for parameters y and x. The result
+/// honors the angle mode.
+#[derive(Default)]
+struct Atan2Impl {}
+
+impl TwoParamOpImpl for Atan2Impl {
+    fn compute(&self, calc: &Calculator, y: Value, x: Value) -> Result<Value, CalculatorError> {
+        Ok(Value::Float(
+            calc.angle_from_radians(f64::from(y).atan2(f64::from(x))),
+        ))
+    }
+}
+
+/// Convert between polar and rectangular coordinates. Angles honor
+/// the angle mode.
+struct CoordinatesImpl {
+    to_polar: bool,
+}
+
+impl OpImpl for CoordinatesImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let b = f64::from(calc.pop_mut()?);
+        let a = f64::from(calc.pop_mut()?);
+
+        let (c, d) = if self.to_polar {
+            (a.hypot(b), calc.angle_from_radians(b.atan2(a)))
+        } else {
+            let (sin, cos) = calc.angle_to_radians(b).sin_cos();
+            (a * cos, a * sin)
+        };
+
+        calc.push_mut(Value::Float(c));
+        calc.push_mut(Value::Float(d));
+        Ok(())
+    }
+}
+
+/// Integer division with quotient and remainder. Like `/`, the
+/// quotient is rounded toward zero, so the remainder has the sign of
+/// the dividend.
+#[derive(Default)]
+struct DivModImpl {}
+
+impl OpImpl for DivModImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let b = calc.pop_mut()?;
+        let a = calc.pop_mut()?;
+
+        let (q, r) = if a.is_big() || b.is_big() {
+            let (a, b) = (BigInt::from(a), BigInt::from(b));
+
+            if b.is_zero() {
+                return Err(CalculatorError::InvalidOperation);
+            }
+            (Value::from(&a / &b), Value::from(a % b))
+        } else {
+            let (a, b) = (calc.to_integer(a)?, calc.to_integer(b)?);
+
+            match (a.checked_div(b), a.checked_rem(b)) {
+                (Some(q), Some(r)) => (Value::Integer(q), Value::Integer(r)),
+                _ => return Err(CalculatorError::InvalidOperation),
+            }
+        };
+
+        calc.push_mut(q);
+        calc.push_mut(r);
+        Ok(())
+    }
+}
+
+/// Round to cents according to the rounding mode. Money stays
+/// decimal, so the result is exact.
+#[derive(Default)]
+struct Round2Impl {}
+
+impl OpImpl for Round2Impl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        let v = calc.pop_mut()?;
+
+        let rounded = match v {
+            Value::Money { amount, currency } => Value::Money {
+                amount: money::round(amount, 2, calc.rounding),
+                currency,
+            },
+            Value::Float(f) => Value::Float(calc.rounding.round(f * 100.0) / 100.0),
+            v => v,
+        };
+
+        calc.push_mut(rounded);
+        Ok(())
+    }
+}
+
+/// Set the unit of angles.
+struct SetAngleModeImpl {
+    mode: AngleMode,
+}
+
+impl OpImpl for SetAngleModeImpl {
+    fn execute(&self, calc: &mut Calculator) -> Result<(), CalculatorError> {
+        calc.set_angle_mode(self.mode);
+
+        Ok(())
+    }
+}
+
+/// The number of ways to choose `r` out of `n` items without order.
+fn combinations(n: i64, r: i64) -> Result<i64, CalculatorError> {
+    if n < 0 || r < 0 {
+        return Err(CalculatorError::InvalidOperation);
+    }
+
+    if r > n {
+        return Ok(0);
+    }
+
+    // Each intermediate result is itself a binomial coefficient that
+    // is not larger than the final one, so the multiplication cannot
+    // overflow i128 as long as the result fits into i64.
+    let mut result: i128 = 1;
+    for i in 0..i128::from(r.min(n - r)) {
+        result = result * (i128::from(n) - i) / (i + 1);
+
+        if result > i128::from(i64::MAX) {
+            return Err(CalculatorError::InvalidOperation);
+        }
+    }
+
+    Ok(result as i64)
+}
+
+/// The number of ways to choose `r` out of `n` items with order.
+fn permutations(n: i64, r: i64) -> Result<i64, CalculatorError> {
+    if n < 0 || r < 0 {
+        return Err(CalculatorError::InvalidOperation);
+    }
+
+    if r > n {
+        return Ok(0);
+    }
+
+    (0..r).try_fold(1i64, |acc, i| {
+        acc.checked_mul(n - i)
+            .ok_or(CalculatorError::InvalidOperation)
+    })
+}
+
+/// Convert seconds to the H.MMSS format of HP calculators, where the
+/// digits after the decimal point are minutes and seconds.
+fn to_sexagesimal(seconds: f64) -> f64 {
+    // Don't let rounding errors of the input turn 15 seconds into
+    // 14.999999 seconds.
+    let total = (seconds.abs() * 1e6).round() / 1e6;
+    let h = (total / 3600.0).floor();
+    let m = ((total - h * 3600.0) / 60.0).floor();
+    let s = total - h * 3600.0 - m * 60.0;
+
+    // A single division keeps the result as close to the decimal
+    // digits as possible.
+    ((h * 1e4 + m * 100.0 + s) / 1e4).copysign(seconds)
+}
+
+/// Convert the H.MMSS format back to hours.
+fn from_sexagesimal(hms: f64) -> f64 {
+    // Work on the digits as integers as far as possible, see
+    // to_sexagesimal.
+    let scaled = (hms.abs() * 1e10).round() / 1e6;
+    let h = (scaled / 1e4).floor();
+    let m = ((scaled - h * 1e4) / 100.0).floor();
+    let s = scaled - h * 1e4 - m * 100.0;
+
+    ((h * 3600.0 + m * 60.0 + s) / 3600.0).copysign(hms)
+}
+
+/// Check the combined stack effect of `ops` against the stack depth
+/// before running them. Returns how many values are missing and the
+/// index of the first operation that runs out. Checking stops at the
+/// first operation without a fixed stack effect.
+fn missing_values<'a>(
+    depth: usize,
+    mut keep: bool,
+    ops: impl Iterator<Item = &'a Operation>,
+) -> Option<(usize, usize)> {
+    let mut depth = depth as i64;
+    let mut missing = 0;
+    let mut first = None;
+
+    for (index, op) in ops.enumerate() {
+        let Some(effect) = op.stack_effect() else {
+            // Operations like `range` still need their arguments.
+            if depth < op.min_pops() as i64 {
+                missing = missing.max(op.min_pops() - depth.max(0) as usize);
+                first.get_or_insert(index);
+            }
+            break;
+        };
+
+        if let Operation::SetKeep(on) = op {
+            keep = *on;
+        }
+
+        depth -= effect.pops as i64;
+        if depth < 0 {
+            missing = missing.max(-depth as usize);
+            first.get_or_insert(index);
+        }
+        depth += effect.pushes as i64;
+
+        // The argument stays.
+        if keep && op.is_unary() {
+            depth += 1;
+        }
+    }
+
+    first.map(|index| (missing, index))
+}
+
+impl From<Operation> for Box<dyn OpImpl> {
+    fn from(op: Operation) -> Self {
+        match op {
+            Operation::Add => Box::new(OverloadedImpl {
+                op: Operation::Add,
+                numeric: TwoParamFloatPromoOpImpl::new(
+                    |a, b| -> Result<Value, CalculatorError> {
+                        Ok(Value::Integer(
+                            a.checked_add(b).ok_or(CalculatorError::InvalidOperation)?,
+                        ))
+                    },
+                    |a, b| -> Result<Value, CalculatorError> { Ok(Value::Float(a + b)) },
+                )
+                .with_big(|a, b| -> Result<Value, CalculatorError> { Ok(Value::from(a + b)) }),
+            }),
+
+            Operation::BitAnd => Box::new(TwoParamIntPromoOpImpl::new(
+                |a, b| -> Result<Value, CalculatorError> { Ok(Value::Integer(a & b)) },
+            )),
+
+            Operation::BitNot => Box::new(BitNotImpl::default()),
+
+            Operation::BitOr => Box::new(TwoParamIntPromoOpImpl::new(
+                |a, b| -> Result<Value, CalculatorError> { Ok(Value::Integer(a | b)) },
+            )),
+
+            Operation::BitXor => Box::new(TwoParamIntPromoOpImpl::new(
+                |a, b| -> Result<Value, CalculatorError> { Ok(Value::Integer(a ^ b)) },
+            )),
+
+            Operation::Divide => Box::new(OverloadedImpl {
+                op: Operation::Divide,
+                numeric: TwoParamFloatPromoOpImpl::new(
+                    |a, b| -> Result<Value, CalculatorError> {
+                        Ok(Value::Integer(
+                            a.checked_div(b).ok_or(CalculatorError::InvalidOperation)?,
+                        ))
+                    },
+                    |a, b| -> Result<Value, CalculatorError> { Ok(Value::Float(a / b)) },
+                )
+                .with_big(|a, b| -> Result<Value, CalculatorError> {
+                    if b.is_zero() {
+                        return Err(CalculatorError::InvalidOperation);
+                    }
+
+                    Ok(Value::from(a / b))
+                }),
+            }),
+
+            Operation::DivMod => Box::new(DivModImpl::default()),
+
+            Operation::Round2 => Box::new(Round2Impl::default()),
+
+            Operation::LeftShift => Box::new(ShiftImpl { left: true }),
+
+            Operation::Multiply => Box::new(OverloadedImpl {
+                op: Operation::Multiply,
+                numeric: TwoParamFloatPromoOpImpl::new(
+                    |a, b| -> Result<Value, CalculatorError> {
+                        Ok(Value::Integer(
+                            a.checked_mul(b).ok_or(CalculatorError::InvalidOperation)?,
+                        ))
+                    },
+                    |a, b| -> Result<Value, CalculatorError> { Ok(Value::Float(a * b)) },
+                )
+                .with_big(|a, b| -> Result<Value, CalculatorError> { Ok(Value::from(a * b)) }),
+            }),
+
+            Operation::SetRadix(r) => Box::new(SetRadixImpl::from(r)),
+            Operation::SetInputBase(base) => Box::new(SetInputBaseImpl { base }),
+
+            Operation::Swap => Box::new(SwapImpl::default()),
+            Operation::Dup => Box::new(DupImpl::default()),
+            Operation::Drop => Box::new(DropImpl::default()),
+            Operation::Clear => Box::new(ClearImpl::default()),
+            Operation::Print => Box::new(PrintImpl::default()),
+
+            Operation::Subtract => Box::new(OverloadedImpl {
+                op: Operation::Subtract,
+                numeric: TwoParamFloatPromoOpImpl::new(
+                    |a, b| -> Result<Value, CalculatorError> {
+                        Ok(Value::Integer(
+                            a.checked_sub(b).ok_or(CalculatorError::InvalidOperation)?,
+                        ))
+                    },
+                    |a, b| -> Result<Value, CalculatorError> { Ok(Value::Float(a - b)) },
+                )
+                .with_big(|a, b| -> Result<Value, CalculatorError> { Ok(Value::from(a - b)) }),
+            }),
+
+            Operation::RightShift => Box::new(ShiftImpl { left: false }),
+
+            Operation::Push(v) => Box::new(PushImpl::from(v)),
+
+            Operation::ReadFile(path) => Box::new(ReadFileImpl { path }),
+
+            Operation::Decode(spec) => Box::new(DecodeImpl { spec }),
+
+            Operation::ShowPermissions => Box::new(ShowPermissionsImpl::default()),
+
+            Operation::ShowBytes => Box::new(ShowBytesImpl::default()),
+
+            Operation::Dump => Box::new(DumpImpl::default()),
+
+            Operation::Explain => Box::new(ExplainImpl::default()),
+
+            Operation::Catalog(category) => Box::new(CatalogImpl { category }),
+            Operation::FromUnix => Box::new(FromUnixImpl::default()),
+            Operation::ToUnix => Box::new(ToUnixImpl::default()),
+            Operation::SetZone(zone) => Box::new(SetZoneImpl { zone }),
+
+            Operation::PushEnv(name) => Box::new(PushEnvImpl { name }),
+
+            Operation::Paste => Box::new(PasteImpl::default()),
+
+            Operation::SetPrecision(digits) => Box::new(SetPrecisionImpl { digits }),
+
+            Operation::SetDigitGroup(size) => Box::new(SetDigitGroupImpl { size }),
+
+            Operation::SetPadding(bits) => Box::new(SetPaddingImpl { bits }),
+
+            Operation::ToInteger => Box::new(ToIntegerImpl::default()),
+
+            Operation::SetRounding(mode) => Box::new(SetRoundingImpl { mode }),
+
+            Operation::SetOverflow(mode) => Box::new(SetOverflowImpl { mode }),
+
+            Operation::SetDemotion(on) => Box::new(SetDemotionImpl { on }),
+
+            Operation::SetFractionDisplay(on) => Box::new(SetFractionDisplayImpl { on }),
+            Operation::SetSiDisplay(on) => Box::new(SetSiDisplayImpl { on }),
+            Operation::SetTypeDisplay(on) => Box::new(SetTypeDisplayImpl { on }),
+            Operation::SetKeep(on) => Box::new(SetKeepImpl { on }),
+            Operation::SetColumn(column) => Box::new(SetColumnImpl { column }),
+            Operation::SetTolerance(tolerance) => Box::new(SetToleranceImpl { tolerance }),
+            Operation::Equal => Box::new(CompareImpl { equal: true }),
+            Operation::NotEqual => Box::new(CompareImpl { equal: false }),
+            Operation::Clamp => Box::new(RangeCheckImpl { predicate: false }),
+            Operation::Between => Box::new(RangeCheckImpl { predicate: true }),
+
+            Operation::Hypot => Box::new(TwoParamFloatOpImpl::new(
+                |a, b| -> Result<Value, CalculatorError> { Ok(Value::Float(a.hypot(b))) },
+            )),
+
+            Operation::Atan2 => Box::new(Atan2Impl::default()),
+
+            Operation::LogBase => Box::new(TwoParamFloatOpImpl::new(
+                |x, base| -> Result<Value, CalculatorError> { Ok(Value::Float(x.log(base))) },
+            )),
+
+            Operation::SetAngleMode(mode) => Box::new(SetAngleModeImpl { mode }),
+
+            Operation::Combinations => Box::new(TwoParamIntPromoOpImpl::new(
+                |n, r| -> Result<Value, CalculatorError> {
+                    Ok(Value::Integer(combinations(n, r)?))
+                },
+            )),
+
+            Operation::Permutations => Box::new(TwoParamIntPromoOpImpl::new(
+                |n, r| -> Result<Value, CalculatorError> {
+                    Ok(Value::Integer(permutations(n, r)?))
+                },
+            )),
+
+            Operation::StatsAdd => Box::new(StatsAddImpl { pair: false }),
+
+            Operation::StatsAddPair => Box::new(StatsAddImpl { pair: true }),
+
+            Operation::StatsCount => Box::new(StatsReadImpl::new(|stats| {
+                Some(Value::Integer(stats.count() as i64))
+            })),
+
+            Operation::StatsMean => {
+                Box::new(StatsReadImpl::new(|stats| stats.mean().map(Value::Float)))
+            }
+
+            Operation::StatsStdDev => Box::new(StatsReadImpl::new(|stats| {
+                stats.std_dev().map(Value::Float)
+            })),
+
+            Operation::StatsClear => Box::new(StatsClearImpl::default()),
+
+            Operation::LinearRegression => Box::new(LinearRegressionImpl::default()),
+
+            Operation::Predict => Box::new(PredictImpl::default()),
+
+            Operation::TvmStore(register) => Box::new(TvmStoreImpl { register }),
+
+            Operation::TvmSolve(register) => Box::new(TvmSolveImpl { register }),
+
+            Operation::Npv => Box::new(CashFlowImpl { npv: true }),
+
+            Operation::Irr => Box::new(CashFlowImpl { npv: false }),
+
+            Operation::Plot => Box::new(PlotImpl { sparkline: false }),
+
+            Operation::Sparkline => Box::new(PlotImpl { sparkline: true }),
+
+            Operation::Store(name) => Box::new(StoreImpl { name }),
+
+            Operation::StoreWith(name, op) => Box::new(StoreWithImpl { name, op: *op }),
+            Operation::Recall(name) => Box::new(RecallImpl { name }),
+            Operation::RecallResult(index) => Box::new(RecallResultImpl { index }),
+            Operation::PushConstant(name) => Box::new(PushConstantImpl { name }),
+            Operation::AssertEq => Box::new(AssertImpl {
+                with_tolerance: false,
+            }),
+            Operation::AssertNear => Box::new(AssertImpl {
+                with_tolerance: true,
+            }),
+            Operation::Range => Box::new(RangeImpl { with_step: true }),
+            Operation::Iota => Box::new(RangeImpl { with_step: false }),
+            Operation::Define(name, ops) => Box::new(DefineImpl { name, ops }),
+            Operation::Block(ops) => Box::new(BlockImpl { ops }),
+            Operation::Run(name) => Box::new(RunImpl { name }),
+            Operation::PlotFunction(name) => Box::new(PlotFunctionImpl { name }),
+            Operation::Integrate(name) => Box::new(IntegrateImpl { name }),
+            Operation::Derive(name) => Box::new(DeriveImpl { name }),
+            Operation::Solve(name) => Box::new(SolveImpl {
+                name,
+                bracket: false,
+            }),
+            Operation::SolveIn(name) => Box::new(SolveImpl {
+                name,
+                bracket: true,
+            }),
+            Operation::Snapshot(name) => Box::new(SnapshotImpl { name }),
+            Operation::Restore(name) => Box::new(RestoreImpl { name }),
+            Operation::Diff(names) => {
+                let (a, b) = *names;
+                Box::new(DiffImpl { a, b })
+            }
+
+            Operation::ListVariables => Box::new(ListVariablesImpl::default()),
+
+            Operation::Exp => Box::new(OneParamFloatOpImpl::new(
+                |a| -> Result<Value, CalculatorError> { Ok(Value::Float(a.exp())) },
+            )),
+
+            Operation::Exp2 => Box::new(OneParamFloatOpImpl::new(
+                |a| -> Result<Value, CalculatorError> { Ok(Value::Float(a.exp2())) },
+            )),
+
+            // These two are precise for arguments near zero, where
+            // exp(a) - 1 and ln(1 + a) lose all significant digits.
+            Operation::ExpM1 => Box::new(OneParamFloatOpImpl::new(
+                |a| -> Result<Value, CalculatorError> { Ok(Value::Float(a.exp_m1())) },
+            )),
+
+            Operation::Ln1p => Box::new(OneParamFloatOpImpl::new(
+                |a| -> Result<Value, CalculatorError> { Ok(Value::Float(a.ln_1p())) },
+            )),
+
+            Operation::Ulp => Box::new(OneParamFloatOpImpl::new(
+                |a| -> Result<Value, CalculatorError> { Ok(Value::Float(ulp(a))) },
+            )),
+
+            Operation::NextUp => Box::new(OneParamFloatOpImpl::new(
+                |a| -> Result<Value, CalculatorError> { Ok(Value::Float(a.next_up())) },
+            )),
+
+            Operation::NextDown => Box::new(OneParamFloatOpImpl::new(
+                |a| -> Result<Value, CalculatorError> { Ok(Value::Float(a.next_down())) },
+            )),
+
+            Operation::ToRect => Box::new(CoordinatesImpl { to_polar: false }),
+
+            Operation::ToPolar => Box::new(CoordinatesImpl { to_polar: true }),
+
+            Operation::IntSqrt => Box::new(OneParamBigOpImpl { big_op: isqrt }),
+
+            Operation::IsPowerOfTwo => Box::new(OneParamBigOpImpl {
+                big_op: is_power_of_two,
+            }),
+
+            Operation::IntLog2 => Box::new(OneParamBigOpImpl { big_op: log2i }),
+
+            Operation::Netmask => Box::new(OneParamIntOpImpl::new(
+                |prefix| -> Result<Value, CalculatorError> {
+                    net::netmask(prefix)
+                        .map(Value::Integer)
+                        .ok_or(CalculatorError::InvalidOperation)
+                },
+            )),
+
+            Operation::Broadcast => Box::new(TwoParamIntPromoOpImpl::new(
+                |address, prefix| -> Result<Value, CalculatorError> {
+                    net::broadcast(address, prefix)
+                        .map(Value::Integer)
+                        .ok_or(CalculatorError::InvalidOperation)
+                },
+            )),
+
+            Operation::Hosts => Box::new(OneParamIntOpImpl::new(
+                |prefix| -> Result<Value, CalculatorError> {
+                    net::hosts(prefix)
+                        .map(Value::Integer)
+                        .ok_or(CalculatorError::InvalidOperation)
+                },
+            )),
+
+            Operation::InSubnet => Box::new(InSubnetImpl::default()),
+
+            Operation::ToDms => Box::new(OneParamFloatOpImpl::new(
+                |a| -> Result<Value, CalculatorError> {
+                    Ok(Value::Float(to_sexagesimal(a * 3600.0)))
+                },
+            )),
+
+            Operation::FromDms => Box::new(OneParamFloatOpImpl::new(
+                |a| -> Result<Value, CalculatorError> { Ok(Value::Float(from_sexagesimal(a))) },
+            )),
+
+            Operation::ToHms => Box::new(OneParamFloatOpImpl::new(
+                |a| -> Result<Value, CalculatorError> { Ok(Value::Float(to_sexagesimal(a))) },
+            )),
+        }
+    }
+}
+
+/// Split the stack change of an operation into the values it popped
+/// and the values it pushed. Values below both are untouched.
+fn stack_change<'a>(before: &'a [Value], after: &'a [Value]) -> (&'a [Value], &'a [Value]) {
+    let kept = before.iter().zip(after).take_while(|(a, b)| a == b).count();
+
+    (&before[kept..], &after[kept..])
+}
+
+/// Emit a tracing event for an applied operation.
+fn trace_operation(
+    name: &str,
+    before: &[Value],
+    after: &[Value],
+    duration: Duration,
+    result: &Result<(), CalculatorError>,
+) {
+    let (popped, pushed) = stack_change(before, after);
+    let list = |values: &[Value]| {
+        values
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    match result {
+        Ok(()) => tracing::debug!(
+            op = %name,
+            popped = %list(popped),
+            pushed = %list(pushed),
+            ?duration,
+            "applied"
+        ),
+        Err(e) => tracing::debug!(op = %name, error = %e, ?duration, "failed"),
+    }
+}
+
+impl Default for Calculator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Calculator {
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        Self {
+            value_stack: vec![],
+            stack_bytes: 0,
+            output_radix: Radix::Dec,
+            input_base: 10,
+            float_precision: 0,
+            fractions: false,
+            si_prefixes: false,
+            types: false,
+            keep: false,
+            column: 0,
+            tolerance: 0.0,
+            locale: Locale::default(),
+            digit_group: 0,
+            padding: 0,
+            rounding: RoundingMode::TowardZero,
+            angle_mode: AngleMode::Radians,
+            overflow: OverflowMode::Error,
+            zone: Zone::Local,
+            demote: false,
+            stats: Statistics::default(),
+            tvm: Tvm::default(),
+            output: vec![],
+            variables: BTreeMap::new(),
+            results: vec![],
+            snapshots: BTreeMap::new(),
+            constants: BTreeMap::new(),
+            definitions: BTreeMap::new(),
+            run_depth: 0,
+            interrupt: None,
+            timeout: None,
+            deadline: None,
+            limits: Limits::default(),
+            last_line: LastLine::default(),
+        }
+    }
+
+    pub fn pop_mut(&mut self) -> Result<Value, CalculatorError> {
+        let v = self
+            .value_stack
+            .pop()
+            .ok_or(CalculatorError::StackUnderflow)?;
+
+        self.stack_bytes = self.stack_bytes.saturating_sub(v.size());
+        Ok(v)
+    }
+
+    pub fn push_mut(&mut self, v: Value) {
+        self.stack_bytes += v.size();
+        self.value_stack.push(v)
+    }
+
+    #[allow(dead_code)]
+    pub fn push(&self, v: Value) -> Calculator {
+        let mut new_calc = self.clone();
+
+        new_calc.push_mut(v);
+        new_calc
+    }
+
+    /// Apply a single operation on the calculator.
+    pub fn apply_mut(&mut self, op: Operation) -> Result<(), CalculatorError> {
+        // Definitions count against the timeout of the operation
+        // that runs them.
+        if self.run_depth > 0 {
+            return self.apply_traced(op);
+        }
+
+        self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let result = self.apply_traced(op);
+        self.deadline = None;
+
+        result
+    }
+
+    fn apply_traced(&mut self, op: Operation) -> Result<(), CalculatorError> {
+        // Copying the stack for every operation is only worth it when
+        // someone listens.
+        if tracing::enabled!(tracing::Level::DEBUG) {
+            let name = op.to_string();
+            let before = self.value_stack.clone();
+            let start = Instant::now();
+
+            let result = self.apply_untraced(op);
+            trace_operation(&name, &before, &self.value_stack, start.elapsed(), &result);
+
+            return result;
+        }
+
+        self.apply_untraced(op)
+    }
+
+    fn apply_untraced(&mut self, op: Operation) -> Result<(), CalculatorError> {
+        // Like an implicit dup before the function.
+        let argument = self
+            .value_stack
+            .last()
+            .cloned()
+            .filter(|_| self.keep && op.is_unary());
+        let kept = argument.is_some() as usize;
+        let effect = op.stack_effect();
+
+        Box::<dyn OpImpl>::from(op).execute(self)?;
+
+        if let Some(argument) = argument {
+            self.stack_bytes += argument.size();
+            self.value_stack
+                .insert(self.value_stack.len() - 1, argument);
+        }
+
+        // Operations with a fixed stack effect only change the top of
+        // the stack. Others, like `range` or `restore`, may change it
+        // without `push_mut`, so everything is counted again.
+        let pushed = match effect {
+            Some(effect) => effect.pushes + kept,
+            None => {
+                self.stack_bytes = self.top_bytes(self.value_stack.len());
+                self.value_stack.len()
+            }
+        };
+        self.check_limits(pushed)?;
+
+        if self.demote {
+            self.demote_stack();
+        }
+
+        Ok(())
+    }
+
+    /// Turn all integral floats on the stack into integers.
+    fn demote_stack(&mut self) {
+        for v in self.value_stack.iter_mut().filter(|v| v.is_float()) {
+            *v = v.clone().demoted();
+        }
+    }
+
+    /// A side-effect free version of [apply_mut] that returns a new
+    /// calculator with the result.
+    #[allow(dead_code)]
+    pub fn apply(&self, op: Operation) -> Result<Self, CalculatorError> {
+        let mut new_calc = self.clone();
+
+        new_calc.apply_mut(op)?;
+        Ok(new_calc)
+    }
+
+    /// Apply the operations stored under `name`.
+    fn run(&mut self, name: &str) -> Result<(), CalculatorError> {
+        let ops = self
+            .definitions
+            .get(name)
+            .ok_or_else(|| CalculatorError::UnknownDefinition(name.to_string()))?
+            .clone();
+
+        if self.run_depth >= MAX_RUN_DEPTH {
+            return Err(CalculatorError::RecursionLimit);
+        }
+
+        self.run_depth += 1;
+        let result = ops.into_iter().try_for_each(|op| {
+            self.check_cancelled()?;
+            self.apply_mut(op)
+        });
+        self.run_depth -= 1;
+
+        result
+    }
+
+    /// The definition `name` as a function from the top of the stack
+    /// to the top of the stack. It runs on a copy of the calculator.
+    fn function(
+        &self,
+        name: &str,
+    ) -> Result<impl FnMut(f64) -> Result<f64, CalculatorError>, CalculatorError> {
+        if !self.definitions.contains_key(name) {
+            return Err(CalculatorError::UnknownDefinition(name.to_string()));
+        }
+
+        let mut scratch = self.clone();
+        let name = name.to_string();
+
+        Ok(move |x| {
+            scratch.set_stack(vec![Value::Float(x)]);
+            scratch.run(&name)?;
+            scratch
+                .top()
+                .map(f64::from)
+                .ok_or(CalculatorError::StackUnderflow)
+        })
+    }
+
+    /// Cancel operations when `interrupt` is set, e.g. from a signal
+    /// handler. The flag is reset once an operation is cancelled.
+    pub fn set_interrupt(&mut self, interrupt: &'static AtomicBool) {
+        self.interrupt = Some(interrupt);
+    }
+
+    /// Cancel operations that run longer than `timeout`.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Replace the stack, e.g. of a scratch calculator.
+    fn set_stack(&mut self, values: Vec<Value>) {
+        self.value_stack = values;
+        self.stack_bytes = self.top_bytes(self.value_stack.len());
+    }
+
+    /// The bytes the top `n` values on the stack take up.
+    fn top_bytes(&self, n: usize) -> usize {
+        let start = self.value_stack.len().saturating_sub(n);
+
+        self.value_stack[start..].iter().map(Value::size).sum()
+    }
+
+    /// Fail if the stack or one of the top `pushed` values grew too
+    /// large. Checking after each operation stops runaway growth like
+    /// repeated squaring early.
+    fn check_limits(&self, pushed: usize) -> Result<(), CalculatorError> {
+        let limits = &self.limits;
+        let exceeded = |e: String| Err(CalculatorError::ResourceLimit(e));
+
+        if self.value_stack.len() > limits.max_values {
+            return exceeded(format!(
+                "{} values on the stack (max {})",
+                self.value_stack.len(),
+                limits.max_values
+            ));
+        }
+
+        let start = self.value_stack.len().saturating_sub(pushed);
+        for v in &self.value_stack[start..] {
+            if let Value::Big(b) = v {
+                if b.bits() > limits.max_bits {
+                    return exceeded(format!(
+                        "integer with {} bits (max {})",
+                        b.bits(),
+                        limits.max_bits
+                    ));
+                }
+            }
+        }
+
+        if self.stack_bytes > limits.max_bytes {
+            return exceeded(format!(
+                "stack of {} (max {})",
+                byte_size(self.stack_bytes as f64, 1024.0),
+                byte_size(limits.max_bytes as f64, 1024.0)
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Fail if the running operation was interrupted or ran out of
+    /// time. Long operations call this regularly.
+    fn check_cancelled(&self) -> Result<(), CalculatorError> {
+        if self
+            .interrupt
+            .is_some_and(|interrupt| interrupt.swap(false, Ordering::Relaxed))
+        {
+            return Err(CalculatorError::Interrupted);
+        }
+
+        match (self.timeout, self.deadline) {
+            (Some(timeout), Some(deadline)) if Instant::now() >= deadline => {
+                Err(CalculatorError::TimedOut(timeout))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Parse a line and apply all its operations. On error, the
+    /// calculator is left unchanged.
+    pub fn eval(&mut self, line: &str) -> Result<&[Value], Error> {
+        self.eval_with(line, |_, _| {})
+    }
+
+    /// Like [eval], but calls `after_each` with every operation and
+    /// the calculator after applying it.
+    pub fn eval_with<F>(&mut self, line: &str, after_each: F) -> Result<&[Value], Error>
+    where
+        F: FnMut(&Operation, &Calculator),
+    {
+        let line = self.select_input(line);
+        let ops = self.parse_line(&line)?;
+
+        self.eval_parsed(&line, ops, after_each)
+    }
+
+    /// Evaluate a script line by line and keep going after errors.
+    /// Returns the failed lines, numbered from 1, and their errors.
+    pub fn eval_lines(&mut self, script: &str) -> Vec<(usize, Error)> {
+        script
+            .lines()
+            .enumerate()
+            .filter_map(|(number, line)| self.eval(line).err().map(|e| (number + 1, e)))
+            .collect()
+    }
+
+    /// Reduce tab-separated input to the column chosen with `col`.
+    pub fn select_input<'a>(&self, input: &'a str) -> Cow<'a, str> {
+        if self.column == 0 || !input.contains('\t') {
+            Cow::Borrowed(input)
+        } else {
+            Cow::Owned(select_column(input, self.column))
+        }
+    }
+
+    /// Parse a line with the constants of this calculator.
+    pub fn parse_line(&self, line: &str) -> Result<Vec<(Operation, Span)>, Error> {
+        parse_spanned_with(line, self.input_base, self.overflow, |name| {
+            self.constants.contains_key(name)
+        })
+        .map_err(|(error, span)| Error::Parse {
+            error,
+            line: line.to_string(),
+            span,
+        })
+    }
+
+    /// Like [eval_with], but for a line that [parse_line] already
+    /// parsed into `ops`.
+    pub fn eval_parsed<F>(
+        &mut self,
+        line: &str,
+        ops: Vec<(Operation, Span)>,
+        mut after_each: F,
+    ) -> Result<&[Value], Error>
+    where
+        F: FnMut(&Operation, &Calculator),
+    {
+        if let Some((missing, index)) = missing_values(
+            self.value_stack.len(),
+            self.keep,
+            ops.iter().map(|(op, _)| op),
+        ) {
+            let (op, span) = &ops[index];
+
+            return Err(Error::Calculator {
+                error: CalculatorError::MissingValues(missing),
+                operation: op.clone(),
+                line: line.to_string(),
+                span: span.clone(),
+            });
+        }
+
+        let mut new_calc = self.clone();
+        let remember = !ops.is_empty();
+        let line_ops: Vec<Operation> = ops.iter().map(|(op, _)| op.clone()).collect();
+
+        for (op, span) in ops {
+            new_calc
+                .check_cancelled()
+                .and_then(|_| new_calc.apply_mut(op.clone()))
+                .map_err(|error| Error::Calculator {
+                    error,
+                    operation: op.clone(),
+                    line: line.to_string(),
+                    span,
+                })?;
+            after_each(&op, &new_calc);
+        }
+
+        // Explaining the explanation is not helpful.
+        if remember && !line_ops.contains(&Operation::Explain) {
+            new_calc.last_line = LastLine {
+                stack: self.value_stack.clone(),
+                ops: line_ops,
+                top: new_calc.top(),
+            };
+        }
+
+        if let Some(top) = new_calc.top().filter(|_| remember) {
+            new_calc.results.push((line.to_string(), top));
+
+            if new_calc.results.len() > MAX_RESULTS {
+                new_calc.results.remove(0);
+            }
+        }
+
+        *self = new_calc;
+        Ok(self.stack())
+    }
+
+    pub fn set_radix(&mut self, radix: Radix) {
+        self.output_radix = radix;
+    }
+
+    /// Display floats with the given number of significant digits. 0
+    /// selects the shortest representation that reads back exactly.
+    pub fn set_precision(&mut self, digits: usize) {
+        self.float_precision = digits;
+    }
+
+    /// Separate hex and binary digits in groups of `size` or not at
+    /// all for 0.
+    pub fn set_digit_group(&mut self, size: usize) {
+        self.digit_group = size;
+    }
+
+    /// Pad hex and binary output with zeros to `bits` wide words.
+    pub fn set_padding(&mut self, bits: usize) {
+        self.padding = bits;
+    }
+
+    /// Display floats as fractions with a bounded denominator.
+    pub fn set_fraction_display(&mut self, on: bool) {
+        self.fractions = on;
+    }
+
+    pub fn set_rounding(&mut self, mode: RoundingMode) {
+        self.rounding = mode;
+    }
+
+    pub fn set_overflow(&mut self, mode: OverflowMode) {
+        self.overflow = mode;
+    }
+
+    pub fn set_demotion(&mut self, on: bool) {
+        self.demote = on;
+    }
+
+    pub fn set_angle_mode(&mut self, mode: AngleMode) {
+        self.angle_mode = mode;
+    }
+
+    /// Convert an angle in radians to the current angle mode.
+    pub fn angle_from_radians(&self, angle: f64) -> f64 {
+        match self.angle_mode {
+            AngleMode::Radians => angle,
+            AngleMode::Degrees => angle.to_degrees(),
+        }
+    }
+
+    /// Convert an angle in the current angle mode to radians.
+    pub fn angle_to_radians(&self, angle: f64) -> f64 {
+        match self.angle_mode {
+            AngleMode::Radians => angle,
+            AngleMode::Degrees => angle.to_radians(),
+        }
+    }
+
+    /// Convert a value to integer honoring the rounding mode. Big
+    /// integers don't fit.
+    pub fn to_integer(&self, v: Value) -> Result<i64, CalculatorError> {
+        match v {
+            Value::Integer(i) => Ok(i),
+            Value::Float(f) => Ok(i64::from(Value::Float(self.rounding.round(f)))),
+            Value::Big(_) => Err(CalculatorError::InvalidOperation),
+            Value::Money { .. } => self.to_integer(Value::Float(v.into())),
+        }
+    }
+
+    pub fn radix(&self) -> Radix {
+        self.output_radix
+    }
+
+    pub fn angle_mode(&self) -> AngleMode {
+        self.angle_mode
+    }
+
+    pub fn overflow(&self) -> OverflowMode {
+        self.overflow
+    }
+
+    /// Write decimal numbers in the output like `locale` does.
+    pub fn set_locale(&mut self, locale: Locale) {
+        self.locale = locale;
+    }
+
+    /// The operations that restore the current display and
+    /// conversion settings.
+    pub fn modes(&self) -> Vec<Operation> {
+        vec![
+            Operation::SetRadix(self.output_radix),
+            Operation::SetPrecision(self.float_precision),
+            Operation::SetDigitGroup(self.digit_group),
+            Operation::SetPadding(self.padding),
+            Operation::SetFractionDisplay(self.fractions),
+            Operation::SetSiDisplay(self.si_prefixes),
+            Operation::SetTypeDisplay(self.types),
+            Operation::SetRounding(self.rounding),
+            Operation::SetOverflow(self.overflow),
+            Operation::SetDemotion(self.demote),
+            Operation::SetKeep(self.keep),
+            Operation::SetColumn(self.column),
+            Operation::SetTolerance(self.tolerance),
+            Operation::SetAngleMode(self.angle_mode),
+            Operation::SetZone(self.zone),
+            Operation::SetInputBase(self.input_base),
+        ]
+    }
+
+    /// Show a line of text to the user.
+    pub fn print(&mut self, line: String) {
+        self.output.push(line);
+    }
+
+    /// Remove and return the text that operations want to show to the
+    /// user.
+    pub fn take_output(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// The names of all variables in alphabetical order.
+    pub fn variable_names(&self) -> Vec<String> {
+        self.variables.keys().cloned().collect()
+    }
+
+    /// All variables with their values in alphabetical order.
+    pub fn variables(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.variables.iter().map(|(name, v)| (name.as_str(), v))
+    }
+
+    /// Define a constant that is pushed when its name appears in a
+    /// line given to [eval](Calculator::eval).
+    pub fn define_constant(&mut self, name: &str, v: Value) {
+        self.constants.insert(name.to_string(), v);
+    }
+
+    /// One `name = value` line per constant.
+    pub fn format_constants(&self) -> Vec<String> {
+        self.constants
+            .iter()
+            .map(|(name, v)| format!("{} = {}", name, self.format_value(v)))
+            .collect()
+    }
+
+    pub fn snapshot_names(&self) -> Vec<String> {
+        self.snapshots.keys().cloned().collect()
+    }
+
+    /// One `name: stack` line per snapshot.
+    pub fn format_snapshots(&self) -> Vec<String> {
+        self.snapshots
+            .iter()
+            .map(|(name, stack)| {
+                let values: Vec<String> = stack.iter().map(|v| self.format_value(v)).collect();
+
+                format!("{}: {}", name, values.join(" "))
+            })
+            .collect()
+    }
+
+    /// One `name = value` line per variable.
+    pub fn format_variables(&self) -> Vec<String> {
+        self.variables
+            .iter()
+            .map(|(name, v)| format!("{} = {}", name, self.format_value(v)))
+            .collect()
+    }
+
+    /// Earlier lines that contain `text` and their results, latest
+    /// first. Repeated lines with the same result are only listed
+    /// once.
+    pub fn search_results(&self, text: &str) -> Vec<String> {
+        let mut seen = vec![];
+
+        self.results
+            .iter()
+            .rev()
+            .enumerate()
+            .filter(|(_, (line, _))| line.contains(text))
+            .filter(|(_, result)| {
+                let new = !seen.contains(result);
+
+                seen.push(*result);
+                new
+            })
+            .map(|(i, (line, v))| format!("${}: {} = {}", i + 1, line, self.format_value(v)))
+            .collect()
+    }
+
+    #[allow(dead_code)]
+    pub fn stack(&self) -> &[Value] {
+        &self.value_stack
+    }
+
+    /// The value on top of the stack, if there is any.
+    pub fn top(&self) -> Option<Value> {
+        self.value_stack.last().cloned()
+    }
+
+    /// Format the whole stack with values separated by `separator`.
+    pub fn format_stack(&self, separator: &str) -> String {
+        self.format_top(self.value_stack.len(), separator)
+    }
+
+    /// Format the `n` values on top of the stack. Omitted values are
+    /// indicated by `...`.
+    pub fn format_top(&self, n: usize, separator: &str) -> String {
+        let skip = self.value_stack.len().saturating_sub(n);
+        let mut tokens: Vec<String> = self.value_stack[skip..]
+            .iter()
+            .map(|v| self.format_value(v))
+            .collect();
+
+        if skip > 0 {
+            tokens.insert(0, "...".to_string());
+        }
+
+        tokens.join(separator)
+    }
+
+    /// Like [Calculator::format_value], but followed by the type of
+    /// the value, e.g. `42:i64`, if types are shown.
+    pub fn format_annotated(&self, v: &Value) -> String {
+        let formatted = self.format_value(v);
+
+        if !self.types {
+            return formatted;
+        }
+
+        // Hex and binary integers are usually bit patterns, whose
+        // width matters more than their sign.
+        let annotation = match v {
+            Value::Integer(i)
+                if *i >= 0 && matches!(self.output_radix, Radix::Hex | Radix::Bin) =>
+            {
+                match i {
+                    0..=0xff => "u8",
+                    0x100..=0xffff => "u16",
+                    0x1_0000..=0xffff_ffff => "u32",
+                    _ => "u64",
+                }
+            }
+            Value::Integer(_) => "i64",
+            Value::Big(_) => "big",
+            Value::Float(_) => "f64",
+            Value::Money { .. } => "money",
+        };
+
+        format!("{}:{}", formatted, annotation)
+    }
+
+    /// Format a single value according to the current output
+    /// settings.
+    pub fn format_value(&self, v: &Value) -> String {
+        match v {
+            Value::Integer(i) => match self.output_radix {
+                Radix::Dec => localize(&format!("{}", i), self.locale),
+                Radix::Ip => net::format_ipv4(*i).unwrap_or_else(|| format!("{}", i)),
+                Radix::Hex => self.format_digits("", "0x", &format!("{:x}", i), 4),
+                Radix::Bin => self.format_digits("", "0b", &format!("{:b}", i), 1),
+            },
+            Value::Big(b) => {
+                let sign = if b.sign() == Sign::Minus { "-" } else { "" };
+
+                match self.output_radix {
+                    Radix::Dec | Radix::Ip => localize(&format!("{}", b), self.locale),
+                    Radix::Hex => {
+                        self.format_digits(sign, "0x", &format!("{:x}", b.magnitude()), 4)
+                    }
+                    Radix::Bin => {
+                        self.format_digits(sign, "0b", &format!("{:b}", b.magnitude()), 1)
+                    }
+                }
+            }
+            Value::Float(fl) => match fraction(*fl, MAX_DENOMINATOR).filter(|_| self.fractions) {
+                Some((n, 1)) => format!("{}", n),
+                Some((n, d)) => format!("{}/{}", n, d),
+                None if self.si_prefixes => {
+                    localize(&si_prefix(*fl, self.float_precision), self.locale)
+                }
+                None => localize(&float_significant(*fl, self.float_precision), self.locale),
+            },
+            Value::Money { amount, currency } => {
+                localize(&money::format(amount, currency), self.locale)
+            }
+        }
+    }
+}
+
+impl Calculator {
+    /// Format the digits of a hex or binary integer, where each digit
+    /// stands for `digit_bits` bits.
+    fn format_digits(&self, sign: &str, prefix: &str, digits: &str, digit_bits: usize) -> String {
+        let width = self.padding.div_ceil(digit_bits);
+        let padded = format!("{:0>w$}", digits, w = width);
+
+        format!(
+            "{}{}{}",
+            sign,
+            prefix,
+            group_digits(&padded, self.digit_group)
+        )
+    }
+}
+
+impl std::fmt::Display for Calculator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", self.format_stack(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simple() -> Result<(), CalculatorError> {
+        assert!(Calculator::new().stack().is_empty());
+
+        assert_eq!(
+            Calculator::new()
+                .apply(Operation::Push(Value::Integer(1)))?
+                .stack(),
+            &[Value::Integer(1)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_integer_2op() -> Result<(), CalculatorError> {
+        // An operation where parameter order does not matter.
+        assert_eq!(
+            Calculator::new()
+                .push(Value::Integer(1))
+                .push(Value::Integer(2))
+                .apply(Operation::Add)?
+                .stack(),
+            &[Value::Integer(3)]
+        );
+
+        // An operation where parameter order does matter.
+        assert_eq!(
+            Calculator::new()
+                .push(Value::Integer(6))
+                .push(Value::Integer(2))
+                .apply(Operation::Divide)?
+                .stack(),
+            &[Value::Integer(3)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_file() -> Result<(), CalculatorError> {
+        let path = std::env::temp_dir().join(format!("clac-test-{}.txt", std::process::id()));
+        std::fs::write(&path, "1 2\n3.5\n").unwrap();
+
+        let calc = Calculator::new().apply(Operation::ReadFile(path.to_string_lossy().to_string()));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            calc?.stack(),
+            &[Value::Integer(1), Value::Integer(2), Value::Float(3.5)]
+        );
+
+        assert!(matches!(
+            Calculator::new().apply(Operation::ReadFile("/nonexistent/clac".to_string())),
+            Err(CalculatorError::FileError(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_env() -> Result<(), CalculatorError> {
+        std::env::set_var("CLAC_TEST_ENV_NUMBER", " 0x10 ");
+        std::env::set_var("CLAC_TEST_ENV_GARBAGE", "1 2");
+
+        assert_eq!(
+            Calculator::new()
+                .apply(Operation::PushEnv("CLAC_TEST_ENV_NUMBER".to_string()))?
+                .stack(),
+            &[Value::Integer(16)]
+        );
+
+        for name in ["CLAC_TEST_ENV_GARBAGE", "CLAC_TEST_ENV_UNSET"] {
+            assert!(matches!(
+                Calculator::new().apply(Operation::PushEnv(name.to_string())),
+                Err(CalculatorError::EnvError(_))
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_precision() -> Result<(), CalculatorError> {
+        let calc = Calculator::new()
+            .push(Value::Float(2.0))
+            .push(Value::Float(3.0))
+            .apply(Operation::Divide)?;
+
+        assert_eq!(calc.to_string(), "0.6666666666666666");
+        assert_eq!(
+            calc.apply(Operation::SetPrecision(4))?.to_string(),
+            "0.6667"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rounding() -> Result<(), CalculatorError> {
+        let round = |mode, f| -> Result<Vec<Value>, CalculatorError> {
+            Ok(Calculator::new()
+                .apply(Operation::SetRounding(mode))?
+                .push(Value::Float(f))
+                .apply(Operation::ToInteger)?
+                .stack()
+                .to_vec())
+        };
+
+        assert_eq!(
+            round(RoundingMode::TowardZero, -2.5)?,
+            &[Value::Integer(-2)]
+        );
+        assert_eq!(round(RoundingMode::HalfEven, 2.5)?, &[Value::Integer(2)]);
+        assert_eq!(round(RoundingMode::HalfEven, 3.5)?, &[Value::Integer(4)]);
+        assert_eq!(round(RoundingMode::HalfUp, 2.5)?, &[Value::Integer(3)]);
+        assert_eq!(round(RoundingMode::Floor, -2.5)?, &[Value::Integer(-3)]);
+        assert_eq!(round(RoundingMode::Ceiling, 2.1)?, &[Value::Integer(3)]);
+
+        // Integer operations convert their parameters the same way.
+        assert_eq!(
+            Calculator::new()
+                .apply(Operation::SetRounding(RoundingMode::Ceiling))?
+                .push(Value::Float(2.5))
+                .push(Value::Integer(1))
+                .apply(Operation::BitAnd)?
+                .stack(),
+            &[Value::Integer(1)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_float_2op() -> Result<(), CalculatorError> {
+        assert_eq!(
+            Calculator::new()
+                .push(Value::Integer(3))
+                .push(Value::Integer(4))
+                .apply(Operation::Hypot)?
+                .stack(),
+            &[Value::Float(5.0)]
+        );
+
+        assert_eq!(
+            Calculator::new()
+                .push(Value::Integer(8))
+                .push(Value::Integer(2))
+                .apply(Operation::LogBase)?
+                .stack(),
+            &[Value::Float(3.0)]
+        );
+
+        let calc = Calculator::new()
+            .push(Value::Integer(1))
+            .push(Value::Integer(-1));
+
+        assert_eq!(
+            calc.apply(Operation::Atan2)?.stack(),
+            &[Value::Float(3.0 * std::f64::consts::FRAC_PI_4)]
+        );
+        assert_eq!(
+            calc.apply(Operation::SetAngleMode(AngleMode::Degrees))?
+                .apply(Operation::Atan2)?
+                .stack(),
+            &[Value::Float(135.0)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exp() -> Result<(), CalculatorError> {
+        let unary = |op, a| -> Result<Value, CalculatorError> {
+            Ok(Calculator::new().push(a).apply(op)?.stack()[0].clone())
+        };
+
+        assert_eq!(unary(Operation::Exp, Value::Integer(0))?, Value::Float(1.0));
+        assert_eq!(
+            unary(Operation::Exp2, Value::Integer(10))?,
+            Value::Float(1024.0)
+        );
+
+        // The naive computation would yield 0.
+        assert_eq!(
+            unary(Operation::ExpM1, Value::Float(1e-20))?,
+            Value::Float(1e-20)
+        );
+        assert_eq!(
+            unary(Operation::Ln1p, Value::Float(1e-20))?,
+            Value::Float(1e-20)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exact_integers() {
+        let mut calc = Calculator::new();
+        let mut eval = |line| calc.eval(line).unwrap().to_vec();
+
+        assert_eq!(
+            eval("99 isqrt 100 isqrt 0 isqrt 10.9 isqrt"),
+            vec![
+                Value::Integer(9),
+                Value::Integer(10),
+                Value::Integer(0),
+                Value::Integer(3)
+            ]
+        );
+        // The float square root of 2^53 + 1 squared is off by one.
+        assert_eq!(
+            eval("clear overflow promote 9007199254740993 dup * isqrt"),
+            vec![Value::Integer(9007199254740993)]
+        );
+        assert_eq!(
+            eval("clear 64 ispow2 65 ispow2 0 ispow2 -4 ispow2"),
+            vec![
+                Value::Integer(1),
+                Value::Integer(0),
+                Value::Integer(0),
+                Value::Integer(0)
+            ]
+        );
+        assert_eq!(
+            eval("clear 1 log2i 1023 log2i 4294967296 dup * dup * dup log2i swap ispow2"),
+            vec![
+                Value::Integer(0),
+                Value::Integer(9),
+                Value::Integer(128),
+                Value::Integer(1)
+            ]
+        );
+
+        assert!(Calculator::new().eval("-1 isqrt").is_err());
+        assert!(Calculator::new().eval("0 log2i").is_err());
+    }
+
+    #[test]
+    fn test_ulp() -> Result<(), CalculatorError> {
+        let unary = |op, a| -> Result<Value, CalculatorError> {
+            Ok(Calculator::new().push(a).apply(op)?.stack()[0].clone())
+        };
+
+        assert_eq!(
+            unary(Operation::Ulp, Value::Integer(1))?,
+            Value::Float(f64::EPSILON)
+        );
+        assert_eq!(
+            unary(Operation::Ulp, Value::Float(-1.0))?,
+            Value::Float(f64::EPSILON)
+        );
+        assert_eq!(
+            unary(Operation::Ulp, Value::Float(f64::MAX))?,
+            Value::Float(2f64.powi(971))
+        );
+        assert_eq!(
+            unary(Operation::NextUp, Value::Float(1.0))?,
+            Value::Float(1.0 + f64::EPSILON)
+        );
+        assert_eq!(
+            unary(Operation::NextDown, Value::Float(0.0))?,
+            Value::Float(-f64::from_bits(1))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_combinatorics() {
+        assert_eq!(combinations(5, 2).ok(), Some(10));
+        assert_eq!(combinations(5, 0).ok(), Some(1));
+        assert_eq!(combinations(2, 5).ok(), Some(0));
+        assert_eq!(combinations(66, 33).ok(), Some(7219428434016265740));
+        assert!(combinations(68, 34).is_err());
+        assert!(combinations(-1, 0).is_err());
+
+        assert_eq!(permutations(5, 2).ok(), Some(20));
+        assert_eq!(permutations(5, 0).ok(), Some(1));
+        assert_eq!(permutations(20, 20).ok(), Some(2432902008176640000));
+        assert!(permutations(21, 21).is_err());
+        assert_eq!(permutations(i64::MAX, 0).ok(), Some(1));
+    }
+
+    #[test]
+    fn test_stats() -> Result<(), CalculatorError> {
+        let calc = Calculator::new()
+            .push(Value::Integer(1))
+            .apply(Operation::StatsAdd)?
+            .push(Value::Integer(2))
+            .push(Value::Integer(10))
+            .apply(Operation::StatsAddPair)?
+            .push(Value::Float(3.0))
+            .apply(Operation::StatsAdd)?;
+
+        // The statistics registers don't use the stack.
+        assert!(calc.stack().is_empty());
+
+        assert_eq!(
+            calc.apply(Operation::StatsCount)?
+                .apply(Operation::StatsMean)?
+                .apply(Operation::StatsStdDev)?
+                .stack(),
+            &[Value::Integer(3), Value::Float(2.0), Value::Float(1.0)]
+        );
+
+        // Linear regression needs pairs only.
+        assert!(calc.apply(Operation::LinearRegression).is_err());
+
+        let calc = calc.apply(Operation::StatsClear)?;
+
+        assert!(calc.apply(Operation::StatsMean).is_err());
+        assert_eq!(
+            calc.apply(Operation::StatsCount)?.stack(),
+            &[Value::Integer(0)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_linear_regression() -> Result<(), CalculatorError> {
+        let mut calc = Calculator::new();
+
+        for (x, y) in [(0, 1), (1, 3), (2, 5)] {
+            calc = calc
+                .push(Value::Integer(x))
+                .push(Value::Integer(y))
+                .apply(Operation::StatsAddPair)?;
+        }
+
+        assert_eq!(
+            calc.apply(Operation::LinearRegression)?.stack(),
+            &[Value::Float(2.0), Value::Float(1.0)]
+        );
+        assert_eq!(
+            calc.push(Value::Integer(10))
+                .apply(Operation::Predict)?
+                .stack(),
+            &[Value::Float(21.0)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_plot() -> Result<(), CalculatorError> {
+        let mut calc = Calculator::new()
+            .push(Value::Integer(1))
+            .push(Value::Integer(2))
+            .apply(Operation::Sparkline)?;
+
+        assert_eq!(calc.stack(), &[Value::Integer(1), Value::Integer(2)]);
+        assert_eq!(calc.take_output(), vec!["▁█"]);
+        assert!(calc.take_output().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_variables() -> Result<(), CalculatorError> {
+        let mut calc = Calculator::new()
+            .push(Value::Integer(42))
+            .apply(Operation::Store("answer".to_string()))?
+            .push(Value::Float(0.5))
+            .apply(Operation::Store("half".to_string()))?;
+
+        assert!(calc.stack().is_empty());
+        assert_eq!(calc.variable_names(), vec!["answer", "half"]);
+
+        calc = calc
+            .apply(Operation::Recall("answer".to_string()))?
+            .apply(Operation::SetRadix(Radix::Hex))?
+            .apply(Operation::ListVariables)?;
+
+        assert_eq!(calc.stack(), &[Value::Integer(42)]);
+        assert_eq!(calc.take_output(), vec!["answer = 0x2a", "half = 0.5"]);
+
+        assert!(matches!(
+            calc.apply(Operation::Recall("nope".to_string())),
+            Err(CalculatorError::UnknownVariable(_))
+        ));
+
+        let store_with = |op| Operation::StoreWith("answer".to_string(), Box::new(op));
+        calc = calc
+            .push(Value::Integer(8))
+            .apply(store_with(Operation::Add))?
+            .push(Value::Integer(2))
+            .apply(store_with(Operation::Divide))?;
+
+        assert_eq!(calc.stack(), &[Value::Integer(42)]);
+        assert_eq!(calc.variables["answer"], Value::Integer(25));
+        assert!(calc
+            .push(Value::Integer(0))
+            .apply(store_with(Operation::Divide))
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_divmod() {
+        let divmod = |line: &str| Calculator::new().eval(line).map(|s| s.to_vec());
+
+        assert_eq!(
+            divmod("17 5 divmod").unwrap(),
+            vec![Value::Integer(3), Value::Integer(2)]
+        );
+        assert_eq!(
+            divmod("-17 5 divmod").unwrap(),
+            vec![Value::Integer(-3), Value::Integer(-2)]
+        );
+        assert_eq!(
+            divmod("17.9 5 divmod").unwrap(),
+            vec![Value::Integer(3), Value::Integer(2)]
+        );
+        assert_eq!(
+            divmod("overflow promote 100000000000000000000 7 divmod").unwrap(),
+            vec![
+                Value::Big(BigInt::from(14_285_714_285_714_285_714u64)),
+                Value::Integer(2)
+            ]
+        );
+        assert!(divmod("1 0 divmod").is_err());
+        assert!(divmod("-9223372036854775808 -1 divmod").is_err());
+    }
+
+    #[test]
+    fn test_money() {
+        let money = |line: &str| {
+            let mut calc = Calculator::new();
+
+            match calc.eval(line) {
+                Ok(_) => Ok(calc
+                    .stack()
+                    .iter()
+                    .map(|v| calc.format_value(v))
+                    .collect::<Vec<_>>()
+                    .join(" ")),
+                Err(Error::Calculator { error, .. }) => Err(error),
+                Err(e) => panic!("{}", e),
+            }
+        };
+
+        assert_eq!(money("0.10EUR 0.20EUR +").unwrap(), "0.30EUR");
+        assert_eq!(money("19.99USD 3 * 5USD -").unwrap(), "54.97USD");
+        assert_eq!(money("100EUR 3 / round2").unwrap(), "33.33EUR");
+        assert_eq!(
+            money("rounding half-up 0.125EUR round2 2.675 round2").unwrap(),
+            "0.13EUR 2.68"
+        );
+        assert_eq!(money("30EUR 120EUR /").unwrap(), "0.25");
+
+        assert_eq!(
+            money("1EUR 1USD +").unwrap_err().to_string(),
+            "Currency mismatch: EUR and USD"
+        );
+        assert_eq!(
+            money("1 1EUR -").unwrap_err().to_string(),
+            "Currency mismatch: a plain number and EUR"
+        );
+        assert_eq!(
+            money("1EUR 1EUR *").unwrap_err().to_string(),
+            "Can't apply `*` to money and money"
+        );
+        assert!(money("1 1EUR /").is_err());
+        assert!(money("1EUR 0 /").is_err());
+    }
+
+    #[test]
+    fn test_shifts() -> Result<(), CalculatorError> {
+        let shift = |a: i64, b: i64, op: Operation| {
+            Calculator::new()
+                .push(Value::Integer(a))
+                .push(Value::Integer(b))
+                .apply(op)
+        };
+
+        assert_eq!(
+            shift(3, 4, Operation::LeftShift)?.stack(),
+            &[Value::Integer(48)]
+        );
+        assert_eq!(
+            shift(-16, 2, Operation::RightShift)?.stack(),
+            &[Value::Integer(-4)]
+        );
+        assert!(matches!(
+            shift(1, 64, Operation::LeftShift),
+            Err(CalculatorError::ShiftOutOfRange { amount: 64 })
+        ));
+        assert!(matches!(
+            shift(1, -1, Operation::RightShift),
+            Err(CalculatorError::ShiftOutOfRange { amount: -1 })
+        ));
+
+        let masked = Calculator::new().apply(Operation::SetOverflow(OverflowMode::Mask))?;
+        assert_eq!(
+            masked
+                .push(Value::Integer(1))
+                .push(Value::Integer(65))
+                .apply(Operation::LeftShift)?
+                .stack(),
+            &[Value::Integer(2)]
+        );
+        assert_eq!(
+            masked
+                .push(Value::Integer(-8))
+                .push(Value::Integer(-63))
+                .apply(Operation::RightShift)?
+                .stack(),
+            &[Value::Integer(-4)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_assertions() {
+        let check = |line: &str| Calculator::new().eval(line).map(|s| s.to_vec());
+
+        assert_eq!(
+            check("7 1 2 + 3 assert-eq").unwrap(),
+            vec![Value::Integer(7)]
+        );
+        assert!(check("2 2.0 assert-eq").is_ok());
+        assert!(check("0.1 0.2 + 0.3 1e-12 assert-near").is_ok());
+
+        assert_eq!(
+            check("1 2 + 4 assert-eq").unwrap_err().to_string(),
+            "Assertion failed: 3 != 4 in `assert-eq` (column 9)"
+        );
+        assert_eq!(
+            check("0.1 0.2 + 0.3 assert-eq").unwrap_err().to_string(),
+            "Assertion failed: 0.30000000000000004 != 0.3 in `assert-eq` (column 15)"
+        );
+        assert!(check("1 1.5 0.1 assert-near").is_err());
+        assert!(check("1 assert-eq").is_err());
+
+        assert!(check("eps 1e-12 0.1 0.2 + 0.3 assert-eq").is_ok());
+        assert!(check("eps 1e-12 1 1.5 0.1 assert-near").is_err());
+        assert!(check("eps 1 2 3 assert-eq").is_err());
+
+        assert!(check("1.50EUR 1.5EUR assert-eq").is_ok());
+        assert!(check("1.50EUR 1.20EUR assert-eq").is_err());
+        assert!(check("1EUR 1USD assert-eq").is_err());
+        assert!(check("1.50EUR 1.20EUR 0.5 assert-near").is_ok());
+        assert!(check("1EUR 1USD 0.5 assert-near").is_err());
+    }
+
+    #[test]
+    fn test_compare() {
+        let check = |line: &str| Calculator::new().eval(line).map(|s| s.to_vec());
+
+        assert_eq!(
+            check("1 1 == 1 2 == 2 2.0 == 1 2 !=").unwrap(),
+            vec![
+                Value::Integer(1),
+                Value::Integer(0),
+                Value::Integer(1),
+                Value::Integer(1)
+            ]
+        );
+        assert_eq!(check("0.1 0.2 + 0.3 ==").unwrap(), vec![Value::Integer(0)]);
+        assert_eq!(
+            check("eps 1e-12 0.1 0.2 + 0.3 == 0.1 0.2 + 0.3 !=").unwrap(),
+            vec![Value::Integer(1), Value::Integer(0)]
+        );
+        // Integers are exact, whatever the tolerance.
+        assert_eq!(check("eps 1 1 2 ==").unwrap(), vec![Value::Integer(0)]);
+        assert!(parse("eps -1").is_err());
+
+        assert_eq!(
+            check("1.50EUR 1.5EUR == 1.50EUR 1.20EUR == 1.50EUR 1.20EUR !=").unwrap(),
+            vec![Value::Integer(1), Value::Integer(0), Value::Integer(1)]
+        );
+        assert!(check("1EUR 1USD ==").is_err());
+        assert!(check("1EUR 1 ==").is_err());
+        assert!(check("1EUR 1USD !=").is_err());
+    }
+
+    #[test]
+    fn test_clamp() {
+        let check = |line: &str| Calculator::new().eval(line).map(|s| s.to_vec());
+
+        assert_eq!(
+            check("5 0 10 clamp -5 0 10 clamp 15 0 10 clamp 0.5 0 1.5 clamp").unwrap(),
+            vec![
+                Value::Integer(5),
+                Value::Integer(0),
+                Value::Integer(10),
+                Value::Float(0.5)
+            ]
+        );
+        assert_eq!(
+            check("0 0 10 between? 10 0 10 between? 10.5 0 10 between?").unwrap(),
+            vec![Value::Integer(1), Value::Integer(1), Value::Integer(0)]
+        );
+        assert_eq!(
+            check("12EUR 0EUR 10EUR clamp").unwrap(),
+            vec![money::parse("10EUR").unwrap()]
+        );
+
+        assert!(check("5 10 0 clamp").is_err());
+        assert!(check("5EUR 0 10 between?").is_err());
+    }
+
+    #[test]
+    fn test_range() {
+        let mut calc = Calculator::new();
+        let ints = |v: &[i64]| v.iter().map(|&i| Value::Integer(i)).collect::<Vec<_>>();
+
+        assert_eq!(calc.eval("4 iota").unwrap(), ints(&[1, 2, 3, 4]).as_slice());
+        assert_eq!(
+            Calculator::new()
+                .eval("10 1 range")
+                .unwrap_err()
+                .to_string(),
+            "Stack Underflow: needs 1 more value in `range` (column 6)"
+        );
+        assert_eq!(
+            Calculator::new().eval("0 range").unwrap_err().to_string(),
+            "Stack Underflow: needs 2 more values in `range` (column 3)"
+        );
+        assert_eq!(
+            Calculator::new().eval("iota").unwrap_err().to_string(),
+            "Stack Underflow: needs 1 more value in `iota` (column 1)"
+        );
+        assert_eq!(
+            Calculator::new().eval("10 0 -3 range").unwrap(),
+            ints(&[10, 7, 4, 1]).as_slice()
+        );
+        assert_eq!(
+            Calculator::new().eval("0 1 0.25 range").unwrap(),
+            &[
+                Value::Float(0.0),
+                Value::Float(0.25),
+                Value::Float(0.5),
+                Value::Float(0.75),
+                Value::Float(1.0)
+            ]
+        );
+        assert_eq!(Calculator::new().eval("0 iota 5 1 1 range").unwrap(), &[]);
+
+        assert!(Calculator::new().eval("1 5 0 range").is_err());
+        assert!(Calculator::new().eval("1e9 iota").is_err());
+        assert!(Calculator::new()
+            .eval("-9223372036854775807 9223372036854775807 1 range")
+            .is_err());
+        assert!(Calculator::new().eval("0 1 0.0 range").is_err());
+    }
+
+    #[test]
+    fn test_definitions() {
+        let mut calc = Calculator::new();
+
+        assert_eq!(
+            calc.eval("def sq [ sto x rcl x rcl x * ] 3 run sq run sq")
+                .unwrap(),
+            &[Value::Integer(81)]
+        );
+        assert!(matches!(
+            calc.apply(Operation::Run("nope".to_string())),
+            Err(CalculatorError::UnknownDefinition(_))
+        ));
+        assert!(matches!(
+            calc.eval("def loop [ run loop ] run loop"),
+            Err(Error::Calculator {
+                error: CalculatorError::RecursionLimit,
+                ..
+            })
+        ));
+
+        calc.eval("-2 2 4 plotf sq").unwrap();
+        let output = calc.take_output();
+        assert_eq!(output.len(), PLOT_HEIGHT + 1);
+        assert_eq!(output[0], "4 |*   *");
+        assert_eq!(output[PLOT_HEIGHT - 1], "0 |--*--");
+        assert_eq!(output[PLOT_HEIGHT], "x = -2 .. 2");
+        assert_eq!(calc.stack(), &[Value::Integer(81)]);
+
+        assert!(calc.eval("0 1 0 plotf sq").is_err());
+    }
+
+    #[test]
+    fn test_calculus() {
+        let mut calc = Calculator::new();
+        let near = |calc: &mut Calculator, line, expected: f64| {
+            let actual = f64::from(calc.eval(line).unwrap()[0].clone());
+
+            assert!((actual - expected).abs() < 1e-9, "{} = {}", line, actual);
+            calc.eval("clear").unwrap();
+        };
+
+        calc.eval("def sq [ dup * ] def e [ exp ]").unwrap();
+        near(&mut calc, "0 3 integ sq", 9.0);
+        near(&mut calc, "0 1 integ e", 1f64.exp() - 1.0);
+        near(&mut calc, "3 deriv sq", 6.0);
+        near(&mut calc, "2 deriv e", 2f64.exp());
+
+        assert!(matches!(
+            calc.eval("0 1 integ nope"),
+            Err(Error::Calculator {
+                error: CalculatorError::UnknownDefinition(_),
+                ..
+            })
+        ));
+        assert!(calc.eval("def nothing [ drop ] 1 deriv nothing").is_err());
+    }
+
+    #[test]
+    fn test_solve() {
+        let mut calc = Calculator::new();
+
+        let mut root = |line| f64::from(calc.eval(line).unwrap()[0].clone());
+        let sqrt2 = 2f64.sqrt();
+
+        // Full precision is within a bit or two.
+        assert!((root("def f [ dup * 2 - ] 1 solve f") - sqrt2).abs() < 1e-15);
+        assert!((root("clear -5 0 solvein f") + sqrt2).abs() < 1e-15);
+        assert!((root("clear eps 0.01 0 2 solvein f") - sqrt2).abs() <= 0.01);
+
+        assert!(matches!(
+            calc.eval("clear 2 3 solvein f"),
+            Err(Error::Calculator {
+                error: CalculatorError::NoRoot,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_finance() {
+        let mut calc = Calculator::new();
+        let mut eval = |line| f64::from(calc.eval(line).unwrap()[0].clone());
+
+        let pmt = eval("360 n= 0.5 i= 200000 pv= 0 fv= pmt?");
+        assert!((pmt + 1199.1010503055138).abs() < 1e-9, "{}", pmt);
+        assert!((eval("clear i?") - 0.5).abs() < 1e-12);
+        assert_eq!(eval("clear -1000 300 400 500 0 npv"), 200.0);
+
+        let irr = eval("clear -1000 300 400 500 irr");
+        assert!((eval(&format!("clear -1000 300 400 500 {} npv", irr))).abs() < 1e-9);
+
+        assert!(matches!(
+            Calculator::new().eval("10 n= fv?"),
+            Err(Error::Calculator {
+                error: CalculatorError::FinanceError(_),
+                ..
+            })
+        ));
+        assert!(Calculator::new().eval("5 npv").is_err());
+        assert!(Calculator::new().eval("100 200 irr").is_err());
+    }
+
+    #[test]
+    fn test_cancel() {
+        static INTERRUPT: AtomicBool = AtomicBool::new(false);
+        let mut calc = Calculator::new();
+
+        calc.set_timeout(Some(Duration::ZERO));
+        assert_eq!(
+            calc.eval("def sq [ dup * ] 3").unwrap(),
+            &[Value::Integer(3)]
+        );
+        assert!(matches!(
+            calc.eval("run sq"),
+            Err(Error::Calculator {
+                error: CalculatorError::TimedOut(_),
+                ..
+            })
+        ));
+        assert!(matches!(
+            calc.apply(Operation::Push(Value::Integer(0)))
+                .and_then(|c| c.apply(Operation::Push(Value::Integer(1))))
+                .and_then(|c| c.apply(Operation::Push(Value::Integer(2))))
+                .and_then(|c| c.apply(Operation::PlotFunction("sq".to_string()))),
+            Err(CalculatorError::TimedOut(_))
+        ));
+
+        calc.set_timeout(None);
+        calc.set_interrupt(&INTERRUPT);
+        INTERRUPT.store(true, Ordering::Relaxed);
+        assert!(matches!(
+            calc.eval("run sq"),
+            Err(Error::Calculator {
+                error: CalculatorError::Interrupted,
+                ..
+            })
+        ));
+        assert_eq!(calc.eval("run sq").unwrap(), &[Value::Integer(9)]);
+    }
+
+    #[test]
+    fn test_constants() {
+        let mut calc = Calculator::new();
+        calc.define_constant("f_cpu", Value::Integer(16_000_000));
+        calc.define_constant("neg", Value::Integer(-1));
+
+        assert_eq!(
+            calc.eval("f_cpu 1000 / neg").unwrap(),
+            &[Value::Integer(16_000), Value::Integer(-1)]
+        );
+        assert_eq!(
+            calc.format_constants(),
+            vec!["f_cpu = 16000000", "neg = -1"]
+        );
+        assert!(calc.eval("f_mem").is_err());
+    }
+
+    #[test]
+    fn test_snapshots() -> Result<(), CalculatorError> {
+        let calc = Calculator::new()
+            .push(Value::Integer(1))
+            .push(Value::Integer(2))
+            .apply(Operation::Snapshot("two".to_string()))?
+            .apply(Operation::Add)?;
+
+        assert_eq!(calc.stack(), &[Value::Integer(3)]);
+        assert_eq!(calc.format_snapshots(), vec!["two: 1 2"]);
+
+        let restored = calc.apply(Operation::Restore("two".to_string()))?;
+        assert_eq!(restored.stack(), &[Value::Integer(1), Value::Integer(2)]);
+        assert_eq!(restored.snapshot_names(), vec!["two"]);
+
+        assert!(matches!(
+            calc.apply(Operation::Restore("nope".to_string())),
+            Err(CalculatorError::UnknownSnapshot(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut calc = Calculator::new();
+
+        calc.eval("1 2 3 snap a 4 * 5 snap b diff a b diff b b")
+            .unwrap();
+        assert_eq!(
+            calc.take_output(),
+            vec!["~ 3: 3 -> 12", "+ 4: 5", "b and b are the same"]
+        );
+
+        calc.eval("clear 1 snap c diff a c").unwrap();
+        assert_eq!(calc.take_output(), vec!["- 2: 2", "- 3: 3"]);
+
+        assert!(calc.eval("diff a nope").is_err());
+    }
+
+    #[test]
+    fn test_float_promo() -> Result<(), CalculatorError> {
+        // An addition with one float parameter becomes a float
+        // addtion.
+        assert_eq!(
+            Calculator::new()
+                .push(Value::Integer(1))
+                .push(Value::Float(2.0))
+                .apply(Operation::Add)?
+                .stack(),
+            &[Value::Float(3.0)]
+        );
+
+        assert_eq!(
+            Calculator::new()
+                .push(Value::Float(2.0))
+                .push(Value::Integer(1))
+                .apply(Operation::Add)?
+                .stack(),
+            &[Value::Float(3.0)]
+        );
+
+        assert_eq!(
+            Calculator::new()
+                .push(Value::Float(1.0))
+                .push(Value::Float(2.0))
+                .apply(Operation::Add)?
+                .stack(),
+            &[Value::Float(3.0)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut calc = Calculator::new();
+
+        assert_eq!(calc.eval("1 2 +").unwrap(), &[Value::Integer(3)]);
+        assert_eq!(calc.eval("4 *").unwrap(), &[Value::Integer(12)]);
+
+        // Failing lines don't change anything.
+        let e = calc.eval("5 foo").unwrap_err();
+        assert!(matches!(e, Error::Parse { .. }));
+        assert_eq!(e.span(), 2..5);
+        assert_eq!(e.to_string(), "Failed to parse token: foo (column 3)");
+
+        let e = calc.eval("5 + +").unwrap_err();
+        assert!(matches!(
+            e,
+            Error::Calculator {
+                error: CalculatorError::MissingValues(1),
+                operation: Operation::Add,
+                ..
+            }
+        ));
+        assert_eq!(e.line(), "5 + +");
+        assert_eq!(
+            e.to_string(),
+            "Stack Underflow: needs 1 more value in `+` (column 5)"
+        );
+        assert_eq!(calc.stack(), &[Value::Integer(12)]);
+
+        // The whole line is checked before anything runs.
+        assert_eq!(
+            calc.eval("+ + +").unwrap_err().to_string(),
+            "Stack Underflow: needs 3 more values in `+` (column 1)"
+        );
+
+        // Operations with a variable stack effect end the check.
+        let e = calc.eval("3 iota + + + +").unwrap_err();
+        assert!(matches!(
+            e,
+            Error::Calculator {
+                error: CalculatorError::StackUnderflow,
+                ..
+            }
+        ));
+
+        let mut results = Calculator::new();
+        results.eval("1").unwrap();
+        results.eval("").unwrap();
+        results.eval("2 +").unwrap();
+        assert_eq!(
+            results.eval("$1 $2 res 1").unwrap(),
+            &[
+                Value::Integer(3),
+                Value::Integer(3),
+                Value::Integer(1),
+                Value::Integer(3)
+            ]
+        );
+        assert!(results.eval("$5").is_err());
+        assert!(results.eval("$0").is_err());
+
+        results.eval("clear 0x20 sto offset").unwrap();
+        results.eval("rcl offset 2 *").unwrap();
+        results.eval("rcl offset 2 *").unwrap();
+        results.eval("clear rcl offset 3 *").unwrap();
+        assert_eq!(
+            results.search_results("offset"),
+            vec!["$1: clear rcl offset 3 * = 96", "$2: rcl offset 2 * = 64"]
+        );
+        assert!(results.search_results("nope").is_empty());
+
+        let mut trace = vec![];
+        calc.eval_with("1 swap", |op, c| trace.push(format!("{} {}", op, c)))
+            .unwrap();
+        assert_eq!(trace, vec!["1 12 1", "swap 1 12"]);
+
+        // Pasted blocks are evaluated as one line.
+        let e = calc.eval("1 2 +\n3 4 +\n+ + + +\n").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Stack Underflow: needs 1 more value in `+` (line 3, column 7)"
+        );
+        assert_eq!(calc.stack(), &[Value::Integer(1), Value::Integer(12)]);
+        assert_eq!(
+            calc.eval("1 2 +\n3 4 +\n").unwrap(),
+            &[
+                Value::Integer(1),
+                Value::Integer(12),
+                Value::Integer(3),
+                Value::Integer(7)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scripts() {
+        let script = "( -- x ) 5\n( -- y ) 6\n( a b -- c ) +\n";
+        let errors = |script: &str| {
+            Calculator::new()
+                .eval_lines(script)
+                .into_iter()
+                .map(|(n, e)| (n, e.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        // Evaluated as a whole or line by line, the script means the same.
+        assert_eq!(
+            Calculator::new().eval(script).unwrap(),
+            &[Value::Integer(11)]
+        );
+        let mut calc = Calculator::new();
+        assert!(calc.eval_lines(script).is_empty());
+        assert_eq!(calc.stack(), &[Value::Integer(11)]);
+
+        let script = "1\n( -- x ) 2 3\n4 +\n";
+        assert_eq!(
+            Calculator::new().eval(script).unwrap_err().to_string(),
+            "Stack effect ( -- x ) doesn't match the operations, which take 0 and leave 2 \
+             (line 2, column 1)"
+        );
+        assert_eq!(
+            errors(script),
+            vec![(
+                2,
+                "Stack effect ( -- x ) doesn't match the operations, which take 0 and leave 2 \
+                 (column 1)"
+                    .to_string()
+            )]
+        );
+    }
 
     #[test]
-    fn test_simple() -> Result<(), CalculatorError> {
-        assert!(Calculator::new().stack().is_empty());
+    fn test_overflow_promote() -> Result<(), CalculatorError> {
+        let calc = Calculator::new()
+            .push(Value::Integer(i64::MAX))
+            .push(Value::Integer(2));
+
+        assert!(calc.apply(Operation::Multiply).is_err());
+
+        let big = calc
+            .apply(Operation::SetOverflow(OverflowMode::Promote))?
+            .apply(Operation::Multiply)?;
+        assert_eq!(big.stack(), &[Value::Big(BigInt::from(i64::MAX) * 2)]);
+        assert_eq!(big.to_string(), "18446744073709551614");
+
+        // Results that fit again become normal integers.
+        let small = big.push(Value::Integer(4)).apply(Operation::Divide)?;
+        assert_eq!(small.stack(), &[Value::Integer(i64::MAX / 2)]);
 
+        // Floats win over big integers.
+        let float = big.push(Value::Float(0.5)).apply(Operation::Multiply)?;
+        assert_eq!(float.stack(), &[Value::Float(i64::MAX as f64)]);
+
+        // Integer-only operations can't handle big integers.
+        assert!(big
+            .push(Value::Integer(1))
+            .apply(Operation::BitAnd)
+            .is_err());
+        assert!(big
+            .push(Value::Integer(0))
+            .apply(Operation::Divide)
+            .is_err());
+
+        // Without promotion, big integers are neither read nor used.
+        let plain = big.apply(Operation::SetOverflow(OverflowMode::Error))?;
+        assert!(plain.push(Value::Integer(1)).apply(Operation::Add).is_err());
+        assert!(matches!(
+            Calculator::new().eval("99999999999999999999 1 +"),
+            Err(Error::Parse { .. })
+        ));
         assert_eq!(
             Calculator::new()
-                .apply(Operation::Push(Value::Integer(1)))?
-                .stack(),
-            &[Value::Integer(1)]
+                .eval("overflow promote 99999999999999999999 1 +")
+                .unwrap(),
+            &[Value::Big(BigInt::from(10).pow(20))]
         );
 
         Ok(())
     }
 
     #[test]
-    fn test_integer_2op() -> Result<(), CalculatorError> {
-        // An operation where parameter order does not matter.
+    fn test_demotion() -> Result<(), CalculatorError> {
+        let calc = Calculator::new().push(Value::Float(4.5));
+
         assert_eq!(
-            Calculator::new()
-                .push(Value::Integer(1))
-                .push(Value::Integer(2))
-                .apply(Operation::Add)?
+            calc.push(Value::Float(2.0))
+                .apply(Operation::Multiply)?
                 .stack(),
-            &[Value::Integer(3)]
+            &[Value::Float(9.0)]
         );
 
-        // An operation where parameter order does matter.
+        let demoting = calc.apply(Operation::SetDemotion(true))?;
+        assert_eq!(demoting.stack(), &[Value::Float(4.5)]);
         assert_eq!(
-            Calculator::new()
-                .push(Value::Integer(6))
-                .push(Value::Integer(2))
-                .apply(Operation::Divide)?
+            demoting
+                .push(Value::Float(2.0))
+                .apply(Operation::Multiply)?
+                .apply(Operation::BitNot)?
                 .stack(),
-            &[Value::Integer(3)]
+            &[Value::Integer(!9)]
+        );
+
+        // Too large for integers.
+        assert_eq!(
+            demoting.apply(Operation::Push(Value::Float(1e19)))?.stack()[1..],
+            [Value::Float(1e19)]
+        );
+        assert_eq!(
+            Value::Float(-(i64::MIN as f64)).demoted(),
+            Value::Float(2f64.powi(63))
+        );
+        assert_eq!(
+            Value::Float(i64::MIN as f64).demoted(),
+            Value::Integer(i64::MIN)
         );
 
         Ok(())
     }
 
     #[test]
-    fn test_float_promo() -> Result<(), CalculatorError> {
-        // An addition with one float parameter becomes a float
-        // addtion.
+    fn test_fraction_display() -> Result<(), CalculatorError> {
+        let calc = Calculator::new()
+            .push(Value::Float(0.75))
+            .push(Value::Float(1.0 / 3.0))
+            .push(Value::Float(2.0))
+            .push(Value::Integer(5))
+            .push(Value::Float(f64::INFINITY));
+
         assert_eq!(
-            Calculator::new()
-                .push(Value::Integer(1))
-                .push(Value::Float(2.0))
-                .apply(Operation::Add)?
-                .stack(),
-            &[Value::Float(3.0)]
+            calc.apply(Operation::SetFractionDisplay(true))?.to_string(),
+            "3/4 1/3 2 5 inf"
         );
 
+        Ok(())
+    }
+
+    #[test]
+    fn test_si_display() -> Result<(), CalculatorError> {
+        let calc = Calculator::new()
+            .push(Value::Float(4.7e-6))
+            .push(Value::Float(12_345.0))
+            .push(Value::Integer(12_345))
+            .apply(Operation::SetSiDisplay(true))?;
+
+        assert_eq!(calc.to_string(), "4.7µ 12.345k 12345");
         assert_eq!(
-            Calculator::new()
-                .push(Value::Float(2.0))
-                .push(Value::Integer(1))
-                .apply(Operation::Add)?
-                .stack(),
-            &[Value::Float(3.0)]
+            calc.apply(Operation::SetPrecision(2))?.to_string(),
+            "4.7µ 12k 12345"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hms() -> Result<(), CalculatorError> {
+        let tohms = |v| -> Result<Value, CalculatorError> {
+            Ok(Calculator::new().push(v).apply(Operation::ToHms)?.stack()[0].clone())
+        };
+
+        assert_eq!(tohms(Value::Integer(5415))?, Value::Float(1.3015));
+        assert_eq!(tohms(Value::Integer(-5415))?, Value::Float(-1.3015));
+        assert_eq!(tohms(Value::Float(59.5))?, Value::Float(0.00595));
+        assert_eq!(tohms(Value::Float(3.0 * 1.1))?, Value::Float(0.000330));
+        assert_eq!(tohms(Value::Integer(360_000))?, Value::Float(100.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dms() -> Result<(), CalculatorError> {
+        let unary = |op, v| -> Result<Value, CalculatorError> {
+            Ok(Calculator::new().push(v).apply(op)?.stack()[0].clone())
+        };
+
+        assert_eq!(
+            unary(Operation::ToDms, Value::Float(1.51))?,
+            Value::Float(1.3036)
+        );
+        assert_eq!(
+            unary(Operation::ToDms, Value::Float(-12.5))?,
+            Value::Float(-12.3)
         );
+        assert_eq!(
+            unary(Operation::FromDms, Value::Float(1.3036))?,
+            Value::Float(1.51)
+        );
+        assert_eq!(
+            unary(Operation::FromDms, Value::Integer(90))?,
+            Value::Float(90.0)
+        );
+
+        // Decimal degrees work with the angle mode.
+        let calc = Calculator::new()
+            .apply(Operation::SetAngleMode(AngleMode::Degrees))?
+            .push(Value::Float(1.0))
+            .push(Value::Float(1.0))
+            .apply(Operation::Atan2)?
+            .apply(Operation::ToDms)?;
+        assert_eq!(calc.stack(), &[Value::Float(45.0)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_coordinates() -> Result<(), CalculatorError> {
+        let deg = Calculator::new().apply(Operation::SetAngleMode(AngleMode::Degrees))?;
 
         assert_eq!(
-            Calculator::new()
-                .push(Value::Float(1.0))
-                .push(Value::Float(2.0))
-                .apply(Operation::Add)?
+            deg.push(Value::Integer(3))
+                .push(Value::Integer(4))
+                .apply(Operation::ToPolar)?
                 .stack(),
-            &[Value::Float(3.0)]
+            &[
+                Value::Float(5.0),
+                Value::Float(4f64.atan2(3.0).to_degrees())
+            ]
+        );
+        assert_eq!(
+            Calculator::new()
+                .push(Value::Integer(2))
+                .push(Value::Float(std::f64::consts::FRAC_PI_2))
+                .apply(Operation::ToRect)?
+                .stack()[1],
+            Value::Float(2.0)
+        );
+
+        // Round trip in degrees.
+        let rect = deg
+            .push(Value::Integer(2))
+            .push(Value::Integer(60))
+            .apply(Operation::ToRect)?;
+        let polar = rect.apply(Operation::ToPolar)?;
+        let close = |v: &Value, e: f64| (f64::from(v.clone()) - e).abs() < 1e-12;
+
+        assert!(close(&rect.stack()[0], 1.0));
+        assert!(close(&polar.stack()[0], 2.0));
+        assert!(close(&polar.stack()[1], 60.0));
+
+        assert!(deg
+            .push(Value::Integer(1))
+            .apply(Operation::ToRect)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_digit_group() -> Result<(), CalculatorError> {
+        let calc = Calculator::new()
+            .push(Value::Integer(0xdead_beef))
+            .push(Value::Integer(-1))
+            .apply(Operation::SetRadix(Radix::Hex))?
+            .apply(Operation::SetDigitGroup(4))?;
+
+        assert_eq!(calc.to_string(), "0xdead_beef 0xffff_ffff_ffff_ffff");
+        assert_eq!(
+            calc.apply(Operation::SetRadix(Radix::Bin))?
+                .apply(Operation::Swap)?
+                .apply(Operation::SetDigitGroup(8))?
+                .format_top(1, " "),
+            "... 0b11011110_10101101_10111110_11101111"
+        );
+        assert_eq!(
+            calc.push(Value::Big(-(BigInt::from(1) << 68usize)))
+                .format_top(1, " "),
+            "... -0x10_0000_0000_0000_0000"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_locale() -> Result<(), CalculatorError> {
+        let mut calc = Calculator::new()
+            .push(Value::Integer(1234567))
+            .push(Value::Float(-1234.5));
+        calc.set_locale("de".parse().unwrap());
+
+        assert_eq!(calc.to_string(), "1.234.567 -1.234,5");
+        assert_eq!(
+            calc.apply(Operation::SetRadix(Radix::Hex))?
+                .format_top(1, " "),
+            "... -1.234,5"
+        );
+        assert_eq!(
+            calc.apply(Operation::SetRadix(Radix::Hex))?
+                .apply(Operation::Swap)?
+                .format_top(1, " "),
+            "... 0x12d687"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut calc = Calculator::new();
+        calc.set_limits(Limits {
+            max_bits: 100,
+            max_values: 10,
+            max_bytes: 8 * std::mem::size_of::<Value>(),
+        });
+
+        calc.eval("overflow promote def sq [ dup * ]").unwrap();
+        assert_eq!(
+            calc.eval("2 run sq run sq run sq run sq run sq run sq")
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(matches!(
+            calc.eval("run sq"),
+            Err(Error::Calculator {
+                error: CalculatorError::ResourceLimit(_),
+                ..
+            })
+        ));
+
+        calc.eval("clear").unwrap();
+        assert_eq!(calc.eval("8 iota").unwrap().len(), 8);
+        assert!(calc.eval("1").is_err());
+        assert_eq!(
+            calc.eval("clear 11 iota").unwrap_err().to_string(),
+            "Resource limit exceeded: 11 values on the stack (max 10) in `iota` (column 10)"
+        );
+
+        // The argument that `keep` puts back counts, too.
+        let mut calc = Calculator::new();
+        calc.set_limits(Limits {
+            max_values: 2,
+            ..Limits::default()
+        });
+        assert_eq!(
+            calc.eval("keep on 1 2 ~").unwrap_err().to_string(),
+            "Resource limit exceeded: 3 values on the stack (max 2) in `~` (column 13)"
+        );
+        assert_eq!(calc.stack(), &[]);
+
+        // Checking the limits doesn't count the whole stack again after
+        // every push, so loading lots of values stays fast.
+        let mut calc = Calculator::new();
+        let start = Instant::now();
+        for i in 0..200_000 {
+            calc.apply_mut(Operation::Push(Value::Integer(i))).unwrap();
+        }
+        assert_eq!(calc.stack().len(), 200_000);
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // Operations that bypass the count are counted again.
+        calc.eval("clear 1000 iota").unwrap();
+        assert_eq!(calc.stack_bytes, 1000 * std::mem::size_of::<Value>());
+        calc.eval("drop 2 +").unwrap();
+        assert_eq!(calc.stack_bytes, 999 * std::mem::size_of::<Value>());
+    }
+
+    #[test]
+    fn test_padding() -> Result<(), CalculatorError> {
+        let calc = Calculator::new()
+            .push(Value::Integer(0xff))
+            .push(Value::Integer(0x1_0000_0000))
+            .apply(Operation::SetRadix(Radix::Hex))?
+            .apply(Operation::SetPadding(32))?;
+
+        assert_eq!(calc.to_string(), "0x000000ff 0x100000000");
+        assert_eq!(
+            calc.apply(Operation::SetDigitGroup(4))?.to_string(),
+            "0x0000_00ff 0x1_0000_0000"
+        );
+        assert_eq!(
+            calc.apply(Operation::SetRadix(Radix::Bin))?
+                .apply(Operation::SetPadding(12))?
+                .format_top(2, " ")
+                .split(' ')
+                .next(),
+            Some("0b000011111111")
+        );
+        // Padding doesn't apply to decimals.
+        assert_eq!(
+            calc.apply(Operation::SetRadix(Radix::Dec))?.to_string(),
+            "255 4294967296"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ipv4() -> Result<(), CalculatorError> {
+        let mut calc = Calculator::new();
+
+        assert_eq!(
+            calc.eval("ip 192.168.1.77/26 broadcast").unwrap(),
+            &[Value::Integer(0xc0a8_017f)]
+        );
+        assert_eq!(calc.to_string(), "192.168.1.127");
+
+        calc.eval("26 netmask 26 hosts -1").unwrap();
+        assert_eq!(calc.format_top(3, " "), "... 255.255.255.192 0.0.0.62 -1");
+
+        assert_eq!(
+            calc.eval("192.168.1.77 192.168.1.0/25 in-subnet?").unwrap()[4..],
+            [Value::Integer(1)]
+        );
+        assert!(calc.eval("1 2 33 in-subnet?").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode() -> Result<(), CalculatorError> {
+        let mut calc = Calculator::new()
+            .push(Value::Integer(0b1011_0101))
+            .apply(Operation::SetRadix(Radix::Hex))?
+            .apply(Operation::Decode("en:0,mode:7:4".to_string()))?;
+
+        assert_eq!(calc.stack(), &[Value::Integer(0b1011_0101)]);
+        assert_eq!(
+            calc.take_output(),
+            vec!["en   [0:0] = 0x1", "mode [7:4] = 0xb"]
+        );
+
+        let path = std::env::temp_dir().join(format!("clac-test-{}.toml", std::process::id()));
+        std::fs::write(&path, "low = [3, 0]\n").unwrap();
+
+        let decoded = calc.apply(Operation::Decode(path.to_string_lossy().to_string()));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(decoded?.take_output(), vec!["low [3:0] = 0x5"]);
+
+        assert!(matches!(
+            calc.apply(Operation::Decode("en".to_string())),
+            Err(CalculatorError::BitfieldError(_))
+        ));
+        assert!(matches!(
+            calc.apply(Operation::Decode("/nonexistent/clac.toml".to_string())),
+            Err(CalculatorError::FileError(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_permissions() -> Result<(), CalculatorError> {
+        let mut calc = Calculator::new()
+            .push(Value::Integer(0o4750))
+            .apply(Operation::ShowPermissions)?;
+
+        assert_eq!(calc.take_output(), vec!["rwsr-x--- 4750"]);
+        assert_eq!(calc.stack(), &[Value::Integer(0o4750)]);
+        assert!(calc
+            .push(Value::Integer(0o10000))
+            .apply(Operation::ShowPermissions)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes() -> Result<(), CalculatorError> {
+        let mut calc = Calculator::new()
+            .push(Value::Integer(1_610_612_736))
+            .apply(Operation::ShowBytes)?;
+
+        assert_eq!(calc.take_output(), vec!["1.5 GiB (1.6 GB)"]);
+        assert_eq!(calc.stack(), &[Value::Integer(1_610_612_736)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dump() -> Result<(), CalculatorError> {
+        let mut calc = Calculator::new()
+            .push(Value::Integer(0xdead_beef))
+            .apply(Operation::Dump)?;
+
+        assert_eq!(
+            calc.take_output(),
+            vec!["de ad be ef (big endian)", "ef be ad de (little endian)"]
+        );
+        assert_eq!(calc.stack(), &[Value::Integer(0xdead_beef)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain() {
+        let mut calc = Calculator::new();
+
+        calc.eval("hex 16").unwrap();
+        calc.eval("2 + 3 *").unwrap();
+        calc.eval("explain").unwrap();
+        assert_eq!(calc.take_output(), vec!["((0x10 + 0x2) * 0x3) = 0x36"]);
+
+        // The explanation itself is not remembered.
+        calc.eval("dec explain").unwrap();
+        assert_eq!(calc.take_output(), vec!["((16 + 2) * 3) = 54"]);
+
+        calc.eval("iota").unwrap();
+        assert!(calc.eval("explain").is_err());
+    }
+
+    #[test]
+    fn test_blocks() {
+        let mut calc = Calculator::new();
+
+        assert_eq!(
+            calc.eval("10 ( 3 4 hypot 2 ) 7").unwrap(),
+            &[
+                Value::Integer(10),
+                Value::Float(5.0),
+                Value::Integer(2),
+                Value::Integer(7)
+            ]
+        );
+        // The outer stack is out of reach.
+        assert!(calc.eval("( + )").is_err());
+        assert_eq!(calc.stack().len(), 4);
+
+        assert_eq!(
+            calc.eval("( 1 sto x ) rcl x").unwrap().last(),
+            Some(&Value::Integer(1))
+        );
+    }
+
+    #[test]
+    fn test_stack_ops() {
+        let mut calc = Calculator::new();
+
+        assert_eq!(
+            calc.eval("1 2 dup").unwrap(),
+            &[Value::Integer(1), Value::Integer(2), Value::Integer(2)]
+        );
+        assert_eq!(calc.eval("drop print").unwrap().len(), 2);
+        assert_eq!(calc.take_output(), vec!["2"]);
+        assert_eq!(calc.eval("clear").unwrap(), &[] as &[Value]);
+        assert!(calc.eval("print").is_err());
+    }
+
+    #[test]
+    fn test_stack_change() {
+        let ints = |v: &[i64]| v.iter().map(|&i| Value::Integer(i)).collect::<Vec<_>>();
+
+        assert_eq!(
+            stack_change(&ints(&[1, 2, 3]), &ints(&[1, 5])),
+            (&ints(&[2, 3])[..], &ints(&[5])[..])
+        );
+        assert_eq!(
+            stack_change(&ints(&[1]), &ints(&[1, 2])),
+            (&[][..], &ints(&[2])[..])
+        );
+    }
+
+    #[test]
+    fn test_input_base() {
+        let mut calc = Calculator::new();
+
+        assert_eq!(calc.eval("inbase 2").unwrap(), &[]);
+        assert_eq!(calc.eval("101 d5 +").unwrap(), &[Value::Integer(10)]);
+        assert!(calc.modes().contains(&Operation::SetInputBase(2)));
+        assert_eq!(
+            calc.eval("inbase 10 11").unwrap(),
+            &[Value::Integer(10), Value::Integer(11)]
+        );
+    }
+
+    #[test]
+    fn test_keep() {
+        let mut calc = Calculator::new();
+
+        assert_eq!(
+            calc.eval("keep on 3 ~").unwrap(),
+            &[Value::Integer(3), Value::Integer(-4)]
+        );
+        // Only unary functions keep their argument. The stack check
+        // knows about that.
+        assert_eq!(calc.eval("+ bytes").unwrap(), &[Value::Integer(-1)]);
+        assert_eq!(calc.eval("keep off ~").unwrap(), &[Value::Integer(0)]);
+        assert_eq!(
+            calc.eval("keep on 2 ~ +").unwrap(),
+            &[Value::Integer(0), Value::Integer(-1)]
+        );
+        assert!(calc.modes().contains(&Operation::SetKeep(true)));
+    }
+
+    #[test]
+    fn test_column() {
+        let mut calc = Calculator::new();
+
+        assert_eq!(
+            calc.clone().eval("1\t2").unwrap(),
+            &[Value::Integer(1), Value::Integer(2)]
+        );
+        assert_eq!(calc.eval("col 2").unwrap(), &[] as &[Value]);
+        assert_eq!(
+            calc.eval("Rent\t800\nFood\t250.5\n\t+").unwrap(),
+            &[Value::Float(1050.5)]
+        );
+        assert_eq!(calc.select_input("1 2"), "1 2");
+        assert!(calc.modes().contains(&Operation::SetColumn(2)));
+    }
+
+    #[test]
+    fn test_catalog() -> Result<(), CalculatorError> {
+        let mut calc = Calculator::new().apply(Operation::Catalog(Some(Category::Networks)))?;
+        assert_eq!(
+            calc.take_output(),
+            vec!["netmask broadcast hosts in-subnet?"]
+        );
+
+        let mut calc = calc.apply(Operation::Catalog(None))?;
+        let output = calc.take_output();
+        assert_eq!(output.len(), 13);
+        assert_eq!(output[1], "bitwise:     & | ^ ~ << >> dump decode");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unix_time() -> Result<(), CalculatorError> {
+        let berlin =
+            Calculator::new().apply(Operation::SetZone(Zone::Named(chrono_tz::Europe::Berlin)))?;
+        let mut calc = berlin
+            .push(Value::Integer(1_709_294_400))
+            .apply(Operation::FromUnix)?;
+
+        assert_eq!(calc.take_output(), vec!["2024-03-01 13:00:00 CET"]);
+        assert_eq!(calc.stack(), &[Value::Integer(1_709_294_400)]);
+
+        let mut calc = berlin.clone();
+        for v in &[2024, 7, 1, 12, 30, 0] {
+            calc.push_mut(Value::Integer(*v));
+        }
+        assert_eq!(
+            calc.apply(Operation::ToUnix)?.stack(),
+            &[Value::Integer(1_719_829_800)]
         );
 
+        // 2:30 doesn't exist on the day clocks go forward.
+        let mut calc = berlin;
+        for v in &[2024, 3, 31, 2, 30, 0] {
+            calc.push_mut(Value::Integer(*v));
+        }
+        assert!(calc.apply(Operation::ToUnix).is_err());
+
         Ok(())
     }
 }
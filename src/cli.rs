@@ -0,0 +1,637 @@
+//! # Command Line Handling
+//!
+//! This module turns command line arguments into [Options] that
+//! control how the calculator talks to the user.
+
+use std::time::Duration;
+
+use clac::parser::parse_values;
+use clac::types::{Limits, Locale, OverflowMode, Value};
+
+use crate::prompt::Template;
+
+/// How chatty the calculator is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verbosity {
+    /// Only print the final top-of-stack value.
+    Quiet,
+    /// Print a prompt with the current stack.
+    Normal,
+    /// Additionally trace every single operation on stderr.
+    Verbose,
+}
+
+/// How the final stack is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The same format as the prompt.
+    Plain,
+    /// Comma-separated values.
+    Csv,
+    /// Tab-separated values.
+    Tsv,
+    /// Shell variable assignments of the stack and the variables.
+    Shell,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = ArgsError;
+
+    fn from_str(s: &str) -> Result<Self, ArgsError> {
+        match s {
+            "plain" => Ok(OutputFormat::Plain),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            _ => Err(ArgsError::InvalidValue(
+                "--format".to_string(),
+                s.to_string(),
+            )),
+        }
+    }
+}
+
+/// Another calculator whose input language is understood.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compat {
+    /// The classic Unix desk calculator.
+    Dc,
+}
+
+impl std::str::FromStr for Compat {
+    type Err = ArgsError;
+
+    fn from_str(s: &str) -> Result<Self, ArgsError> {
+        match s {
+            "dc" => Ok(Compat::Dc),
+            _ => Err(ArgsError::InvalidValue(
+                "--compat".to_string(),
+                s.to_string(),
+            )),
+        }
+    }
+}
+
+/// How `:copy` sets the clipboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clipboard {
+    /// OSC 52 in SSH sessions, clipboard helpers otherwise.
+    Auto,
+    /// Only clipboard helpers like xclip.
+    System,
+    /// Only the OSC 52 escape sequence.
+    Osc52,
+}
+
+impl std::str::FromStr for Clipboard {
+    type Err = ArgsError;
+
+    fn from_str(s: &str) -> Result<Self, ArgsError> {
+        match s {
+            "auto" => Ok(Clipboard::Auto),
+            "system" => Ok(Clipboard::System),
+            "osc52" => Ok(Clipboard::Osc52),
+            _ => Err(ArgsError::InvalidValue(
+                "--clipboard".to_string(),
+                s.to_string(),
+            )),
+        }
+    }
+}
+
+/// Where the stack is kept between invocations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// One stack for everything.
+    Global,
+    /// One stack per working directory.
+    Directory,
+}
+
+impl std::str::FromStr for Scope {
+    type Err = ArgsError;
+
+    fn from_str(s: &str) -> Result<Self, ArgsError> {
+        match s {
+            "global" => Ok(Scope::Global),
+            "dir" => Ok(Scope::Directory),
+            _ => Err(ArgsError::InvalidValue(
+                "--persist".to_string(),
+                s.to_string(),
+            )),
+        }
+    }
+}
+
+/// Everything that can be configured on the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub verbosity: Verbosity,
+    pub help: bool,
+    /// Walk through the basics instead of calculating.
+    pub tutorial: bool,
+    /// Log every operation with its operands and duration.
+    pub trace: bool,
+
+    pub format: OutputFormat,
+    /// How decimal numbers are written in the output.
+    pub out_locale: Locale,
+    /// Overrides the field delimiter of the output format.
+    pub delimiter: Option<String>,
+
+    pub prompt: Template,
+    /// Highlight stack values changed by the last line.
+    pub highlight: bool,
+    /// How `:copy` sets the clipboard.
+    pub clipboard: Clipboard,
+
+    /// A constants file to load instead of the default one.
+    pub constants: Option<String>,
+
+    /// Values on the stack before anything is evaluated.
+    pub push: Vec<Value>,
+    /// Restore the stack of the last invocation and save it on exit.
+    pub persist: Option<Scope>,
+
+    /// Push all numbers from stdin before evaluating the expression.
+    pub stdin_data: bool,
+    /// Talk CBOR frames on stdin and stdout instead of text.
+    pub pipe_proto: bool,
+    /// Read input in the language of another calculator.
+    pub compat: Option<Compat>,
+    /// Only read this column of tab-separated input.
+    pub column: Option<usize>,
+    /// Cancel operations that run longer than this.
+    pub op_timeout: Option<Duration>,
+    /// How large values and the stack may grow.
+    pub limits: Limits,
+
+    /// A script file to evaluate again whenever it changes.
+    pub watch: Option<String>,
+    /// A script file to evaluate once as a test.
+    pub check: Option<String>,
+    /// Report failed lines of the script and carry on.
+    pub keep_going: bool,
+    /// A script file to measure the throughput with.
+    pub bench_input: Option<String>,
+
+    /// An expression to evaluate instead of reading lines from stdin.
+    pub expression: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            verbosity: Verbosity::Normal,
+            help: false,
+            tutorial: false,
+            trace: false,
+            format: OutputFormat::Plain,
+            out_locale: Locale::default(),
+            delimiter: None,
+            prompt: Template::default(),
+            highlight: true,
+            clipboard: Clipboard::Auto,
+            constants: None,
+            push: vec![],
+            persist: None,
+            stdin_data: false,
+            pipe_proto: false,
+            compat: None,
+            column: None,
+            op_timeout: None,
+            limits: Limits::default(),
+            watch: None,
+            check: None,
+            keep_going: false,
+            bench_input: None,
+            expression: None,
+        }
+    }
+}
+
+pub const USAGE: &str = "\
+Usage: clac [OPTIONS] [--] [EXPRESSION...]
+
+Without an expression, clac reads lines from stdin.
+
+Options:
+  -q, --quiet     Only print the final top-of-stack value
+  -v, --verbose   Trace each operation and its stack transition on stderr
+  --trace         Log each operation with popped and pushed values and
+                  its duration on stderr (or set RUST_LOG=clac=debug)
+  --format FMT    Print the final stack as plain, csv or tsv
+  --export-shell  Print the stack and variables as CLAC_TOS=...,
+                  CLAC_STACK_1=... lines for eval in shell scripts
+  --delimiter D   Separate output values by D (\\t and \\n are understood)
+  --out-locale L  Write decimal separators and digit groups like locale L,
+                  e.g. de, en_US or fr (input is not affected)
+  --prompt TMPL   Prompt template with {stack}, {stack:N} (top N values),
+                  {depth}, {radix} and {angle} placeholders
+  --no-highlight  Don't highlight stack values changed by the last line
+  --clipboard M   How :copy sets the clipboard: auto (OSC 52 over SSH,
+                  helpers like xclip otherwise), system or osc52
+  --constants F   Load constants from F instead of
+                  ~/.config/clac/constants.toml
+  --push 1,2,3    Push comma-separated numbers before anything else
+  --persist[=dir] Keep the stack between runs, globally or per directory
+  --stdin-data    Push all numbers from stdin, then evaluate the expression
+  --pipe-proto    Read length-prefixed CBOR requests from stdin and answer
+                  with the stack, for programs that embed clac
+  --compat dc     Understand dc commands (p n d r c k i o ...) instead, e.g.
+                  clac --compat dc '2 3+p'
+  --column N      Only read column N of tab-separated input, e.g. rows
+                  copied from a spreadsheet
+  --op-timeout S  Cancel operations that run longer than S seconds, e.g.
+                  runaway definitions (Ctrl-C cancels them, too)
+  --max-bits N    Fail when an integer grows beyond N bits (1048576)
+  --max-stack N   Fail when the stack holds more than N values (1000000)
+  --max-memory M  Fail when the stack takes up more than M MiB (256)
+  --watch FILE    Evaluate FILE and again whenever it changes
+  --check FILE    Evaluate FILE and exit with an error if anything fails,
+                  e.g. assert-eq
+  --keep-going    With --check, report failed lines, continue and
+                  summarize the failures at the end
+  --bench-input F Evaluate F line by line and report operations per second
+  --tutorial      Learn the basics of RPN step by step
+  -h, --help      Print this help
+";
+
+/// All errors during command line parsing are represented by this
+/// type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgsError {
+    UnknownOption(String),
+    MissingValue(String),
+    InvalidValue(String, String),
+}
+
+impl std::fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            ArgsError::UnknownOption(o) => write!(f, "Unknown option: {} (try --help)", o),
+            ArgsError::MissingValue(o) => write!(f, "Option {} needs a value", o),
+            ArgsError::InvalidValue(o, v) => write!(f, "Invalid value for {}: {}", o, v),
+        }
+    }
+}
+
+impl std::error::Error for ArgsError {}
+
+fn is_option(arg: &str) -> bool {
+    arg.starts_with('-') && arg.len() > 1 && arg.parse::<f64>().is_err()
+}
+
+/// Get the value of an option either from `--option=value` or from
+/// the next argument.
+fn option_value(
+    name: &str,
+    inline: Option<&str>,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<String, ArgsError> {
+    inline
+        .map(str::to_string)
+        .or_else(|| args.next())
+        .ok_or_else(|| ArgsError::MissingValue(name.to_string()))
+}
+
+/// Parse the value of a numeric option that must be positive.
+fn positive<T: std::str::FromStr + Default + PartialOrd>(
+    name: &str,
+    value: String,
+) -> Result<T, ArgsError> {
+    value
+        .parse()
+        .ok()
+        .filter(|n| *n > T::default())
+        .ok_or_else(|| ArgsError::InvalidValue(name.to_string(), value))
+}
+
+/// Replace the escape sequences that are awkward to type in a shell.
+fn unescape(s: &str) -> String {
+    s.replace("\\t", "\t").replace("\\n", "\n")
+}
+
+impl Options {
+    /// The separator between values in the final output.
+    pub fn output_delimiter(&self) -> &str {
+        match (&self.delimiter, self.format) {
+            (Some(d), _) => d,
+            (None, OutputFormat::Csv) => ",",
+            (None, OutputFormat::Tsv) => "\t",
+            (None, OutputFormat::Plain | OutputFormat::Shell) => " ",
+        }
+    }
+
+    /// Parse command line arguments (without the program name).
+    ///
+    /// Options are only recognized before the first part of the
+    /// expression. Negative numbers start the expression as well.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, ArgsError> {
+        let mut options = Options::default();
+        let mut args = args.into_iter().peekable();
+
+        while let Some(arg) = args.next_if(|a| is_option(a)) {
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value)),
+                _ => (arg.as_str(), None),
+            };
+
+            match name {
+                "-q" | "--quiet" => options.verbosity = Verbosity::Quiet,
+                "-v" | "--verbose" => options.verbosity = Verbosity::Verbose,
+                "-h" | "--help" => options.help = true,
+                "--tutorial" => options.tutorial = true,
+                "--stdin-data" => options.stdin_data = true,
+                "--pipe-proto" => options.pipe_proto = true,
+                "--no-highlight" => options.highlight = false,
+                "--keep-going" => options.keep_going = true,
+                "--trace" => options.trace = true,
+                "--prompt" => {
+                    options.prompt = option_value(name, inline, &mut args)?
+                        .parse()
+                        .map_err(|e| ArgsError::InvalidValue(name.to_string(), e))?
+                }
+                "--push" => {
+                    let values = option_value(name, inline, &mut args)?;
+
+                    options.push.extend(
+                        parse_values(&values.replace(',', " "), OverflowMode::Error)
+                            .map_err(|_| ArgsError::InvalidValue(name.to_string(), values))?,
+                    )
+                }
+                "--persist" => {
+                    options.persist = Some(inline.map_or(Ok(Scope::Global), str::parse)?)
+                }
+                "--constants" => options.constants = Some(option_value(name, inline, &mut args)?),
+                "--watch" => options.watch = Some(option_value(name, inline, &mut args)?),
+                "--check" => options.check = Some(option_value(name, inline, &mut args)?),
+                "--bench-input" => {
+                    options.bench_input = Some(option_value(name, inline, &mut args)?)
+                }
+                "--column" => {
+                    let column = option_value(name, inline, &mut args)?;
+
+                    options.column = Some(
+                        column
+                            .parse()
+                            .ok()
+                            .filter(|&c| c > 0)
+                            .ok_or_else(|| ArgsError::InvalidValue(name.to_string(), column))?,
+                    )
+                }
+                "--op-timeout" => {
+                    let seconds = option_value(name, inline, &mut args)?;
+
+                    options.op_timeout = Some(
+                        seconds
+                            .parse()
+                            .ok()
+                            .and_then(|s| Duration::try_from_secs_f64(s).ok())
+                            .filter(|t| !t.is_zero())
+                            .ok_or_else(|| ArgsError::InvalidValue(name.to_string(), seconds))?,
+                    )
+                }
+                "--max-bits" => {
+                    options.limits.max_bits =
+                        positive(name, option_value(name, inline, &mut args)?)?
+                }
+                "--max-stack" => {
+                    options.limits.max_values =
+                        positive(name, option_value(name, inline, &mut args)?)?
+                }
+                "--max-memory" => {
+                    let mib: usize = positive(name, option_value(name, inline, &mut args)?)?;
+
+                    options.limits.max_bytes = mib.saturating_mul(1 << 20)
+                }
+                "--clipboard" => {
+                    options.clipboard = option_value(name, inline, &mut args)?.parse()?
+                }
+                "--compat" => {
+                    options.compat = Some(option_value(name, inline, &mut args)?.parse()?)
+                }
+                "--out-locale" => {
+                    let locale = option_value(name, inline, &mut args)?;
+
+                    options.out_locale = locale
+                        .parse()
+                        .map_err(|_| ArgsError::InvalidValue(name.to_string(), locale))?
+                }
+                "--format" => options.format = option_value(name, inline, &mut args)?.parse()?,
+                "--export-shell" => options.format = OutputFormat::Shell,
+                "--delimiter" => {
+                    options.delimiter = Some(unescape(&option_value(name, inline, &mut args)?))
+                }
+                "--" => break,
+                _ => return Err(ArgsError::UnknownOption(arg)),
+            }
+        }
+
+        let expression: Vec<String> = args.collect();
+        if !expression.is_empty() {
+            options.expression = Some(expression.join(" "));
+        }
+
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from(args: &[&str]) -> Result<Options, ArgsError> {
+        Options::from_args(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn test_flags() {
+        assert_eq!(from(&[]), Ok(Options::default()));
+        assert_eq!(from(&["-q"]).unwrap().verbosity, Verbosity::Quiet);
+        assert_eq!(from(&["--verbose"]).unwrap().verbosity, Verbosity::Verbose);
+        assert!(!from(&["--no-highlight"]).unwrap().highlight);
+        assert!(from(&["--keep-going"]).unwrap().keep_going);
+        assert!(from(&["--trace"]).unwrap().trace);
+        assert!(from(&["--pipe-proto"]).unwrap().pipe_proto);
+        assert!(from(&["--tutorial"]).unwrap().tutorial);
+        assert_eq!(from(&["--compat", "dc"]).unwrap().compat, Some(Compat::Dc));
+        assert!(from(&["--compat=bc"]).is_err());
+        assert_eq!(
+            from(&["--clipboard=osc52"]).unwrap().clipboard,
+            Clipboard::Osc52
+        );
+        assert!(from(&["--clipboard", "x11"]).is_err());
+        assert_eq!(
+            from(&["--constants=c.toml"]).unwrap().constants,
+            Some("c.toml".to_string())
+        );
+        assert_eq!(
+            from(&["--watch", "calc.clac"]).unwrap().watch,
+            Some("calc.clac".to_string())
+        );
+        assert_eq!(
+            from(&["--check=test.clac"]).unwrap().check,
+            Some("test.clac".to_string())
+        );
+        assert_eq!(
+            from(&["--bench-input", "bench.clac"]).unwrap().bench_input,
+            Some("bench.clac".to_string())
+        );
+        assert_eq!(
+            from(&["-x"]),
+            Err(ArgsError::UnknownOption("-x".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        let csv = from(&["--format", "csv"]).unwrap();
+        assert_eq!(csv.format, OutputFormat::Csv);
+        assert_eq!(csv.output_delimiter(), ",");
+
+        assert_eq!(
+            from(&["--export-shell"]).unwrap().format,
+            OutputFormat::Shell
+        );
+
+        let tsv = from(&["--format=tsv", "1"]).unwrap();
+        assert_eq!(tsv.output_delimiter(), "\t");
+        assert_eq!(tsv.expression, Some("1".to_string()));
+
+        assert_eq!(
+            from(&["--format=csv", "--delimiter", "\\n"])
+                .unwrap()
+                .output_delimiter(),
+            "\n"
+        );
+
+        assert_eq!(
+            from(&["--format", "xml"]),
+            Err(ArgsError::InvalidValue(
+                "--format".to_string(),
+                "xml".to_string()
+            ))
+        );
+        assert_eq!(
+            from(&["--format"]),
+            Err(ArgsError::MissingValue("--format".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_out_locale() {
+        assert_eq!(
+            from(&["--out-locale", "de_DE.UTF-8"]).unwrap().out_locale,
+            Locale {
+                decimal: ',',
+                group: Some('.')
+            }
+        );
+        assert!(from(&["--out-locale=tlh"]).is_err());
+    }
+
+    #[test]
+    fn test_push() {
+        assert_eq!(
+            from(&["--push", "1,-2", "--push=0x10", "+"]).unwrap(),
+            Options {
+                push: vec![Value::Integer(1), Value::Integer(-2), Value::Integer(16)],
+                expression: Some("+".to_string()),
+                ..Options::default()
+            }
+        );
+        assert_eq!(
+            from(&["--push", "1,two"]),
+            Err(ArgsError::InvalidValue(
+                "--push".to_string(),
+                "1,two".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_persist() {
+        assert_eq!(from(&["--persist"]).unwrap().persist, Some(Scope::Global));
+        assert_eq!(
+            from(&["--persist=dir", "dir"]).unwrap(),
+            Options {
+                persist: Some(Scope::Directory),
+                expression: Some("dir".to_string()),
+                ..Options::default()
+            }
+        );
+        assert!(from(&["--persist=home"]).is_err());
+    }
+
+    #[test]
+    fn test_column() {
+        assert_eq!(from(&["--column", "2"]).unwrap().column, Some(2));
+        assert_eq!(
+            from(&["--column=0"]),
+            Err(ArgsError::InvalidValue(
+                "--column".to_string(),
+                "0".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_op_timeout() {
+        assert_eq!(
+            from(&["--op-timeout", "1.5"]).unwrap().op_timeout,
+            Some(Duration::from_millis(1500))
+        );
+        assert!(from(&["--op-timeout=0"]).is_err());
+        assert!(from(&["--op-timeout=-1"]).is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = from(&["--max-bits=64", "--max-stack", "3", "--max-memory=1"])
+            .unwrap()
+            .limits;
+
+        assert_eq!(
+            limits,
+            Limits {
+                max_bits: 64,
+                max_values: 3,
+                max_bytes: 1 << 20
+            }
+        );
+        assert_eq!(
+            from(&["--max-stack", "0"]),
+            Err(ArgsError::InvalidValue(
+                "--max-stack".to_string(),
+                "0".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_prompt() {
+        assert_eq!(
+            from(&["--prompt", "{depth}> "]).unwrap().prompt,
+            "{depth}> ".parse().unwrap()
+        );
+        assert!(from(&["--prompt={nope}"]).is_err());
+    }
+
+    #[test]
+    fn test_expression() {
+        assert_eq!(
+            from(&["-q", "1", "-2", "+"]).unwrap().expression,
+            Some("1 -2 +".to_string())
+        );
+
+        assert_eq!(
+            from(&["-2", "-q"]).unwrap().expression,
+            Some("-2 -q".to_string())
+        );
+
+        assert_eq!(
+            from(&["--", "-h"]).unwrap().expression,
+            Some("-h".to_string())
+        );
+    }
+}
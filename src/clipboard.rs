@@ -0,0 +1,151 @@
+//! # Clipboard Access
+//!
+//! Instead of linking against every windowing system, we talk to the
+//! clipboard via the usual command line helpers. On remote machines,
+//! where there are none, the terminal can set the clipboard with an
+//! OSC 52 escape sequence.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Commands that print the clipboard contents, in order of
+/// preference.
+const PASTE_COMMANDS: &[&[&str]] = &[
+    &["wl-paste", "--no-newline"],
+    &["xclip", "-selection", "clipboard", "-out"],
+    &["xsel", "--clipboard", "--output"],
+    &["pbpaste"],
+    &["powershell.exe", "-NoProfile", "-Command", "Get-Clipboard"],
+];
+
+/// Commands that set the clipboard contents from stdin, in order of
+/// preference.
+const COPY_COMMANDS: &[&[&str]] = &[
+    &["wl-copy"],
+    &["xclip", "-selection", "clipboard", "-in"],
+    &["xsel", "--clipboard", "--input"],
+    &["pbcopy"],
+    &["clip.exe"],
+];
+
+/// Read the clipboard contents as text.
+///
+/// Helpers that are not installed or fail (e.g. `wl-paste` outside of
+/// a Wayland session) are skipped.
+pub fn paste() -> Result<String, String> {
+    let mut error = "No clipboard helper (wl-paste, xclip, xsel, pbpaste) found".to_string();
+
+    for command in PASTE_COMMANDS {
+        match Command::new(command[0]).args(&command[1..]).output() {
+            Ok(output) if output.status.success() => {
+                return String::from_utf8(output.stdout)
+                    .map_err(|_| "Clipboard does not contain text".to_string())
+            }
+            Ok(output) => {
+                error = format!(
+                    "{}: {}",
+                    command[0],
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => error = format!("{}: {}", command[0], e),
+        }
+    }
+
+    Err(error)
+}
+
+/// Set the clipboard contents to `text` like [paste] reads them.
+pub fn copy(text: &str) -> Result<(), String> {
+    let mut error = "No clipboard helper (wl-copy, xclip, xsel, pbcopy) found".to_string();
+
+    for command in COPY_COMMANDS {
+        let mut child = match Command::new(command[0])
+            .args(&command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                error = format!("{}: {}", command[0], e);
+                continue;
+            }
+        };
+
+        // Dropping stdin closes it, so the helper sees the end.
+        let written = child
+            .stdin
+            .take()
+            .map_or(Ok(()), |mut stdin| stdin.write_all(text.as_bytes()));
+
+        match (written, child.wait_with_output()) {
+            (Ok(()), Ok(output)) if output.status.success() => return Ok(()),
+            (_, Ok(output)) => {
+                error = format!(
+                    "{}: {}",
+                    command[0],
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )
+            }
+            (_, Err(e)) => error = format!("{}: {}", command[0], e),
+        }
+    }
+
+    Err(error)
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode `bytes` as padded Base64.
+fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | u32::from(*b) << (16 - 8 * i));
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// The escape sequence that asks the terminal to set the clipboard
+/// to `text`. It passes through SSH, and tmux forwards it with
+/// `set-clipboard on`.
+pub fn osc52(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", base64(text.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0xff, 0xfe]), "//4=");
+    }
+
+    #[test]
+    fn test_osc52() {
+        assert_eq!(osc52("42"), "\x1b]52;c;NDI=\x07");
+    }
+}
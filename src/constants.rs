@@ -0,0 +1,75 @@
+//! # User Constants
+//!
+//! Project-specific constants, like clock frequencies or conversion
+//! factors, live in a TOML file with one `name = value` entry each.
+//! The entries may also be in a `[constants]` table. Values are
+//! numbers or strings with any literal clac understands, e.g.
+//! `"0x4000"` or `"4KiB"`.
+
+use std::path::PathBuf;
+
+use clac::parser::parse_values;
+use clac::types::{OverflowMode, Value};
+
+/// The constants file that is loaded if it exists.
+pub fn default_path() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config.join("clac").join("constants.toml"))
+}
+
+/// Parse the constants from the contents of a constants file.
+pub fn parse(contents: &str) -> Result<Vec<(String, Value)>, String> {
+    let mut table: toml::Table = contents
+        .parse()
+        .map_err(|e: toml::de::Error| e.to_string())?;
+
+    if let Some(toml::Value::Table(constants)) = table.remove("constants") {
+        table = constants;
+    }
+
+    table
+        .iter()
+        .map(|(name, value)| {
+            let error = || format!("Invalid constant: {} = {}", name, value);
+            let v = match value {
+                toml::Value::Integer(i) => Value::Integer(*i),
+                toml::Value::Float(f) => Value::Float(*f),
+                toml::Value::String(s) => match parse_values(s, OverflowMode::Error).as_deref() {
+                    Ok([v]) => v.clone(),
+                    _ => return Err(error()),
+                },
+                _ => return Err(error()),
+            };
+
+            Ok((name.clone(), v))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("f_cpu = 16_000_000\nc = 299792458.0\npage = \"4KiB\""),
+            Ok(vec![
+                ("f_cpu".to_string(), Value::Integer(16_000_000)),
+                ("c".to_string(), Value::Float(299_792_458.0)),
+                ("page".to_string(), Value::Integer(4096)),
+            ])
+        );
+        assert_eq!(
+            parse("[constants]\nbaud = 115200"),
+            Ok(vec![("baud".to_string(), Value::Integer(115_200))])
+        );
+
+        assert!(parse("x = \"1 2\"").is_err());
+        assert!(parse("x = true").is_err());
+        assert!(parse("x = ").is_err());
+    }
+}
@@ -0,0 +1,275 @@
+//! # dc Compatibility
+//!
+//! With `--compat dc`, input lines are written in the vocabulary of
+//! the classic Unix `dc` and translated to clac operations before
+//! evaluation. Commands don't need whitespace between them, so
+//! one-liners like `2 3+p` work as they do in `dc`.
+
+/// All errors during translation are represented by this type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DcError {
+    /// A command clac has no equivalent for.
+    Unsupported(char),
+    /// `k`, `i` and `o` only work right after a literal number.
+    NeedsLiteral(char),
+    /// A number is malformed or has digits outside the input radix.
+    InvalidNumber(String),
+    /// The radix is not supported by `i` or `o`.
+    InvalidRadix(String),
+}
+
+impl std::fmt::Display for DcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            DcError::Unsupported(c) => write!(f, "Unsupported dc command: {}", c),
+            DcError::NeedsLiteral(c) => write!(f, "dc command {} needs a literal number", c),
+            DcError::InvalidNumber(n) => write!(f, "Invalid number: {}", n),
+            DcError::InvalidRadix(r) => write!(f, "Unsupported radix: {}", r),
+        }
+    }
+}
+
+impl std::error::Error for DcError {}
+
+/// The largest scale that is honored. Floats don't have more
+/// significant decimal digits than this anyway.
+const MAX_SCALE: u32 = 15;
+
+/// The clac operations for a dc command, other than the ones that
+/// need a literal argument.
+fn command(c: char) -> Option<&'static str> {
+    Some(match c {
+        '+' => "+",
+        '-' => "-",
+        '*' => "*",
+        '/' => "/",
+        '%' => "divmod swap drop",
+        '~' => "divmod",
+        'd' => "dup",
+        'r' => "swap",
+        'c' => "clear",
+        'p' => "print",
+        'n' => "print drop",
+        _ => return None,
+    })
+}
+
+/// A piece of dc input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(String),
+    Command(char),
+}
+
+/// Split dc input into numbers and commands. Digits above 9 are
+/// upper case, so lower case letters are always commands.
+fn tokens(line: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = line.chars().peekable();
+    let is_digit = |c: &char| c.is_ascii_digit() || c.is_ascii_uppercase() || *c == '.';
+
+    while let Some(c) = chars.next() {
+        if is_digit(&c) || c == '_' {
+            let mut number = c.to_string();
+
+            while let Some(d) = chars.next_if(is_digit) {
+                number.push(d);
+            }
+            tokens.push(Token::Number(number));
+        } else if !c.is_whitespace() {
+            tokens.push(Token::Command(c));
+        }
+    }
+
+    tokens
+}
+
+/// Translates dc input to clac. The input radix set with `i` and the
+/// scale set with `k` carry over to later lines.
+pub struct Translator {
+    input_radix: u32,
+    /// The number of fractional digits division keeps.
+    scale: u32,
+}
+
+impl Default for Translator {
+    fn default() -> Self {
+        Translator {
+            input_radix: 10,
+            scale: 0,
+        }
+    }
+}
+
+impl Translator {
+    /// Convert a dc number in the input radix to a clac literal. dc
+    /// writes negative numbers with `_` and allows a bare `.5`.
+    fn number(&self, digits: &str) -> Result<String, DcError> {
+        let invalid = || DcError::InvalidNumber(digits.to_string());
+        let (sign, magnitude) = match digits.strip_prefix('_') {
+            Some(rest) => ("-", rest),
+            None => ("", digits),
+        };
+
+        let n = if self.input_radix == 10 {
+            let magnitude = match magnitude.strip_prefix('.') {
+                Some(fraction) => format!("0.{}", fraction),
+                None => magnitude.to_string(),
+            };
+
+            magnitude
+                .parse::<f64>()
+                .ok()
+                .filter(|_| !magnitude.ends_with('.'))
+                .map(|_| magnitude)
+        } else {
+            // Fractions in other radixes are rare enough to not
+            // bother.
+            i64::from_str_radix(magnitude, self.input_radix)
+                .ok()
+                .map(|n| n.to_string())
+        };
+
+        n.map(|n| format!("{}{}", sign, n)).ok_or_else(invalid)
+    }
+
+    /// Translate a line of dc commands to a line of clac operations.
+    pub fn translate(&mut self, line: &str) -> Result<String, DcError> {
+        let mut ops = vec![];
+        // A number is only emitted once it's clear that it isn't the
+        // argument of `k`, `i` or `o`.
+        let mut pending: Option<String> = None;
+
+        for token in tokens(line) {
+            let c = match token {
+                Token::Number(n) => {
+                    if let Some(previous) = pending.replace(n) {
+                        ops.push(self.number(&previous)?);
+                    }
+                    continue;
+                }
+                Token::Command(c) => c,
+            };
+
+            if let 'k' | 'i' | 'o' = c {
+                let digits = pending.take().ok_or(DcError::NeedsLiteral(c))?;
+                let n = self.number(&digits)?;
+                let radix = || DcError::InvalidRadix(n.clone());
+
+                match c {
+                    'k' => {
+                        self.scale = n
+                            .parse::<u32>()
+                            .map_err(|_| DcError::InvalidNumber(n.clone()))?
+                            .min(MAX_SCALE);
+                        // dc cuts off digits beyond the scale.
+                        ops.push("rounding toward-zero".to_string());
+                    }
+                    'i' => {
+                        self.input_radix = n
+                            .parse()
+                            .ok()
+                            .filter(|r| (2..=16).contains(r))
+                            .ok_or_else(radix)?
+                    }
+                    _ => ops.push(
+                        match n.as_str() {
+                            "2" => "bin",
+                            "10" => "dec",
+                            "16" => "hex",
+                            _ => return Err(radix()),
+                        }
+                        .to_string(),
+                    ),
+                }
+                continue;
+            }
+
+            if let Some(n) = pending.take() {
+                ops.push(self.number(&n)?);
+            }
+            if c == '/' && self.scale > 0 {
+                // Divide the dividend scaled up, so integers keep the
+                // digits up to the scale, and scale the quotient down.
+                let factor = 10i64.pow(self.scale);
+                ops.push(format!("swap {} * swap / int {}.0 /", factor, factor));
+                continue;
+            }
+            ops.push(command(c).ok_or(DcError::Unsupported(c))?.to_string());
+        }
+
+        if let Some(n) = pending {
+            ops.push(self.number(&n)?);
+        }
+
+        Ok(ops.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clac::calc::Calculator;
+    use clac::types::Value;
+
+    fn translate(line: &str) -> Result<String, DcError> {
+        Translator::default().translate(line)
+    }
+
+    /// The stack after evaluating a line of dc.
+    fn eval(line: &str) -> Vec<Value> {
+        Calculator::new()
+            .eval(&translate(line).unwrap())
+            .unwrap()
+            .to_vec()
+    }
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(translate("2 3+p"), Ok("2 3 + print".to_string()));
+        assert_eq!(translate("_5 .5*n"), Ok("-5 0.5 * print drop".to_string()));
+        assert_eq!(translate("7 2%f"), Err(DcError::Unsupported('f')));
+        assert_eq!(translate("16o255"), Ok("hex 255".to_string()));
+        assert_eq!(translate("k"), Err(DcError::NeedsLiteral('k')));
+        assert_eq!(translate("8o"), Err(DcError::InvalidRadix("8".to_string())));
+        assert_eq!(
+            translate("1.2.3"),
+            Err(DcError::InvalidNumber("1.2.3".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_scale() {
+        assert_eq!(eval("1 3/"), vec![Value::Integer(0)]);
+        assert_eq!(eval("2k 1 3/"), vec![Value::Float(0.33)]);
+        assert_eq!(eval("3k 2 3/"), vec![Value::Float(0.666)]);
+        assert_eq!(eval("2k _1 3/"), vec![Value::Float(-0.33)]);
+        assert_eq!(eval("2k 1.5 7/"), vec![Value::Float(0.21)]);
+        assert_eq!(eval("2k 6 3/"), vec![Value::Float(2.0)]);
+
+        let mut dc = Translator::default();
+        dc.translate("4k").unwrap();
+        assert_eq!(
+            Calculator::new()
+                .eval(&dc.translate("1 8/").unwrap())
+                .unwrap(),
+            &[Value::Float(0.125)]
+        );
+        assert_eq!(
+            translate("_1k"),
+            Err(DcError::InvalidNumber("-1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_input_radix() {
+        let mut dc = Translator::default();
+
+        assert_eq!(dc.translate("16i FF"), Ok("255".to_string()));
+        assert_eq!(
+            dc.translate("_10 G"),
+            Err(DcError::InvalidNumber("G".to_string()))
+        );
+        assert_eq!(dc.translate("Ai 10"), Ok("10".to_string()));
+    }
+}
@@ -0,0 +1,193 @@
+//! # Operator Overloading
+//!
+//! Binary operators like `+` look up what to do by the operation and
+//! the [Type]s of both operands. Plain numbers that have no entry fall
+//! back to the usual promotion to a common [Kind](crate::types::Kind).
+//! New value types register their overloads in [OVERLOADS] instead of
+//! adding cases to every operation.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::mem::Discriminant;
+
+use lazy_static::lazy_static;
+
+use crate::calc::{Calculator, CalculatorError};
+use crate::money::{self, Amount};
+use num_bigint::BigInt;
+
+use crate::types::{Operation, Type, Value};
+
+/// The implementation of an operator for one pair of operand types.
+pub type Overload = fn(&Calculator, Value, Value) -> Result<Value, CalculatorError>;
+
+/// Overloads by operation and operand types.
+#[derive(Default)]
+struct Table(HashMap<(Discriminant<Operation>, Type, Type), Overload>);
+
+/// The types of plain numbers.
+const NUMBERS: &[Type] = &[Type::Integer, Type::Big, Type::Float];
+
+impl Table {
+    /// Use `overload` for `op` with any of the types in `a` and `b`.
+    fn register(&mut self, op: Operation, a: &[Type], b: &[Type], overload: Overload) {
+        for &a in a {
+            for &b in b {
+                self.0.insert((std::mem::discriminant(&op), a, b), overload);
+            }
+        }
+    }
+}
+
+lazy_static! {
+    static ref OVERLOADS: Table = {
+        let mut table = Table::default();
+        let money = &[Type::Money];
+
+        // Mixing money and plain numbers is registered, so it fails
+        // with a currency mismatch instead of a missing overload.
+        for a in [money, NUMBERS] {
+            for b in [money, NUMBERS] {
+                if a != b {
+                    table.register(Operation::Add, a, b, add_money);
+                    table.register(Operation::Subtract, a, b, subtract_money);
+                    table.register(Operation::Multiply, a, b, multiply_money);
+                }
+            }
+        }
+        table.register(Operation::Add, money, money, add_money);
+        table.register(Operation::Subtract, money, money, subtract_money);
+        table.register(Operation::Divide, money, money, divide_money);
+        table.register(Operation::Divide, money, NUMBERS, divide_money);
+
+        table
+    };
+}
+
+/// The overload of `op` for operands of types `a` and `b`.
+pub fn lookup(op: &Operation, a: Type, b: Type) -> Option<Overload> {
+    OVERLOADS
+        .0
+        .get(&(std::mem::discriminant(op), a, b))
+        .copied()
+}
+
+/// Split both operands into amount and currency.
+fn amounts(a: Value, b: Value) -> Result<(Amount, Amount), CalculatorError> {
+    let amount = |v| money::amount(v).ok_or(CalculatorError::InvalidOperation);
+
+    Ok((amount(a)?, amount(b)?))
+}
+
+/// The common currency of two amounts that are added or subtracted.
+fn same_currency(a: Option<String>, b: Option<String>) -> Result<String, CalculatorError> {
+    match (a, b) {
+        (Some(a), Some(b)) if a == b => Ok(a),
+        (a, b) => Err(currency_mismatch(a, b)),
+    }
+}
+
+/// The error for combining amounts in different currencies. A missing
+/// currency is a plain number.
+fn currency_mismatch(a: Option<String>, b: Option<String>) -> CalculatorError {
+    let name = |c: Option<String>| c.unwrap_or_else(|| "a plain number".to_string());
+
+    CalculatorError::CurrencyMismatch(format!("{} and {}", name(a), name(b)))
+}
+
+fn add_money(_calc: &Calculator, a: Value, b: Value) -> Result<Value, CalculatorError> {
+    let ((a, ca), (b, cb)) = amounts(a, b)?;
+
+    Ok(Value::Money {
+        currency: same_currency(ca, cb)?,
+        amount: a.checked_add(b).ok_or(CalculatorError::InvalidOperation)?,
+    })
+}
+
+fn subtract_money(_calc: &Calculator, a: Value, b: Value) -> Result<Value, CalculatorError> {
+    let ((a, ca), (b, cb)) = amounts(a, b)?;
+
+    Ok(Value::Money {
+        currency: same_currency(ca, cb)?,
+        amount: a.checked_sub(b).ok_or(CalculatorError::InvalidOperation)?,
+    })
+}
+
+/// Scale money by a plain number.
+fn multiply_money(_calc: &Calculator, a: Value, b: Value) -> Result<Value, CalculatorError> {
+    let ((a, ca), (b, cb)) = amounts(a, b)?;
+
+    Ok(Value::Money {
+        currency: ca.or(cb).ok_or(CalculatorError::InvalidOperation)?,
+        amount: a.checked_mul(b).ok_or(CalculatorError::InvalidOperation)?,
+    })
+}
+
+/// Divide money by a plain number or by money in the same currency,
+/// which gives a plain ratio.
+fn divide_money(_calc: &Calculator, a: Value, b: Value) -> Result<Value, CalculatorError> {
+    let ((a, ca), (b, cb)) = amounts(a, b)?;
+    let quotient = a.checked_div(b).ok_or(CalculatorError::InvalidOperation)?;
+
+    match (ca, cb) {
+        (Some(ca), Some(cb)) if ca == cb => Ok(Value::Float(money::to_f64(&quotient))),
+        (Some(currency), None) => Ok(Value::Money {
+            currency,
+            amount: quotient,
+        }),
+        (ca, cb) => Err(currency_mismatch(ca, cb)),
+    }
+}
+
+/// Order two values. Money only compares to money in the same
+/// currency and integers are compared exactly, unless there is a
+/// float involved.
+pub fn compare(a: &Value, b: &Value) -> Result<Ordering, CalculatorError> {
+    if a.is_money() || b.is_money() {
+        let ((a, ca), (b, cb)) = amounts(a.clone(), b.clone())?;
+
+        same_currency(ca, cb)?;
+        Ok(a.cmp(&b))
+    } else if a.is_float() || b.is_float() {
+        f64::from(a.clone())
+            .partial_cmp(&f64::from(b.clone()))
+            .ok_or(CalculatorError::InvalidOperation)
+    } else {
+        Ok(BigInt::from(a.clone()).cmp(&BigInt::from(b.clone())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        assert!(lookup(&Operation::Add, Type::Money, Type::Integer).is_some());
+        assert!(lookup(&Operation::Divide, Type::Money, Type::Float).is_some());
+        assert!(lookup(&Operation::Divide, Type::Float, Type::Money).is_none());
+        assert!(lookup(&Operation::Multiply, Type::Money, Type::Money).is_none());
+        assert!(lookup(&Operation::Add, Type::Integer, Type::Float).is_none());
+    }
+
+    #[test]
+    fn test_compare() {
+        let money = |s| money::parse(s).unwrap();
+
+        assert_eq!(
+            compare(&Value::Integer(i64::MAX), &Value::Integer(i64::MAX - 1)).ok(),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            compare(&Value::Integer(2), &Value::Float(2.5)).ok(),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            compare(&money("1.50EUR"), &money("1.5EUR")).ok(),
+            Some(Ordering::Equal)
+        );
+        assert!(compare(&money("1EUR"), &money("1USD")).is_err());
+        assert!(compare(&money("1EUR"), &Value::Integer(1)).is_err());
+        assert!(compare(&Value::Float(f64::NAN), &Value::Integer(1)).is_err());
+    }
+}
@@ -0,0 +1,64 @@
+//! # Errors
+//!
+//! Evaluating a line can fail while parsing or while calculating.
+//! [Error] covers both and remembers where in the line things went
+//! wrong, so embedders only need to handle one type.
+
+use thiserror::Error;
+
+use crate::calc::CalculatorError;
+use crate::parser::{ParseError, Span};
+use crate::types::Operation;
+
+/// All errors of evaluating a line are represented by this type.
+#[derive(Debug, Clone, Error)]
+pub enum Error {
+    /// A token in `line` at `span` could not be parsed.
+    #[error("{error} ({})", position(line, span))]
+    Parse {
+        error: ParseError,
+        line: String,
+        span: Span,
+    },
+    /// The `operation` in `line` at `span` failed.
+    #[error("{error} in `{operation}` ({})", position(line, span))]
+    Calculator {
+        error: CalculatorError,
+        operation: Operation,
+        line: String,
+        span: Span,
+    },
+}
+
+/// Describe where `span` starts in `line`. Lines with several lines,
+/// like pasted blocks, also get the line number.
+fn position(line: &str, span: &Span) -> String {
+    let before = &line[..span.start];
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+
+    if line.trim_end().contains('\n') {
+        format!(
+            "line {}, column {}",
+            before.matches('\n').count() + 1,
+            column
+        )
+    } else {
+        format!("column {}", column)
+    }
+}
+
+impl Error {
+    /// The line that failed to evaluate.
+    pub fn line(&self) -> &str {
+        match self {
+            Error::Parse { line, .. } | Error::Calculator { line, .. } => line,
+        }
+    }
+
+    /// Where in [line](Error::line) the error happened.
+    pub fn span(&self) -> Span {
+        match self {
+            Error::Parse { span, .. } | Error::Calculator { span, .. } => span.clone(),
+        }
+    }
+}
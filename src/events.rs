@@ -0,0 +1,157 @@
+//! # Event Loop
+//!
+//! Reading a line blocks until the user presses enter, so the line
+//! editor lives on its own thread. It and all other background tasks,
+//! like the file watcher, send [Event]s to one channel that the main
+//! loop waits on. The calculator itself stays on the main thread.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::input::Input;
+
+/// Something the main loop has to react to.
+#[derive(Debug)]
+pub enum Event {
+    /// A line of input.
+    Line(String),
+    /// The end of input.
+    Eof,
+    /// Reading input failed.
+    InputError(io::Error),
+    /// A watched file was modified.
+    Changed(PathBuf),
+}
+
+/// What the input thread needs to read the next line.
+struct Request {
+    prompt: String,
+    variables: Vec<String>,
+    snapshots: Vec<String>,
+}
+
+/// The channel all events arrive on.
+pub struct Events {
+    sender: Sender<Event>,
+    receiver: Receiver<Event>,
+    /// Asks the input thread for another line, once it is started.
+    requests: Option<Sender<Request>>,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        let (sender, receiver) = channel();
+
+        Events {
+            sender,
+            receiver,
+            requests: None,
+        }
+    }
+
+    /// Start reading lines from stdin on a separate thread. Lines are
+    /// only read when asked for with [Events::read_line].
+    pub fn start_input(&mut self) {
+        let (requests, incoming) = channel::<Request>();
+        let events = self.sender.clone();
+
+        // The line editor locks stdin, which can't move between
+        // threads, so the thread creates it.
+        thread::spawn(move || {
+            let mut input = Input::new();
+
+            for request in incoming {
+                input.set_variables(request.variables);
+                input.set_snapshots(request.snapshots);
+
+                let event = match input.read_line(&request.prompt) {
+                    Ok(Some(line)) => Event::Line(line),
+                    Ok(None) => Event::Eof,
+                    Err(e) => Event::InputError(e),
+                };
+                let done = !matches!(event, Event::Line(_));
+
+                if events.send(event).is_err() || done {
+                    break;
+                }
+            }
+        });
+
+        self.requests = Some(requests);
+    }
+
+    /// Ask the input thread for the next line. The line arrives as
+    /// [Event::Line].
+    pub fn read_line(&self, prompt: String, variables: Vec<String>, snapshots: Vec<String>) {
+        let request = Request {
+            prompt,
+            variables,
+            snapshots,
+        };
+
+        // Without an input thread, the request is for nothing.
+        if let Some(requests) = &self.requests {
+            let _ = requests.send(request);
+        }
+    }
+
+    /// Poll `path` for changes on a separate thread. The first check
+    /// counts as a change, if the file exists.
+    pub fn watch(&self, path: &Path, interval: Duration) {
+        let events = self.sender.clone();
+        let path = path.to_path_buf();
+
+        thread::spawn(move || {
+            let mut last = None;
+
+            loop {
+                // Editors may briefly remove the file while saving it.
+                let current = modified(&path).ok();
+
+                if current.is_some() && current != last {
+                    last = current;
+
+                    if events.send(Event::Changed(path.clone())).is_err() {
+                        break;
+                    }
+                }
+
+                thread::sleep(interval);
+            }
+        });
+    }
+
+    /// Wait for the next event.
+    pub fn next(&self) -> Event {
+        // We hold a sender ourselves, so the channel never closes.
+        self.receiver.recv().expect("event channel closed")
+    }
+}
+
+fn modified(path: &Path) -> io::Result<SystemTime> {
+    std::fs::metadata(path)?.modified()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch() {
+        let path = std::env::temp_dir().join(format!("clac-events-{}", std::process::id()));
+        std::fs::write(&path, "1").unwrap();
+
+        let events = Events::new();
+        events.watch(&path, Duration::from_millis(10));
+
+        match events.next() {
+            Event::Changed(changed) => assert_eq!(changed, path),
+            e => panic!("unexpected event: {:?}", e),
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+//! # Infix Explanations
+//!
+//! `explain` replays a line on a stack of expressions instead of
+//! numbers, which turns `1 2 + 3 *` back into `((1 + 2) * 3)`. Only
+//! operations with a fixed stack effect can be replayed this way.
+
+use crate::types::{Operation, Value};
+
+/// The infix symbol of a binary operator.
+fn operator(op: &Operation) -> Option<&'static str> {
+    match op {
+        Operation::Add => Some("+"),
+        Operation::Subtract => Some("-"),
+        Operation::Multiply => Some("*"),
+        Operation::Divide => Some("/"),
+        Operation::BitAnd => Some("&"),
+        Operation::BitOr => Some("|"),
+        Operation::BitXor => Some("^"),
+        Operation::LeftShift => Some("<<"),
+        Operation::RightShift => Some(">>"),
+        Operation::Equal => Some("=="),
+        Operation::NotEqual => Some("!="),
+        _ => None,
+    }
+}
+
+/// Replay `ops` on a stack of expressions. Pushed values are
+/// formatted with `literal`. Returns the operation that can't be
+/// replayed on failure.
+pub fn infix(
+    mut stack: Vec<String>,
+    ops: &[Operation],
+    literal: impl Fn(&Value) -> String,
+) -> Result<Vec<String>, Operation> {
+    for op in ops {
+        let arity = match op {
+            Operation::Add
+            | Operation::Subtract
+            | Operation::Multiply
+            | Operation::Divide
+            | Operation::BitAnd
+            | Operation::BitOr
+            | Operation::BitXor
+            | Operation::LeftShift
+            | Operation::RightShift
+            | Operation::Equal
+            | Operation::NotEqual
+            | Operation::Swap
+            | Operation::Hypot
+            | Operation::Atan2
+            | Operation::LogBase
+            | Operation::Combinations
+            | Operation::Permutations => 2,
+            Operation::BitNot
+            | Operation::Round2
+            | Operation::ToInteger
+            | Operation::Exp
+            | Operation::Exp2
+            | Operation::ExpM1
+            | Operation::Ln1p
+            | Operation::Ulp
+            | Operation::NextUp
+            | Operation::NextDown
+            | Operation::IntSqrt
+            | Operation::IsPowerOfTwo
+            | Operation::IntLog2
+            | Operation::ToHms
+            | Operation::ToDms
+            | Operation::FromDms
+            | Operation::Netmask
+            | Operation::Hosts
+            | Operation::Dup
+            | Operation::Drop
+            | Operation::Store(_) => 1,
+            Operation::Clamp | Operation::Between => 3,
+            _ => 0,
+        };
+
+        if stack.len() < arity {
+            return Err(op.clone());
+        }
+        let args = stack.split_off(stack.len() - arity);
+
+        match (op, args.as_slice()) {
+            (Operation::Push(v), _) => stack.push(literal(v)),
+            (Operation::PushEnv(_), _)
+            | (Operation::PushConstant(_), _)
+            | (Operation::RecallResult(_), _) => stack.push(op.to_string()),
+            (Operation::Recall(name), _) => stack.push(name.clone()),
+            (Operation::Swap, [a, b]) => stack.extend([b.clone(), a.clone()]),
+            (Operation::Dup, [a]) => stack.extend([a.clone(), a.clone()]),
+            (Operation::Store(_), _) | (Operation::Drop, _) => {}
+            (Operation::BitNot, [a]) => stack.push(format!("~{}", a)),
+            (_, [a, b]) => stack.push(match operator(op) {
+                Some(symbol) => format!("({} {} {})", a, symbol, b),
+                None => format!("{}({}, {})", op, a, b),
+            }),
+            (_, [a]) => stack.push(format!("{}({})", op, a)),
+            (_, [a, b, c]) => stack.push(format!("{}({}, {}, {})", op, a, b, c)),
+            // Settings and displays don't touch the stack.
+            (
+                Operation::SetRadix(_)
+                | Operation::SetInputBase(_)
+                | Operation::SetPrecision(_)
+                | Operation::SetDigitGroup(_)
+                | Operation::SetPadding(_)
+                | Operation::SetRounding(_)
+                | Operation::SetOverflow(_)
+                | Operation::SetDemotion(_)
+                | Operation::SetFractionDisplay(_)
+                | Operation::SetSiDisplay(_)
+                | Operation::SetTypeDisplay(_)
+                | Operation::SetKeep(_)
+                | Operation::SetColumn(_)
+                | Operation::SetTolerance(_)
+                | Operation::SetAngleMode(_)
+                | Operation::SetZone(_)
+                | Operation::ShowBytes
+                | Operation::Print
+                | Operation::Dump
+                | Operation::ShowPermissions
+                | Operation::FromUnix
+                | Operation::Plot
+                | Operation::Sparkline
+                | Operation::ListVariables
+                | Operation::Define(_, _)
+                | Operation::Snapshot(_)
+                | Operation::Diff(_),
+                _,
+            ) => {}
+            _ => return Err(op.clone()),
+        }
+    }
+
+    Ok(stack)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn explain(stack: &[&str], line: &str) -> Result<Vec<String>, Operation> {
+        infix(
+            stack.iter().map(|s| s.to_string()).collect(),
+            &parse(line).unwrap(),
+            |v| v.to_string(),
+        )
+    }
+
+    #[test]
+    fn test_infix() {
+        assert_eq!(explain(&[], "1 2 + 3 *"), Ok(vec!["((1 + 2) * 3)".into()]));
+        assert_eq!(
+            explain(&["x"], "2 swap - hex ~ 3 4 hypot"),
+            Ok(vec!["~(2 - x)".into(), "hypot(3, 4)".into()])
+        );
+        assert_eq!(
+            explain(&["1"], "rcl r 2 * sto a $1 round2"),
+            Ok(vec!["1".into(), "round2($1)".into()])
+        );
+        assert_eq!(explain(&["1"], "iota"), Err(Operation::Iota));
+    }
+}
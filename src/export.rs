@@ -0,0 +1,81 @@
+//! # Shell Export
+//!
+//! `--export-shell` prints the stack and the variables as shell
+//! assignments, so scripts can `eval` the output and use more than
+//! the top of the stack:
+//!
+//! ```sh
+//! eval "$(clac --export-shell '7 2 divmod')"
+//! echo "$CLAC_STACK_2 remainder $CLAC_TOS"
+//! ```
+
+use clac::calc::Calculator;
+
+/// Quote `s` for the shell, unless it is harmless as it is.
+fn quote(s: &str) -> String {
+    let harmless = |c: char| c.is_ascii_alphanumeric() || "+-.,_:/".contains(c);
+
+    if !s.is_empty() && s.chars().all(harmless) {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+/// One assignment per line. `CLAC_STACK_1` is the top of the stack,
+/// which is also `CLAC_TOS`, if there is one. Variables become
+/// `CLAC_VAR_name`.
+pub fn format(calc: &Calculator) -> String {
+    let mut lines = vec![format!("CLAC_DEPTH={}", calc.stack().len())];
+
+    if let Some(top) = calc.top() {
+        lines.push(format!("CLAC_TOS={}", quote(&calc.format_value(&top))));
+    }
+    for (level, v) in calc.stack().iter().rev().enumerate() {
+        lines.push(format!(
+            "CLAC_STACK_{}={}",
+            level + 1,
+            quote(&calc.format_value(v))
+        ));
+    }
+    for (name, v) in calc.variables() {
+        lines.push(format!(
+            "CLAC_VAR_{}={}",
+            name,
+            quote(&calc.format_value(v))
+        ));
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("-1.5e-7"), "-1.5e-7");
+        assert_eq!(quote("12.3µ"), "'12.3µ'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+        assert_eq!(quote(""), "''");
+    }
+
+    #[test]
+    fn test_format() {
+        let mut calc = Calculator::new();
+
+        assert_eq!(format(&calc), "CLAC_DEPTH=0");
+
+        calc.eval("42 sto answer 7 2 divmod 1EUR").unwrap();
+        assert_eq!(
+            format(&calc),
+            "CLAC_DEPTH=3\n\
+             CLAC_TOS=1.00EUR\n\
+             CLAC_STACK_1=1.00EUR\n\
+             CLAC_STACK_2=1\n\
+             CLAC_STACK_3=3\n\
+             CLAC_VAR_answer=42"
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//! # Time Value of Money
+//!
+//! Like on the financial calculators of HP, five registers describe a
+//! loan or an investment: the number of periods `n`, the interest rate
+//! per period `i` in percent, the present value `pv`, the payment per
+//! period `pmt` and the future value `fv`. Any one of them follows from
+//! the other four. Money paid is negative, money received positive,
+//! and payments are due at the end of each period.
+
+use crate::numeric;
+use crate::types::TvmRegister;
+
+/// Why a register can't be solved for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TvmError {
+    /// One of the other registers has no value yet.
+    Unset(TvmRegister),
+    /// No value satisfies the other registers.
+    NoSolution,
+}
+
+impl std::fmt::Display for TvmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            TvmError::Unset(register) => write!(f, "{} is not set", register),
+            TvmError::NoSolution => write!(f, "no solution"),
+        }
+    }
+}
+
+impl std::error::Error for TvmError {}
+
+const REGISTERS: [TvmRegister; 5] = [
+    TvmRegister::N,
+    TvmRegister::I,
+    TvmRegister::Pv,
+    TvmRegister::Pmt,
+    TvmRegister::Fv,
+];
+
+/// The TVM registers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tvm {
+    /// Indexed by [TvmRegister].
+    values: [Option<f64>; 5],
+}
+
+/// The growth of one unit over `n` periods at `i` percent, and the
+/// value of `n` payments of one unit at the end. The latter is precise
+/// for rates near zero.
+fn factors(n: f64, i: f64) -> (f64, f64) {
+    let rate = i / 100.0;
+    let growth = n * rate.ln_1p();
+
+    if rate == 0.0 {
+        (1.0, n)
+    } else {
+        (growth.exp(), growth.exp_m1() / rate)
+    }
+}
+
+/// What is left of the cash flows after `n` periods. It is zero when
+/// the registers agree.
+fn balance(n: f64, i: f64, pv: f64, pmt: f64, fv: f64) -> f64 {
+    let (growth, annuity) = factors(n, i);
+
+    pv * growth + pmt * annuity + fv
+}
+
+impl Tvm {
+    pub fn set(&mut self, register: TvmRegister, v: f64) {
+        self.values[register as usize] = Some(v);
+    }
+
+    fn get(&self, register: TvmRegister) -> Result<f64, TvmError> {
+        self.values[register as usize].ok_or(TvmError::Unset(register))
+    }
+
+    /// Compute `register` from the other four and store it.
+    pub fn solve(&mut self, register: TvmRegister) -> Result<f64, TvmError> {
+        let [n, i, pv, pmt, fv] =
+            REGISTERS.map(|r| if r == register { Ok(0.0) } else { self.get(r) });
+        let (n, i, pv, pmt, fv) = (n?, i?, pv?, pmt?, fv?);
+        let (growth, annuity) = factors(n, i);
+
+        let v = match register {
+            TvmRegister::N if i == 0.0 => -(pv + fv) / pmt,
+            TvmRegister::N => {
+                let rate = i / 100.0;
+
+                ((pmt - fv * rate) / (pmt + pv * rate)).ln() / rate.ln_1p()
+            }
+            TvmRegister::I => {
+                numeric::solve(|i| Ok::<_, TvmError>(balance(n, i, pv, pmt, fv)), 1.0, 0.0)?
+                    .ok_or(TvmError::NoSolution)?
+            }
+            TvmRegister::Pv => -(pmt * annuity + fv) / growth,
+            TvmRegister::Pmt => -(pv * growth + fv) / annuity,
+            TvmRegister::Fv => -(pv * growth + pmt * annuity),
+        };
+
+        if !v.is_finite() {
+            return Err(TvmError::NoSolution);
+        }
+
+        self.set(register, v);
+        Ok(v)
+    }
+}
+
+/// The net present value of cash flows, one per period starting now,
+/// at `i` percent per period.
+pub fn npv(i: f64, flows: &[f64]) -> f64 {
+    let discount = 1.0 + i / 100.0;
+
+    flows
+        .iter()
+        .rev()
+        .fold(0.0, |sum, flow| sum / discount + flow)
+}
+
+/// The interest rate in percent at which the net present value of the
+/// cash flows is zero, if there is one. Rates of -100% and below
+/// make no sense, but the search may stray there.
+pub fn irr(flows: &[f64]) -> Option<f64> {
+    numeric::solve(|i| Ok::<_, ()>(npv(i, flows)), 10.0, 0.0)
+        .ok()
+        .flatten()
+        .filter(|&i| i > -100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn near(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9 * b.abs().max(1.0)
+    }
+
+    /// A 30 year mortgage of 200000 at 6% per year, paid monthly.
+    fn mortgage() -> Tvm {
+        let mut tvm = Tvm::default();
+
+        tvm.set(TvmRegister::N, 360.0);
+        tvm.set(TvmRegister::I, 0.5);
+        tvm.set(TvmRegister::Pv, 200000.0);
+        tvm.set(TvmRegister::Fv, 0.0);
+        tvm
+    }
+
+    #[test]
+    fn test_solve() {
+        let mut tvm = mortgage();
+        let pmt = tvm.solve(TvmRegister::Pmt).unwrap();
+
+        assert!(near(pmt, -1199.1010503055138), "{}", pmt);
+
+        for register in REGISTERS {
+            let expected = tvm.get(register).unwrap();
+            let actual = tvm.solve(register).unwrap();
+
+            assert!(near(actual, expected), "{}: {}", register, actual);
+        }
+    }
+
+    #[test]
+    fn test_zero_interest() {
+        let mut tvm = Tvm::default();
+
+        tvm.set(TvmRegister::I, 0.0);
+        tvm.set(TvmRegister::Pv, 1200.0);
+        tvm.set(TvmRegister::Fv, 0.0);
+        tvm.set(TvmRegister::Pmt, -100.0);
+        assert_eq!(tvm.solve(TvmRegister::N), Ok(12.0));
+        assert_eq!(tvm.solve(TvmRegister::Pmt), Ok(-100.0));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut tvm = Tvm::default();
+
+        tvm.set(TvmRegister::N, 10.0);
+        assert_eq!(
+            tvm.solve(TvmRegister::Fv),
+            Err(TvmError::Unset(TvmRegister::I))
+        );
+
+        // Nobody lends money for nothing in return.
+        let mut tvm = mortgage();
+        tvm.set(TvmRegister::Pmt, 0.0);
+        assert_eq!(tvm.solve(TvmRegister::I), Err(TvmError::NoSolution));
+    }
+
+    #[test]
+    fn test_npv() {
+        let flows = [-1000.0, 300.0, 400.0, 500.0];
+
+        assert!(near(npv(0.0, &flows), 200.0));
+        assert!(near(
+            npv(10.0, &flows),
+            -1000.0 + 300.0 / 1.1 + 400.0 / 1.21 + 500.0 / 1.331
+        ));
+
+        let irr = irr(&flows).unwrap();
+        assert!(npv(irr, &flows).abs() < 1e-9, "{}", irr);
+        assert_eq!(super::irr(&[100.0, 200.0]), None);
+    }
+}
@@ -0,0 +1,460 @@
+//! # Output Formatting
+//!
+//! Helpers to render values for humans. The calculator decides which
+//! of them to use based on its display settings.
+
+use std::convert::TryFrom;
+
+use crate::types::Locale;
+
+/// Format a float with the given number of significant digits.
+///
+/// This works like `%g` in C: small and large magnitudes switch to
+/// scientific notation and trailing zeros are dropped.
+pub fn float_significant(f: f64, digits: usize) -> String {
+    if !f.is_finite() || digits == 0 {
+        // Shortest representation that reads back exactly.
+        return format!("{}", f);
+    }
+
+    // Let the standard library do the rounding, then decide on the
+    // layout based on the resulting exponent.
+    let scientific = format!("{:.*e}", digits - 1, f);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+
+    if exponent < -4 || exponent >= digits as i32 {
+        format!("{}e{}", trim_zeros(mantissa), exponent)
+    } else {
+        let decimals = (digits as i32 - 1 - exponent) as usize;
+
+        trim_zeros(&format!("{:.*}", decimals, f)).to_string()
+    }
+}
+
+/// Format a float with an SI prefix, so the exponent is a multiple of
+/// three, e.g. `12.3k` or `4.7µ`. Digits are significant digits of
+/// the mantissa like for [float_significant].
+pub fn si_prefix(f: f64, digits: usize) -> String {
+    const PREFIXES: [&str; 17] = [
+        "y", "z", "a", "f", "p", "n", "µ", "m", "", "k", "M", "G", "T", "P", "E", "Z", "Y",
+    ];
+
+    if !f.is_finite() || f == 0.0 {
+        return float_significant(f, digits);
+    }
+
+    let mut exponent = (f.abs().log10() / 3.0).floor() as i32 * 3;
+    let mantissa = |exponent: i32| float_significant(f / 10f64.powi(exponent), digits);
+
+    // Rounding the mantissa may carry over into the next prefix.
+    if mantissa(exponent)
+        .parse::<f64>()
+        .is_ok_and(|m| m.abs() >= 1000.0)
+    {
+        exponent += 3;
+    }
+
+    match usize::try_from(exponent / 3 + 8)
+        .ok()
+        .and_then(|i| PREFIXES.get(i))
+    {
+        Some(prefix) => format!("{}{}", mantissa(exponent), prefix),
+        None => float_significant(f, digits),
+    }
+}
+
+/// Separate groups of `size` digits with `_`, counting from the
+/// right. A size of 0 leaves the digits alone.
+pub fn group_digits(digits: &str, size: usize) -> String {
+    if size == 0 {
+        return digits.to_string();
+    }
+
+    let chars: Vec<char> = digits.chars().collect();
+    let groups: Vec<String> = chars
+        .rchunks(size)
+        .rev()
+        .map(|g| g.iter().collect())
+        .collect();
+
+    groups.join("_")
+}
+
+/// Write the number at the start of `s`, like `-1234.5` in
+/// `-1234.5EUR`, with the decimal separator and digit grouping of
+/// `locale`. Whatever follows the number is kept.
+pub fn localize(s: &str, locale: Locale) -> String {
+    if locale == Locale::default() {
+        return s.to_string();
+    }
+
+    let (sign, rest) = match s.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", s),
+    };
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let (integer, rest) = rest.split_at(digits);
+
+    let integer = match locale.group {
+        Some(group) => group_digits(integer, 3).replace('_', &group.to_string()),
+        None => integer.to_string(),
+    };
+    let rest = match rest.strip_prefix('.') {
+        Some(fraction) => format!("{}{}", locale.decimal, fraction),
+        None => rest.to_string(),
+    };
+
+    format!("{}{}{}", sign, integer, rest)
+}
+
+/// Render the lower 12 bits of a file mode like `ls -l`, e.g.
+/// `rwxr-x---` for 0o750.
+pub fn permissions(mode: i64) -> String {
+    let bit = |b: i64, c: char| if mode & b != 0 { c } else { '-' };
+    // Setuid, setgid and sticky bits replace the x of their class.
+    let special = |x: i64, s: i64, set: char| match (mode & x != 0, mode & s != 0) {
+        (true, true) => set,
+        (false, true) => set.to_ascii_uppercase(),
+        (true, false) => 'x',
+        (false, false) => '-',
+    };
+
+    [
+        bit(0o400, 'r'),
+        bit(0o200, 'w'),
+        special(0o100, 0o4000, 's'),
+        bit(0o040, 'r'),
+        bit(0o020, 'w'),
+        special(0o010, 0o2000, 's'),
+        bit(0o004, 'r'),
+        bit(0o002, 'w'),
+        special(0o001, 0o1000, 't'),
+    ]
+    .iter()
+    .collect()
+}
+
+/// Render a byte count with the largest unit that keeps it at or
+/// above 1, e.g. `1.5 GiB` for `base` 1024 or `1.6 GB` for 1000.
+pub fn byte_size(bytes: f64, base: f64) -> String {
+    const IEC: [&str; 7] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    const SI: [&str; 7] = ["B", "kB", "MB", "GB", "TB", "PB", "EB"];
+
+    let units = if base == 1024.0 { IEC } else { SI };
+    let mut size = bytes;
+    let mut unit = 0;
+
+    while size.abs() >= base && unit < units.len() - 1 {
+        size /= base;
+        unit += 1;
+    }
+
+    format!("{} {}", trim_zeros(&format!("{:.1}", size)), units[unit])
+}
+
+/// The bytes of an integer in big endian order without leading zero
+/// bytes. Negative integers keep all eight bytes of their two's
+/// complement.
+pub fn significant_bytes(i: i64) -> Vec<u8> {
+    let bytes = i.to_be_bytes();
+    let skip = if i < 0 {
+        0
+    } else {
+        bytes.iter().take_while(|&&b| b == 0).count().min(7)
+    };
+
+    bytes[skip..].to_vec()
+}
+
+/// Format bytes as space-separated hex pairs, e.g. `de ad be ef`.
+pub fn hex_bytes<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> String {
+    bytes
+        .into_iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Approximate a float by a fraction `(numerator, denominator)` with a
+/// denominator of at most `max_denominator`.
+///
+/// This uses the convergents of the continued fraction of `f` and
+/// stops as soon as one of them is exact. Returns `None` for values
+/// that don't fit or can't be approximated within a relative error
+/// of 1e-9.
+pub fn fraction(f: f64, max_denominator: i64) -> Option<(i64, i64)> {
+    if !f.is_finite() || f.abs() >= i64::MAX as f64 {
+        return None;
+    }
+
+    // The two previous convergents h/k.
+    let (mut h0, mut h1) = (0i64, 1i64);
+    let (mut k0, mut k1) = (1i64, 0i64);
+    let mut x = f.abs();
+
+    loop {
+        let a = x.floor();
+        let (h2, k2) = match (
+            (a as i64).checked_mul(h1).and_then(|h| h.checked_add(h0)),
+            (a as i64).checked_mul(k1).and_then(|k| k.checked_add(k0)),
+        ) {
+            (Some(h), Some(k)) if k <= max_denominator => (h, k),
+            _ => break,
+        };
+
+        h0 = h1;
+        h1 = h2;
+        k0 = k1;
+        k1 = k2;
+
+        if h1 as f64 / k1 as f64 == f.abs() || x == a {
+            break;
+        }
+        x = 1.0 / (x - a);
+    }
+
+    if (h1 as f64 / k1 as f64 - f.abs()).abs() > f.abs() * 1e-9 {
+        return None;
+    }
+
+    let numerator = if f < 0.0 { -h1 } else { h1 };
+
+    Some((numerator, k1))
+}
+
+/// Render labeled values as a horizontal bar chart. The longest bar
+/// is `width` characters long. Bars of negative values are drawn with
+/// `-` instead of `#`.
+pub fn bar_chart(labels: &[String], values: &[f64], width: usize) -> Vec<String> {
+    let label_width = labels.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    let max = values.iter().fold(0.0f64, |m, v| m.max(v.abs()));
+
+    labels
+        .iter()
+        .zip(values)
+        .map(|(label, &v)| {
+            let len = if max > 0.0 {
+                (v.abs() / max * width as f64).round() as usize
+            } else {
+                0
+            };
+            let bar = if v < 0.0 { "-" } else { "#" }.repeat(len);
+
+            format!("{:>w$} {}", label, bar, w = label_width)
+        })
+        .collect()
+}
+
+/// Render values as a single line of block characters scaled between
+/// their minimum and maximum.
+pub fn sparkline(values: &[f64]) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+    values
+        .iter()
+        .map(|&v| {
+            if max > min {
+                BLOCKS[((v - min) / (max - min) * 7.0).round() as usize]
+            } else {
+                BLOCKS[3]
+            }
+        })
+        .collect()
+}
+
+/// Plot function values as `*` on a grid with `height` rows and one
+/// column per value. Missing values leave a gap. The x axis is drawn
+/// if it is in range and the rows are labeled with the largest and
+/// smallest value.
+pub fn function_plot(values: &[Option<f64>], height: usize) -> Vec<String> {
+    let finite = values.iter().flatten().filter(|v| v.is_finite());
+    let min = finite.clone().cloned().fold(f64::INFINITY, f64::min);
+    let max = finite.cloned().fold(f64::NEG_INFINITY, f64::max);
+
+    if min > max || height == 0 {
+        return vec![];
+    }
+
+    let row = |v: f64| {
+        if max > min {
+            ((max - v) / (max - min) * (height - 1) as f64).round() as usize
+        } else {
+            height / 2
+        }
+    };
+
+    let mut grid = vec![vec![' '; values.len()]; height];
+    if min <= 0.0 && 0.0 <= max {
+        grid[row(0.0)] = vec!['-'; values.len()];
+    }
+    for (x, v) in values.iter().enumerate() {
+        if let Some(v) = v.filter(|v| v.is_finite()) {
+            grid[row(v)][x] = '*';
+        }
+    }
+
+    let top = float_significant(max, 4);
+    let bottom = float_significant(min, 4);
+    let width = top.len().max(bottom.len());
+
+    grid.iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let label = match i {
+                0 => &top,
+                i if i == height - 1 => &bottom,
+                _ => "",
+            };
+
+            format!(
+                "{:>w$} |{}",
+                label,
+                line.iter().collect::<String>(),
+                w = width
+            )
+            .trim_end()
+            .to_string()
+        })
+        .collect()
+}
+
+/// Remove trailing zeros after the decimal point.
+fn trim_zeros(s: &str) -> &str {
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_significant() {
+        assert_eq!(float_significant(1.0 / 3.0, 4), "0.3333");
+        assert_eq!(float_significant(2.0 / 3.0, 3), "0.667");
+        assert_eq!(float_significant(1234.5678, 6), "1234.57");
+        assert_eq!(float_significant(1234.5678, 2), "1.2e3");
+        assert_eq!(float_significant(0.00001234, 3), "1.23e-5");
+        assert_eq!(float_significant(-2.5, 10), "-2.5");
+        assert_eq!(float_significant(100.0, 3), "100");
+        assert_eq!(float_significant(0.0, 3), "0");
+        assert_eq!(float_significant(f64::INFINITY, 3), "inf");
+    }
+
+    #[test]
+    fn test_si_prefix() {
+        assert_eq!(si_prefix(12_300.0, 0), "12.3k");
+        assert_eq!(si_prefix(4.7e-6, 0), "4.7µ");
+        assert_eq!(si_prefix(-3.3e-9, 0), "-3.3n");
+        assert_eq!(si_prefix(1.0, 0), "1");
+        assert_eq!(si_prefix(0.5, 0), "500m");
+        assert_eq!(si_prefix(999_960.0, 4), "1M");
+        assert_eq!(si_prefix(1234.5678, 3), "1.23k");
+        assert_eq!(si_prefix(0.0, 0), "0");
+        assert_eq!(si_prefix(1e30, 3), "1e30");
+        assert_eq!(si_prefix(f64::NAN, 0), "NaN");
+    }
+
+    #[test]
+    fn test_group_digits() {
+        assert_eq!(group_digits("deadbeef", 4), "dead_beef");
+        assert_eq!(group_digits("10100110", 4), "1010_0110");
+        assert_eq!(group_digits("1100110", 4), "110_0110");
+        assert_eq!(group_digits("ff", 4), "ff");
+        assert_eq!(group_digits("deadbeef", 0), "deadbeef");
+        assert_eq!(group_digits("", 4), "");
+    }
+
+    #[test]
+    fn test_localize() {
+        let de: Locale = "de_DE.UTF-8".parse().unwrap();
+
+        assert_eq!(localize("-1234567.125", de), "-1.234.567,125");
+        assert_eq!(localize("1234.50EUR", de), "1.234,50EUR");
+        assert_eq!(localize("1.5e-7", de), "1,5e-7");
+        assert_eq!(localize("999", de), "999");
+        assert_eq!(localize("inf", de), "inf");
+        assert_eq!(localize("12345.5", "de_CH".parse().unwrap()), "12'345.5");
+        assert_eq!(localize("12345.5", Locale::default()), "12345.5");
+        assert!("xx".parse::<Locale>().is_err());
+    }
+
+    #[test]
+    fn test_permissions() {
+        assert_eq!(permissions(0o750), "rwxr-x---");
+        assert_eq!(permissions(0o644), "rw-r--r--");
+        assert_eq!(permissions(0o4755), "rwsr-xr-x");
+        assert_eq!(permissions(0o2640), "rw-r-S---");
+        assert_eq!(permissions(0o1777), "rwxrwxrwt");
+        assert_eq!(permissions(0), "---------");
+    }
+
+    #[test]
+    fn test_byte_size() {
+        assert_eq!(byte_size(1536.0 * 1024.0 * 1024.0, 1024.0), "1.5 GiB");
+        assert_eq!(byte_size(1536.0 * 1024.0 * 1024.0, 1000.0), "1.6 GB");
+        assert_eq!(byte_size(3.2e6, 1000.0), "3.2 MB");
+        assert_eq!(byte_size(1023.0, 1024.0), "1023 B");
+        assert_eq!(byte_size(-2048.0, 1024.0), "-2 KiB");
+        assert_eq!(byte_size(1e21, 1000.0), "1000 EB");
+    }
+
+    #[test]
+    fn test_significant_bytes() {
+        assert_eq!(hex_bytes(&significant_bytes(0xdead_beef)), "de ad be ef");
+        assert_eq!(hex_bytes(significant_bytes(0x1234).iter().rev()), "34 12");
+        assert_eq!(hex_bytes(&significant_bytes(0)), "00");
+        assert_eq!(hex_bytes(&significant_bytes(-2)), "ff ff ff ff ff ff ff fe");
+    }
+
+    #[test]
+    fn test_fraction() {
+        assert_eq!(fraction(0.5, 1000), Some((1, 2)));
+        assert_eq!(fraction(-0.75, 1000), Some((-3, 4)));
+        assert_eq!(fraction(1.0 / 3.0, 1000), Some((1, 3)));
+        assert_eq!(fraction(3.0, 1000), Some((3, 1)));
+        assert_eq!(fraction(0.0, 1000), Some((0, 1)));
+        assert_eq!(
+            fraction(std::f64::consts::PI, 100_000),
+            Some((312_689, 99_532))
+        );
+        assert_eq!(fraction(std::f64::consts::PI, 1000), None);
+        assert_eq!(fraction(1e-10, 1000), None);
+        assert_eq!(fraction(f64::NAN, 1000), None);
+        assert_eq!(fraction(1e300, 1000), None);
+    }
+
+    #[test]
+    fn test_plots() {
+        let labels: Vec<String> = vec!["1".into(), "-2".into(), "40".into()];
+
+        assert_eq!(
+            bar_chart(&labels, &[1.0, -2.0, 4.0], 8),
+            vec![" 1 ##", "-2 ----", "40 ########"]
+        );
+        assert_eq!(bar_chart(&labels[..1], &[0.0], 8), vec!["1 "]);
+
+        assert_eq!(sparkline(&[1.0, 8.0, 4.5]), "▁█▅");
+        assert_eq!(sparkline(&[2.0, 2.0]), "▄▄");
+        assert_eq!(sparkline(&[]), "");
+
+        assert_eq!(
+            function_plot(&[Some(-1.0), Some(0.0), None, Some(1.0)], 3),
+            vec![" 1 |   *", "   |-*--", "-1 |*"]
+        );
+        assert_eq!(
+            function_plot(&[Some(2.0), Some(2.0)], 3),
+            vec!["2 |", "  |**", "2 |"]
+        );
+        assert!(function_plot(&[None], 3).is_empty());
+    }
+}
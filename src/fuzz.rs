@@ -0,0 +1,56 @@
+//! # Fuzzing Support
+//!
+//! The fuzz targets in `fuzz/` and the regression tests below share
+//! this entry point, so every crash the fuzzer finds can be turned
+//! into a unit test by dropping the (minimized) input into
+//! `fuzz/regressions/`.
+
+use crate::calc::Calculator;
+use crate::parser::parse;
+use crate::types::Operation;
+
+/// Parse arbitrary bytes as input and apply the resulting
+/// operations. This must never panic.
+pub fn parse_and_apply(data: &[u8]) {
+    let line = match std::str::from_utf8(data) {
+        Ok(line) => line,
+        Err(_) => return,
+    };
+
+    let ops = match parse(line) {
+        Ok(ops) => ops,
+        Err(_) => return,
+    };
+
+    let mut calc = Calculator::new();
+
+    for op in ops {
+        // Don't let the fuzzer poke around in the file system,
+        // environment or clipboard.
+        match &op {
+            Operation::ReadFile(_) | Operation::PushEnv(_) | Operation::Paste => continue,
+            Operation::Decode(spec) if spec.ends_with(".toml") => continue,
+            _ => {}
+        }
+
+        if calc.apply_mut(op).is_err() {
+            break;
+        }
+    }
+
+    let _ = calc.to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regressions() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/regressions");
+
+        for entry in std::fs::read_dir(dir).unwrap() {
+            parse_and_apply(&std::fs::read(entry.unwrap().path()).unwrap());
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! # Line Input
+//!
+//! On a terminal, we read lines with a line editor that offers
+//! history and tab completion. Everything else (pipes, files) is read
+//! line by line as is.
+
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Config, Context, Editor, Helper};
+
+/// Provides completions for the line editor.
+#[derive(Default)]
+pub struct ClacHelper {
+    /// The currently defined variables.
+    variables: Vec<String>,
+    /// The names of stack snapshots.
+    snapshots: Vec<String>,
+}
+
+/// Find the start of the word at `pos` and its completions.
+fn complete_word(
+    line: &str,
+    pos: usize,
+    variables: &[String],
+    snapshots: &[String],
+) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let start = before
+        .char_indices()
+        .rfind(|(_, c)| c.is_whitespace())
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let word = &before[start..];
+
+    let mut previous = before[..start].split_whitespace().rev();
+    let names = match (previous.next(), previous.next()) {
+        // Combining into a variable needs an existing one.
+        (Some("rcl" | "sto+" | "sto-" | "sto*" | "sto/"), _) => variables,
+        (Some("restore" | "diff"), _) | (Some(_), Some("diff")) => snapshots,
+        _ => &[],
+    };
+
+    (
+        start,
+        names
+            .iter()
+            .filter(|n| n.starts_with(word))
+            .cloned()
+            .collect(),
+    )
+}
+
+impl Completer for ClacHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete_word(line, pos, &self.variables, &self.snapshots))
+    }
+}
+
+impl Hinter for ClacHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ClacHelper {}
+
+impl Validator for ClacHelper {}
+
+impl Helper for ClacHelper {}
+
+/// Where input lines come from.
+pub enum Input {
+    Editor(Box<Editor<ClacHelper, DefaultHistory>>),
+    Plain(io::Lines<io::StdinLock<'static>>),
+}
+
+impl Input {
+    /// Use the line editor, if stdin is a terminal.
+    pub fn new() -> Self {
+        if io::stdin().is_terminal() {
+            // Pasted text arrives in one piece, even with several
+            // lines, so it is evaluated as a unit.
+            let config = Config::builder().bracketed_paste(true).build();
+
+            if let Ok(mut editor) = Editor::with_config(config) {
+                editor.set_helper(Some(ClacHelper::default()));
+                return Input::Editor(Box::new(editor));
+            }
+        }
+
+        Input::Plain(io::stdin().lock().lines())
+    }
+
+    /// Tell the completer which variables exist.
+    pub fn set_variables(&mut self, variables: Vec<String>) {
+        if let Input::Editor(editor) = self {
+            if let Some(helper) = editor.helper_mut() {
+                helper.variables = variables;
+            }
+        }
+    }
+
+    /// Tell the completer which snapshots exist.
+    pub fn set_snapshots(&mut self, snapshots: Vec<String>) {
+        if let Input::Editor(editor) = self {
+            if let Some(helper) = editor.helper_mut() {
+                helper.snapshots = snapshots;
+            }
+        }
+    }
+
+    /// Read the next line or `None` at the end of input.
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        match self {
+            Input::Editor(editor) => match editor.readline(prompt) {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        let _ = editor.add_history_entry(line.as_str());
+                    }
+                    Ok(Some(line))
+                }
+                // Ctrl-C discards the line like in a shell.
+                Err(ReadlineError::Interrupted) => Ok(Some(String::new())),
+                Err(ReadlineError::Eof) => Ok(None),
+                Err(ReadlineError::Io(e)) => Err(e),
+                Err(e) => Err(io::Error::other(e)),
+            },
+            Input::Plain(lines) => {
+                print!("{}", prompt);
+                io::stdout().flush()?;
+
+                lines.next().transpose()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complete() {
+        let variables = vec!["alpha".to_string(), "beta".to_string(), "alps".to_string()];
+
+        assert_eq!(
+            complete_word("1 rcl al", 8, &variables, &[]),
+            (6, vec!["alpha".to_string(), "alps".to_string()])
+        );
+        assert_eq!(
+            complete_word("rcl ", 4, &variables, &[]),
+            (4, variables.clone())
+        );
+        assert_eq!(
+            complete_word("2 sto+ b", 8, &variables, &[]),
+            (7, vec!["beta".to_string()])
+        );
+        assert_eq!(complete_word("1 al", 4, &variables, &[]), (2, vec![]));
+        assert_eq!(complete_word("", 0, &variables, &[]), (0, vec![]));
+        assert_eq!(
+            complete_word("restore a", 9, &variables, &["base".to_string()]),
+            (8, vec![])
+        );
+        assert_eq!(
+            complete_word("restore ", 8, &variables, &["base".to_string()]),
+            (8, vec!["base".to_string()])
+        );
+        assert_eq!(
+            complete_word("diff base b", 11, &variables, &["base".to_string()]),
+            (10, vec!["base".to_string()])
+        );
+    }
+}
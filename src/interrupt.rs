@@ -0,0 +1,34 @@
+//! # Interrupts
+//!
+//! While an operation runs, Ctrl-C cancels it instead of ending the
+//! session. The signal handler only sets a flag that the calculator
+//! checks between the steps of long operations.
+
+use std::sync::atomic::AtomicBool;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    INTERRUPTED.store(true, std::sync::atomic::Ordering::Relaxed);
+}
+
+/// Catch Ctrl-C from now on. Returns the flag it sets.
+#[cfg(unix)]
+pub fn catch() -> &'static AtomicBool {
+    let handler: extern "C" fn(libc::c_int) = on_interrupt;
+
+    // SAFETY: The handler only touches an atomic, which is
+    // async-signal-safe.
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+
+    &INTERRUPTED
+}
+
+/// Without signals, Ctrl-C ends the program as usual.
+#[cfg(not(unix))]
+pub fn catch() -> &'static AtomicBool {
+    &INTERRUPTED
+}
@@ -0,0 +1,25 @@
+//! # CLAC - A Reverse Polish Calculator
+//!
+//! The calculator engine is available as a library, so it can be
+//! embedded and fuzzed. The `clac` binary is a thin layer on top.
+
+mod bitfield;
+pub mod calc;
+pub mod clipboard;
+mod dispatch;
+mod error;
+mod explain;
+mod finance;
+mod format;
+#[doc(hidden)]
+pub mod fuzz;
+mod money;
+mod net;
+mod numeric;
+pub mod parser;
+#[cfg(test)]
+mod proptests;
+mod stats;
+pub mod types;
+
+pub use error::Error;
@@ -1,40 +1,375 @@
-mod calc;
-mod parser;
-mod types;
+mod cli;
+mod constants;
+mod dc;
+mod events;
+mod export;
+mod input;
+mod interrupt;
+mod meta;
+mod prompt;
+mod proto;
+mod state;
+mod tutorial;
+mod watch;
 
-use anyhow::Result;
-use std::io::{self, BufRead, Write};
+use anyhow::{Context, Result};
+use std::io::{self, IsTerminal};
 use std::iter::Iterator;
+use std::path::Path;
+use std::time::Instant;
+use tracing_subscriber::EnvFilter;
 
-use crate::calc::Calculator;
-use crate::parser::parse;
+use clac::calc::Calculator;
+use clac::parser::parse_values;
+use clac::types::Operation;
+use clac::Error;
 
-fn parse_and_do(calc: &Calculator, line: &str) -> Result<Calculator> {
+use crate::cli::{Compat, Options, OutputFormat, Verbosity, USAGE};
+use crate::events::{Event, Events};
+use crate::meta::Flow;
+
+/// Evaluate a line on a copy of the calculator. With `timing`, report
+/// how long parsing and evaluation took on stderr.
+fn parse_and_do(
+    calc: &Calculator,
+    line: &str,
+    verbosity: Verbosity,
+    timing: bool,
+) -> Result<Calculator, Error> {
     let mut new_calc = calc.clone();
+    let line = &calc.select_input(line);
+
+    let start = Instant::now();
+    let ops = calc.parse_line(line)?;
+    let parsed = Instant::now();
+
+    if verbosity == Verbosity::Verbose {
+        let mut before = calc.to_string();
+
+        new_calc.eval_parsed(line, ops, |op, after| {
+            eprintln!("[{}] {} -> [{}]", before, op, after);
+            before = after.to_string();
+        })?;
+    } else {
+        new_calc.eval_parsed(line, ops, |_, _| {})?;
+    }
+    let evaluated = Instant::now();
+
+    for line in new_calc.take_output() {
+        println!("{}", line);
+    }
 
-    for op in parse(&line)? {
-        new_calc.apply_mut(op)?
+    if timing {
+        eprintln!("parse {:?}, eval {:?}", parsed - start, evaluated - parsed);
     }
 
     Ok(new_calc)
 }
 
-fn main() -> Result<()> {
-    let stdin = io::stdin();
-    let mut calc = Calculator::default();
-    let mut lines = stdin.lock().lines();
+/// Format the final result according to the output options.
+fn format_result(options: &Options, calc: &Calculator) -> String {
+    if options.format == OutputFormat::Shell {
+        export::format(calc)
+    } else if options.verbosity == Verbosity::Quiet {
+        calc.top().map_or(String::new(), |v| calc.format_value(&v))
+    } else {
+        calc.format_stack(options.output_delimiter())
+    }
+}
+
+/// Print the final result according to the output options.
+fn print_result(options: &Options, calc: &Calculator) {
+    // Shell scripts expect the assignments even for an empty stack.
+    if options.verbosity != Verbosity::Quiet
+        || options.format == OutputFormat::Shell
+        || calc.top().is_some()
+    {
+        println!("{}", format_result(options, calc));
+    }
+}
+
+/// Evaluate an expression given on the command line.
+fn run_expression(options: &Options, calc: &Calculator, expression: &str) -> Result<Calculator> {
+    let calc = match options.compat {
+        // Like dc, only print what the expression asks for.
+        Some(Compat::Dc) => {
+            let expression = dc::Translator::default().translate(expression)?;
+
+            parse_and_do(calc, &expression, options.verbosity, false)?
+        }
+        None => {
+            let calc = parse_and_do(calc, expression, options.verbosity, false)?;
+
+            print_result(options, &calc);
+            calc
+        }
+    };
+
+    Ok(calc)
+}
+
+/// Evaluate a script line by line like the REPL does and report the
+/// throughput.
+fn run_benchmark(calc: &Calculator, path: &str) -> Result<()> {
+    let script = std::fs::read_to_string(path).with_context(|| format!("Can't read {}", path))?;
+    let mut calc = calc.clone();
+    let mut operations = 0usize;
+
+    let start = Instant::now();
+    for line in script.lines() {
+        // Like the REPL, each line works on a copy.
+        let mut new_calc = calc.clone();
+
+        new_calc.eval_with(line, |_, _| operations += 1)?;
+        new_calc.take_output();
+        calc = new_calc;
+    }
+    let seconds = start.elapsed().as_secs_f64();
+
+    println!(
+        "{} lines, {} operations in {:.3} s ({:.0} ops/s)",
+        script.lines().count(),
+        operations,
+        seconds,
+        operations as f64 / seconds
+    );
+    Ok(())
+}
+
+/// Evaluate a script once and exit with an error if anything fails.
+/// Scripts with assertions double as tests.
+///
+/// With `--keep-going`, the script is evaluated line by line. Failed
+/// lines are reported and skipped, and a summary follows at the end.
+fn run_check(options: &Options, mut calc: Calculator, path: &str) -> Result<()> {
+    let script = std::fs::read_to_string(path).with_context(|| format!("Can't read {}", path))?;
+
+    if !options.keep_going {
+        if let Err(e) = calc.eval(&script) {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        }
+    } else {
+        let errors = calc.eval_lines(&script);
+
+        for (number, e) in &errors {
+            eprintln!("{}:{}: {}", path, number, e);
+        }
+
+        if !errors.is_empty() {
+            let lines = script.lines().count();
+
+            eprintln!("{}: {} of {} lines failed", path, errors.len(), lines);
+            std::process::exit(1);
+        }
+    }
+
+    if options.verbosity != Verbosity::Quiet {
+        println!("{}: ok", path);
+    }
+    Ok(())
+}
+
+/// Read lines from stdin and evaluate them one by one.
+fn run_interactive(options: &Options, mut calc: Calculator) -> io::Result<Calculator> {
+    let mut events = Events::new();
+    events.start_input();
+    calc.set_interrupt(interrupt::catch());
+
+    // Whether input ended in the middle of a prompt.
+    let mut at_eof = false;
+
+    // Escape sequences only make sense on a terminal.
+    let highlight =
+        options.highlight && io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    let mut changed = 0;
+    let mut timing = false;
+    let mut dc = options.compat.map(|Compat::Dc| dc::Translator::default());
 
     loop {
-        print!("{} | ", calc);
-        io::stdout().flush()?;
+        let prompt = if options.verbosity == Verbosity::Quiet || dc.is_some() {
+            String::new()
+        } else {
+            options.prompt.render(&calc, changed)
+        };
+
+        events.read_line(prompt, calc.variable_names(), calc.snapshot_names());
 
-        match lines.next() {
-            Some(line) => match parse_and_do(&calc, &line?) {
-                Ok(new_calc) => calc = new_calc,
-                Err(e) => println!("Error: {}", e),
+        // Other events don't end the wait for the line.
+        let line = loop {
+            match events.next() {
+                Event::Line(line) => break Some(line),
+                Event::Eof => break None,
+                Event::InputError(e) => return Err(e),
+                Event::Changed(_) => {}
+            }
+        };
+        let line = match line {
+            Some(line) => line,
+            None => {
+                at_eof = true;
+                break;
+            }
+        };
+
+        if meta::is_meta(&line) {
+            changed = 0;
+            match meta::execute(&line, &calc, options.clipboard) {
+                Ok(Flow::Continue) => {}
+                Ok(Flow::Quit) => break,
+                Ok(Flow::ToggleTiming) => {
+                    timing = !timing;
+                    println!("Timing is {}", if timing { "on" } else { "off" });
+                }
+                Err(e) => eprintln!("Error: {}", e),
+            }
+            continue;
+        }
+
+        let line = match &mut dc {
+            Some(dc) => match dc.translate(&line) {
+                Ok(line) => line,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    continue;
+                }
             },
-            None => break,
+            None => line,
+        };
+
+        match parse_and_do(&calc, &line, options.verbosity, timing) {
+            Ok(new_calc) => {
+                if highlight {
+                    changed = prompt::changed_values(calc.stack(), new_calc.stack());
+                }
+                calc = new_calc
+            }
+            Err(e) => {
+                changed = 0;
+                eprintln!("Error: {}", e)
+            }
+        }
+    }
+
+    // The prompt already shows the stack, unless it is hidden or the
+    // user asked for a specific format. dc users print what they need.
+    if dc.is_some() {
+        return Ok(calc);
+    }
+    if options.verbosity == Verbosity::Quiet {
+        print_result(options, &calc);
+    } else if options.format != OutputFormat::Plain {
+        if at_eof {
+            // Finish the line with the last prompt first.
+            println!();
         }
+        print_result(options, &calc);
+    }
+
+    Ok(calc)
+}
+
+/// Log calculator operations on stderr with `--trace` or as `RUST_LOG`
+/// says.
+fn init_tracing(trace: bool) {
+    let filter = if trace {
+        EnvFilter::new("clac=debug")
+    } else if std::env::var_os("RUST_LOG").is_some() {
+        EnvFilter::from_default_env()
+    } else {
+        return;
+    };
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .init();
+}
+
+fn main() -> Result<()> {
+    let options = Options::from_args(std::env::args().skip(1))?;
+
+    init_tracing(options.trace);
+
+    if options.help {
+        print!("{}", USAGE);
+        return Ok(());
+    }
+
+    if options.tutorial {
+        return Ok(tutorial::run()?);
+    }
+
+    let mut calc = Calculator::default();
+    calc.set_locale(options.out_locale);
+    calc.set_timeout(options.op_timeout);
+    calc.set_limits(options.limits);
+
+    // A missing default constants file is fine, a missing explicit one
+    // is not.
+    let constants_file = match &options.constants {
+        Some(path) => {
+            Some(std::fs::read_to_string(path).with_context(|| format!("Can't read {}", path))?)
+        }
+        None => constants::default_path().and_then(|path| std::fs::read_to_string(path).ok()),
+    };
+    if let Some(contents) = constants_file {
+        for (name, v) in constants::parse(&contents).map_err(anyhow::Error::msg)? {
+            calc.define_constant(&name, v);
+        }
+    }
+
+    let state_path = options.persist.and_then(state::path);
+    if let Some(path) = &state_path {
+        for v in state::load(path).with_context(|| format!("Can't read {}", path.display()))? {
+            calc.apply_mut(Operation::Push(v))?;
+        }
+    }
+
+    for v in &options.push {
+        calc.apply_mut(Operation::Push(v.clone()))?;
+    }
+
+    if let Some(column) = options.column {
+        calc.apply_mut(Operation::SetColumn(column))?;
+    }
+
+    if let Some(path) = &options.check {
+        return run_check(&options, calc, path);
+    }
+
+    if let Some(path) = &options.bench_input {
+        return run_benchmark(&calc, path);
+    }
+
+    if let Some(path) = &options.watch {
+        watch::run(&options, &calc, Path::new(path))?;
+        return Ok(());
+    }
+
+    if options.stdin_data {
+        // Stdin is used up by the data, so there is nothing to
+        // interact with afterwards.
+        for v in parse_values(
+            &calc.select_input(&io::read_to_string(io::stdin())?),
+            calc.overflow(),
+        )? {
+            calc.apply_mut(Operation::Push(v))?;
+        }
+
+        calc = run_expression(&options, &calc, options.expression.as_deref().unwrap_or(""))?;
+    } else {
+        calc = match &options.expression {
+            Some(expression) => run_expression(&options, &calc, expression)?,
+            None if options.pipe_proto => proto::run(calc)?,
+            None => run_interactive(&options, calc)?,
+        };
+    }
+
+    if let Some(path) = &state_path {
+        state::save(path, calc.stack())
+            .with_context(|| format!("Can't write {}", path.display()))?;
     }
 
     Ok(())
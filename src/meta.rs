@@ -0,0 +1,216 @@
+//! # REPL Meta-Commands
+//!
+//! Lines starting with `:` control the REPL instead of the
+//! calculator. They are kept out of [Operation], so they can't
+//! collide with math tokens.
+
+use std::io::Write;
+use std::path::Path;
+
+use clac::calc::Calculator;
+use clac::clipboard;
+use clac::types::Operation;
+
+use crate::cli::Clipboard;
+use crate::state;
+
+/// What the REPL should do after a meta-command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+    /// Start or stop reporting how long each line takes.
+    ToggleTiming,
+}
+
+/// All errors during meta-commands are represented by this type.
+#[derive(Debug)]
+pub enum MetaError {
+    UnknownCommand(String),
+    MissingArgument(String),
+    Io(std::io::Error),
+    /// The line given to `:preview` failed.
+    Calculator(clac::Error),
+    /// There is nothing to copy.
+    EmptyStack,
+    /// No clipboard helper worked.
+    Clipboard(String),
+}
+
+impl std::fmt::Display for MetaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            MetaError::UnknownCommand(c) => write!(f, "Unknown command: {} (try :help)", c),
+            MetaError::MissingArgument(c) => write!(f, "Missing argument for: {}", c),
+            MetaError::Io(e) => write!(f, "{}", e),
+            MetaError::Calculator(e) => write!(f, "{}", e),
+            MetaError::EmptyStack => write!(f, "The stack is empty"),
+            MetaError::Clipboard(e) => write!(f, "Clipboard error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MetaError {}
+
+impl From<std::io::Error> for MetaError {
+    fn from(e: std::io::Error) -> Self {
+        MetaError::Io(e)
+    }
+}
+
+impl From<clac::Error> for MetaError {
+    fn from(e: clac::Error) -> Self {
+        MetaError::Calculator(e)
+    }
+}
+
+const HELP: &str = "\
+Enter numbers and operations separated by whitespace.
+
+Numbers:     42 -7 0xff 0FFh 0b101 1010b 777o 1.5 -2.5e-3
+             1:30:15 12°30'15\" 10.0.0.1 10.0.0.0/8 $ENV_VAR 19.99EUR
+Arithmetic:  + - * / divmod round2 logb hypot atan2 exp exp2 expm1 ln1p ncr npr
+             ulp nextup nextdown (neighboring floats) isqrt ispow2 log2i (exact)
+             x lo hi clamp
+Bitwise:     & | ^ ~ << >> dump decode SPEC (name:hi:lo,... or FILE.toml)
+Stack:       swap dup drop clear print range (start stop step) iota keep on|off
+Results:     $1 $2 ... res N (results of earlier lines, latest first) ans ($1)
+             explain (the previous line as infix expression)
+Variables:   sto NAME rcl NAME vars sto+ sto- sto* sto/ NAME
+Snapshots:   snap NAME restore NAME diff NAME NAME (changed values)
+Programs:    def NAME [ ... ] run NAME plotf NAME (start stop steps)
+             integ NAME (a b) deriv NAME (x)
+             solve NAME (guess) solvein NAME (a b) (roots, eps sets the tolerance)
+             ( ... ) (run on a fresh stack, push what is left)
+             ( a b -- c ) (checked stack-effect comment)
+Conversion:  int rounding MODE (half-even half-up toward-zero floor ceiling)
+             overflow MODE (error promote mask) demote on|off tohms
+             todms fromdms torect topolar
+Display:     dec hex bin group N pad N prec N frac on|off si on|off deg rad
+             plot spark catalog [CATEGORY] (all operations by category)
+             inbase N (read bare integers in base N, #N or dN is decimal)
+             types on|off (show types in the prompt, e.g. 42:i64 0xff:u8)
+Networks:    netmask broadcast hosts in-subnet? ip
+Files:       rwxr-x--- perm bytes
+Time:        fromunix tounix (Y M D h m s) tz ZONE (local Europe/Berlin ...)
+Statistics:  s+ s2+ sn smean sstd sclear lr predict
+Finance:     n= i= pv= pmt= fv= (set) n? i? pv? pmt? fv? (solve) flows rate npv, flows irr
+Input:       readf FILE paste col N (Nth column of tab-separated lines)
+Testing:     a b assert-eq a b tol assert-near == != eps E (float tolerance)
+             x lo hi between? (1 if lo <= x <= hi)
+
+Meta-commands:
+  :help       Show this help
+  :mode       Show the current settings
+  :vars       Show all variables
+  :snaps      Show all stack snapshots
+  :consts     Show all constants from constants.toml
+  :save FILE  Save the stack, so `readf FILE` restores it
+  :copy       Copy the top of the stack to the clipboard (via OSC 52 over SSH)
+  :preview L  Show the stack after line L without changing anything
+  :search T   Show earlier lines containing T and their results ($N)
+  :time       Toggle reporting how long each line takes
+  :quit       Leave clac
+";
+
+/// Does this line contain a meta-command?
+pub fn is_meta(line: &str) -> bool {
+    line.trim_start().starts_with(':')
+}
+
+/// Execute a meta-command line. `:copy` uses the `clipboard`.
+pub fn execute(line: &str, calc: &Calculator, clipboard: Clipboard) -> Result<Flow, MetaError> {
+    let mut words = line.split_whitespace();
+    let command = words.next().unwrap_or(":");
+    let mut argument = || {
+        words
+            .next()
+            .ok_or_else(|| MetaError::MissingArgument(command.to_string()))
+    };
+
+    match command {
+        ":help" | ":h" => print!("{}", HELP),
+        ":quit" | ":q" => return Ok(Flow::Quit),
+        ":time" => return Ok(Flow::ToggleTiming),
+        ":mode" => println!("{}", to_line(&calc.modes())),
+        ":vars" => {
+            for line in calc.format_variables() {
+                println!("{}", line);
+            }
+        }
+        ":consts" => {
+            for line in calc.format_constants() {
+                println!("{}", line);
+            }
+        }
+        ":snaps" => {
+            for line in calc.format_snapshots() {
+                println!("{}", line);
+            }
+        }
+        ":save" => save(argument()?, calc)?,
+        ":copy" => copy(calc, clipboard)?,
+        ":preview" | ":p" => preview(line.trim_start()[command.len()..].trim(), calc)?,
+        ":search" | ":s" => {
+            let text = line.trim_start()[command.len()..].trim();
+
+            if text.is_empty() {
+                return Err(MetaError::MissingArgument(command.to_string()));
+            }
+            for line in calc.search_results(text) {
+                println!("{}", line);
+            }
+        }
+        _ => return Err(MetaError::UnknownCommand(command.to_string())),
+    }
+
+    Ok(Flow::Continue)
+}
+
+fn to_line(ops: &[Operation]) -> String {
+    let tokens: Vec<String> = ops.iter().map(|op| op.to_string()).collect();
+
+    tokens.join(" ")
+}
+
+/// Show the stack after evaluating `line` without keeping the result.
+fn preview(line: &str, calc: &Calculator) -> Result<(), MetaError> {
+    let mut scratch = calc.clone();
+
+    scratch.eval(line)?;
+    for line in scratch.take_output() {
+        println!("{}", line);
+    }
+    println!("{}", scratch.format_stack(" "));
+
+    Ok(())
+}
+
+/// Copy the top of the stack as it is displayed.
+fn copy(calc: &Calculator, mode: Clipboard) -> Result<(), MetaError> {
+    let text = calc
+        .stack()
+        .last()
+        .map(|v| calc.format_value(v))
+        .ok_or(MetaError::EmptyStack)?;
+    // Helpers on a server would only fill the server's clipboard.
+    let remote = ["SSH_TTY", "SSH_CONNECTION"]
+        .iter()
+        .any(|name| std::env::var_os(name).is_some());
+
+    match mode {
+        Clipboard::System => clipboard::copy(&text).map_err(MetaError::Clipboard),
+        Clipboard::Auto if !remote => clipboard::copy(&text).map_err(MetaError::Clipboard),
+        Clipboard::Auto | Clipboard::Osc52 => {
+            let mut stdout = std::io::stdout();
+
+            stdout.write_all(clipboard::osc52(&text).as_bytes())?;
+            Ok(stdout.flush()?)
+        }
+    }
+}
+
+/// Write the stack to a file as one number per line.
+fn save(path: &str, calc: &Calculator) -> Result<(), MetaError> {
+    Ok(state::save(Path::new(path), calc.stack())?)
+}
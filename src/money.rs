@@ -0,0 +1,112 @@
+//! # Money
+//!
+//! Amounts of money are decimal numbers tagged with a currency, like
+//! `12.50EUR`. Decimal arithmetic keeps cents exact where floats drift,
+//! and amounts in different currencies never mix.
+
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use lazy_static::lazy_static;
+use num_traits::ToPrimitive;
+use regex::Regex;
+use rust_decimal::{Decimal, RoundingStrategy};
+
+use crate::types::{RoundingMode, Value};
+
+/// An amount with its currency. Plain numbers have no currency.
+pub type Amount = (Decimal, Option<String>);
+
+/// Parse a literal like `12.50EUR` or `-3USD`.
+pub fn parse(s: &str) -> Option<Value> {
+    lazy_static! {
+        static ref MONEY_RE: Regex = Regex::new("^([+-]?[0-9]+(\\.[0-9]+)?)([A-Z]{3})$").unwrap();
+    }
+
+    let c = MONEY_RE.captures(s)?;
+
+    Some(Value::Money {
+        amount: Decimal::from_str(&c[1]).ok()?,
+        currency: c[3].to_string(),
+    })
+}
+
+/// Format an amount with at least the two decimals of cents.
+pub fn format(amount: &Decimal, currency: &str) -> String {
+    let mut amount = *amount;
+
+    if amount.scale() < 2 {
+        amount.rescale(2);
+    }
+
+    format!("{}{}", amount, currency)
+}
+
+/// Split a value into amount and currency. Floats that have no
+/// decimal representation, like infinity, have no amount.
+pub fn amount(v: Value) -> Option<Amount> {
+    match v {
+        Value::Money { amount, currency } => Some((amount, Some(currency))),
+        Value::Integer(i) => Some((Decimal::from(i), None)),
+        Value::Float(f) => Some((Decimal::try_from(f).ok()?, None)),
+        Value::Big(b) => Some((Decimal::from_str(&b.to_string()).ok()?, None)),
+    }
+}
+
+/// Round an amount to `places` decimals.
+pub fn round(amount: Decimal, places: u32, mode: RoundingMode) -> Decimal {
+    amount.round_dp_with_strategy(
+        places,
+        match mode {
+            RoundingMode::HalfEven => RoundingStrategy::MidpointNearestEven,
+            RoundingMode::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            RoundingMode::TowardZero => RoundingStrategy::ToZero,
+            RoundingMode::Floor => RoundingStrategy::ToNegativeInfinity,
+            RoundingMode::Ceiling => RoundingStrategy::ToPositiveInfinity,
+        },
+    )
+}
+
+/// The amount as float, e.g. for functions that have no decimal
+/// version.
+pub fn to_f64(amount: &Decimal) -> f64 {
+    amount.to_f64().unwrap_or(f64::NAN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_format() {
+        let money = |amount: &str, currency: &str| Value::Money {
+            amount: Decimal::from_str(amount).unwrap(),
+            currency: currency.to_string(),
+        };
+
+        assert_eq!(parse("12.5EUR"), Some(money("12.5", "EUR")));
+        assert_eq!(parse("-3USD"), Some(money("-3", "USD")));
+        assert_eq!(parse("3usd"), None);
+        assert_eq!(parse("3.USD"), None);
+        assert_eq!(parse("3EURO"), None);
+
+        assert_eq!(format(&Decimal::from(3), "USD"), "3.00USD");
+        assert_eq!(
+            format(&Decimal::from_str("0.125").unwrap(), "EUR"),
+            "0.125EUR"
+        );
+    }
+
+    #[test]
+    fn test_round() {
+        let d = |s| Decimal::from_str(s).unwrap();
+
+        assert_eq!(round(d("2.345"), 2, RoundingMode::HalfEven), d("2.34"));
+        assert_eq!(round(d("2.345"), 2, RoundingMode::HalfUp), d("2.35"));
+        assert_eq!(round(d("-2.349"), 2, RoundingMode::TowardZero), d("-2.34"));
+        assert_eq!(round(d("-2.341"), 2, RoundingMode::Floor), d("-2.35"));
+
+        assert_eq!(amount(Value::Float(0.1)), Some((d("0.1"), None)));
+        assert_eq!(amount(Value::Float(f64::INFINITY)), None);
+    }
+}
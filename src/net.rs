@@ -0,0 +1,121 @@
+//! # IPv4 Addresses
+//!
+//! IPv4 addresses are plain integers on the stack. This module
+//! converts them from and to dotted quads and does the subnet math.
+
+/// The largest IPv4 address as an integer.
+const MAX_ADDRESS: i64 = u32::MAX as i64;
+
+/// Parse a dotted quad like `192.168.0.1`.
+pub fn parse_ipv4(s: &str) -> Option<i64> {
+    let octets: Vec<&str> = s.split('.').collect();
+
+    if octets.len() != 4 {
+        return None;
+    }
+
+    octets.iter().try_fold(0, |address, octet| {
+        if octet.is_empty() || octet.len() > 3 || !octet.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        Some(address << 8 | i64::from(octet.parse::<u8>().ok()?))
+    })
+}
+
+/// Format an integer as dotted quad, if it is a valid address.
+pub fn format_ipv4(address: i64) -> Option<String> {
+    if !(0..=MAX_ADDRESS).contains(&address) {
+        return None;
+    }
+
+    let octets: Vec<String> = (0..4)
+        .rev()
+        .map(|i| (address >> (8 * i) & 0xff).to_string())
+        .collect();
+
+    Some(octets.join("."))
+}
+
+/// The netmask of a prefix length, e.g. 255.255.255.0 for 24.
+pub fn netmask(prefix: i64) -> Option<i64> {
+    if !(0..=32).contains(&prefix) {
+        return None;
+    }
+
+    Some(MAX_ADDRESS << (32 - prefix) & MAX_ADDRESS)
+}
+
+/// The broadcast address of the subnet that contains `address`.
+pub fn broadcast(address: i64, prefix: i64) -> Option<i64> {
+    if !(0..=MAX_ADDRESS).contains(&address) {
+        return None;
+    }
+
+    Some(address | !netmask(prefix)? & MAX_ADDRESS)
+}
+
+/// The number of usable host addresses in a subnet. Point-to-point
+/// links (/31) and single hosts (/32) have no network and broadcast
+/// addresses.
+pub fn hosts(prefix: i64) -> Option<i64> {
+    match prefix {
+        32 => Some(1),
+        31 => Some(2),
+        0..=30 => Some((1 << (32 - prefix)) - 2),
+        _ => None,
+    }
+}
+
+/// Check whether `address` is in the subnet `network`/`prefix`.
+pub fn in_subnet(address: i64, network: i64, prefix: i64) -> Option<bool> {
+    let mask = netmask(prefix)?;
+
+    if !(0..=MAX_ADDRESS).contains(&address) || !(0..=MAX_ADDRESS).contains(&network) {
+        return None;
+    }
+
+    Some(address & mask == network & mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(parse_ipv4("192.168.1.10"), Some(0xc0a8_010a));
+        assert_eq!(parse_ipv4("0.0.0.0"), Some(0));
+        assert_eq!(parse_ipv4("256.0.0.1"), None);
+        assert_eq!(parse_ipv4("1.2.3"), None);
+        assert_eq!(parse_ipv4("1.2.3.+4"), None);
+
+        assert_eq!(format_ipv4(0xc0a8_010a), Some("192.168.1.10".to_string()));
+        assert_eq!(
+            format_ipv4(MAX_ADDRESS),
+            Some("255.255.255.255".to_string())
+        );
+        assert_eq!(format_ipv4(-1), None);
+        assert_eq!(format_ipv4(MAX_ADDRESS + 1), None);
+    }
+
+    #[test]
+    fn test_subnets() {
+        assert_eq!(netmask(24), Some(0xffff_ff00));
+        assert_eq!(netmask(0), Some(0));
+        assert_eq!(netmask(32), Some(MAX_ADDRESS));
+        assert_eq!(netmask(33), None);
+
+        assert_eq!(broadcast(0xc0a8_010a, 24), Some(0xc0a8_01ff));
+        assert_eq!(broadcast(0xc0a8_010a, 32), Some(0xc0a8_010a));
+
+        assert_eq!(hosts(24), Some(254));
+        assert_eq!(hosts(0), Some(4_294_967_294));
+        assert_eq!(hosts(31), Some(2));
+        assert_eq!(hosts(-1), None);
+
+        assert_eq!(in_subnet(0xc0a8_010a, 0xc0a8_0100, 24), Some(true));
+        assert_eq!(in_subnet(0xc0a8_020a, 0xc0a8_0100, 24), Some(false));
+        assert_eq!(in_subnet(0xc0a8_020a, 0xc0a8_0100, 16), Some(true));
+    }
+}
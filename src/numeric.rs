@@ -0,0 +1,291 @@
+//! # Numeric Calculus
+//!
+//! Integrals, derivatives and roots of functions that are only known
+//! by evaluating them, like stored programs. The functions may fail, in
+//! which case the whole computation fails with the same error.
+
+/// How many steps Brent's method takes at most. It usually needs a
+/// few dozen.
+const MAX_ITERATIONS: u32 = 200;
+
+/// How often the search for a sign change around a guess doubles its
+/// distance.
+const MAX_EXPANSIONS: u32 = 64;
+
+/// How often an interval is halved at most. This bounds the number of
+/// evaluations for functions that don't converge, e.g. at a pole.
+const MAX_DEPTH: u32 = 16;
+
+/// Simpson's rule on [a, b]. Returns the midpoint, the function value
+/// there and the estimate.
+fn simpson<E>(
+    f: &mut impl FnMut(f64) -> Result<f64, E>,
+    (a, fa): (f64, f64),
+    (b, fb): (f64, f64),
+) -> Result<(f64, f64, f64), E> {
+    let m = (a + b) / 2.0;
+    let fm = f(m)?;
+
+    Ok((m, fm, (b - a) / 6.0 * (fa + 4.0 * fm + fb)))
+}
+
+/// Refine the estimate `whole` of the integral over [a, b] until both
+/// halves agree with it within `tolerance`.
+fn adaptive<E>(
+    f: &mut impl FnMut(f64) -> Result<f64, E>,
+    a: (f64, f64),
+    b: (f64, f64),
+    m: (f64, f64),
+    whole: f64,
+    tolerance: f64,
+    depth: u32,
+) -> Result<f64, E> {
+    let (lm, flm, left) = simpson(f, a, m)?;
+    let (rm, frm, right) = simpson(f, m, b)?;
+    let delta = left + right - whole;
+
+    // The error of the halves is about a fifteenth of the difference,
+    // which also makes a good correction.
+    if depth == 0 || delta.abs() <= 15.0 * tolerance || !delta.is_finite() {
+        return Ok(left + right + delta / 15.0);
+    }
+
+    Ok(
+        adaptive(f, a, m, (lm, flm), left, tolerance / 2.0, depth - 1)?
+            + adaptive(f, m, b, (rm, frm), right, tolerance / 2.0, depth - 1)?,
+    )
+}
+
+/// The integral of `f` from `a` to `b` with adaptive Simpson
+/// quadrature to within about `tolerance`.
+pub fn integrate<E>(
+    mut f: impl FnMut(f64) -> Result<f64, E>,
+    a: f64,
+    b: f64,
+    tolerance: f64,
+) -> Result<f64, E> {
+    let (fa, fb) = (f(a)?, f(b)?);
+    let (m, fm, whole) = simpson(&mut f, (a, fa), (b, fb))?;
+
+    adaptive(
+        &mut f,
+        (a, fa),
+        (b, fb),
+        (m, fm),
+        whole,
+        tolerance,
+        MAX_DEPTH,
+    )
+}
+
+/// The derivative of `f` at `x` with the five-point central
+/// difference. The step balances truncation against rounding errors.
+pub fn derivative<E>(mut f: impl FnMut(f64) -> Result<f64, E>, x: f64) -> Result<f64, E> {
+    let h = f64::EPSILON.powf(0.2) * x.abs().max(1.0);
+
+    Ok((f(x - 2.0 * h)? - 8.0 * f(x - h)? + 8.0 * f(x + h)? - f(x + 2.0 * h)?) / (12.0 * h))
+}
+
+/// Whether `a` and `b` have different signs. Products of tiny values
+/// can underflow to 0, so they are not multiplied.
+fn opposite(a: f64, b: f64) -> bool {
+    (a < 0.0 && b > 0.0) || (a > 0.0 && b < 0.0)
+}
+
+/// Brent's method for a root in [a, b], where `fa` and `fb` have
+/// different signs. It combines bisection, which always converges,
+/// with inverse quadratic interpolation, which converges fast.
+fn brent<E>(
+    f: &mut impl FnMut(f64) -> Result<f64, E>,
+    (mut a, mut fa): (f64, f64),
+    (mut b, mut fb): (f64, f64),
+    tolerance: f64,
+) -> Result<f64, E> {
+    let (mut c, mut fc) = (a, fa);
+    let mut d = b - a;
+    let mut e = d;
+
+    for _ in 0..MAX_ITERATIONS {
+        // Keep the root between b and c, with b the better guess.
+        if (fb > 0.0) == (fc > 0.0) {
+            c = a;
+            fc = fa;
+            d = b - a;
+            e = d;
+        }
+        if fc.abs() < fb.abs() {
+            a = b;
+            b = c;
+            c = a;
+            fa = fb;
+            fb = fc;
+            fc = fa;
+        }
+
+        let tol = 2.0 * f64::EPSILON * b.abs() + tolerance / 2.0;
+        let half = (c - b) / 2.0;
+        if half.abs() <= tol || fb == 0.0 {
+            return Ok(b);
+        }
+
+        if e.abs() >= tol && fa.abs() > fb.abs() {
+            let s = fb / fa;
+            let (mut p, mut q) = if a == c {
+                // Secant step.
+                (2.0 * half * s, 1.0 - s)
+            } else {
+                let (q, r) = (fa / fc, fb / fc);
+
+                (
+                    s * (2.0 * half * q * (q - r) - (b - a) * (r - 1.0)),
+                    (q - 1.0) * (r - 1.0) * (s - 1.0),
+                )
+            };
+            if p > 0.0 {
+                q = -q;
+            } else {
+                p = -p;
+            }
+
+            // Only interpolate if it stays well inside the bracket.
+            if 2.0 * p < (3.0 * half * q - (tol * q).abs()).min((e * q).abs()) {
+                e = d;
+                d = p / q;
+            } else {
+                d = half;
+                e = d;
+            }
+        } else {
+            d = half;
+            e = d;
+        }
+
+        a = b;
+        fa = fb;
+        b += if d.abs() > tol { d } else { tol.copysign(half) };
+        fb = f(b)?;
+    }
+
+    Ok(b)
+}
+
+/// A root of `f` between `a` and `b` to within `tolerance`, which may
+/// be 0 for full precision. `None` if `f` has the same sign at both
+/// ends.
+pub fn find_root<E>(
+    mut f: impl FnMut(f64) -> Result<f64, E>,
+    a: f64,
+    b: f64,
+    tolerance: f64,
+) -> Result<Option<f64>, E> {
+    let (fa, fb) = (f(a)?, f(b)?);
+
+    if fa == 0.0 {
+        return Ok(Some(a));
+    }
+    if fb == 0.0 {
+        return Ok(Some(b));
+    }
+    if !opposite(fa, fb) {
+        return Ok(None);
+    }
+
+    brent(&mut f, (a, fa), (b, fb), tolerance).map(Some)
+}
+
+/// A root of `f` near `guess`. Looks for a sign change in steps that
+/// double in size on both sides and continues like [find_root].
+pub fn solve<E>(
+    mut f: impl FnMut(f64) -> Result<f64, E>,
+    guess: f64,
+    tolerance: f64,
+) -> Result<Option<f64>, E> {
+    let fg = f(guess)?;
+
+    if fg == 0.0 {
+        return Ok(Some(guess));
+    }
+    if !fg.is_finite() {
+        return Ok(None);
+    }
+
+    let mut step = 0.01 * guess.abs().max(1.0);
+    for _ in 0..MAX_EXPANSIONS {
+        for x in [guess + step, guess - step] {
+            let fx = f(x)?;
+
+            if fx == 0.0 {
+                return Ok(Some(x));
+            }
+            if opposite(fx, fg) {
+                return brent(&mut f, (guess, fg), (x, fx), tolerance).map(Some);
+            }
+        }
+        step *= 2.0;
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ok(f: fn(f64) -> f64) -> impl FnMut(f64) -> Result<f64, ()> {
+        move |x| Ok(f(x))
+    }
+
+    #[test]
+    fn test_integrate() {
+        let near = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+        assert!(near(
+            integrate(ok(|x| x * x), 0.0, 3.0, 1e-10).unwrap(),
+            9.0
+        ));
+        assert!(near(
+            integrate(ok(f64::sin), 0.0, std::f64::consts::PI, 1e-10).unwrap(),
+            2.0
+        ));
+        assert!(near(
+            integrate(ok(f64::exp), 1.0, 0.0, 1e-10).unwrap(),
+            1.0 - 1f64.exp()
+        ));
+        assert_eq!(integrate(|_| Err("fails"), 0.0, 1.0, 1e-10), Err("fails"));
+    }
+
+    #[test]
+    fn test_find_root() {
+        let sqrt2 = find_root(ok(|x| x * x - 2.0), 0.0, 2.0, 0.0).unwrap();
+
+        assert!((sqrt2.unwrap() - 2f64.sqrt()).abs() <= 2.0 * f64::EPSILON);
+        assert_eq!(find_root(ok(|x| x - 1.0), 1.0, 5.0, 0.0), Ok(Some(1.0)));
+        assert_eq!(find_root(ok(|x| x * x + 1.0), -1.0, 1.0, 0.0), Ok(None));
+
+        let rough = find_root(ok(f64::cos), 0.0, 3.0, 1e-3).unwrap().unwrap();
+        assert!((rough - std::f64::consts::FRAC_PI_2).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_solve() {
+        let root = solve(ok(|x| x.exp() - 10.0), 0.0, 0.0).unwrap().unwrap();
+        assert!((root - 10f64.ln()).abs() < 1e-14);
+
+        let root = solve(ok(|x| x * x * x + 1000.0), 5.0, 0.0)
+            .unwrap()
+            .unwrap();
+        assert!((root + 10.0).abs() < 1e-12);
+
+        assert_eq!(solve(ok(|x| x * x + 1.0), 0.0, 0.0), Ok(None));
+        assert_eq!(solve(ok(f64::ln), -1.0, 0.0), Ok(None));
+    }
+
+    #[test]
+    fn test_derivative() {
+        let near = |a: f64, b: f64| (a - b).abs() < 1e-9 * b.abs().max(1.0);
+
+        assert!(near(derivative(ok(|x| x * x), 3.0).unwrap(), 6.0));
+        assert!(near(derivative(ok(f64::exp), 10.0).unwrap(), 10f64.exp()));
+        assert!(near(derivative(ok(f64::cos), 0.0).unwrap(), 0.0));
+    }
+}
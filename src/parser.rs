@@ -5,49 +5,118 @@
 use lazy_static::lazy_static;
 use regex::Regex;
 
+use std::collections::BTreeMap;
+use std::ops::Range;
 use std::str::FromStr;
 
-use crate::types::{Operation, Radix, Value};
+use num_bigint::BigInt;
+
+use crate::money;
+use crate::net::parse_ipv4;
+use crate::types::{
+    AngleMode, Category, Locale, Operation, OverflowMode, Radix, RoundingMode, StackEffect,
+    TvmRegister, Type, Value, Zone,
+};
 
 /// All parsing errors are represented by this type.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct ParseError {
-    /// The token that could not be parsed.
-    offending_token: String,
+pub enum ParseError {
+    /// The token could not be parsed.
+    InvalidToken(String),
+    /// The command needs an argument, but the input ended before it.
+    MissingArgument(String),
+    /// A stack-effect comment like `( a b -- c )` doesn't match the
+    /// operations after it.
+    WrongStackEffect {
+        declared: String,
+        actual: StackEffect,
+    },
 }
 
 impl std::fmt::Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
-        write!(f, "Failed to parse token: {}", self.offending_token)
+        match self {
+            ParseError::InvalidToken(t) => write!(f, "Failed to parse token: {}", t),
+            ParseError::MissingArgument(t) => write!(f, "Missing argument for: {}", t),
+            ParseError::WrongStackEffect { declared, actual } => write!(
+                f,
+                "Stack effect {} doesn't match the operations, which take {} and leave {}",
+                declared, actual.pops, actual.pushes
+            ),
+        }
     }
 }
 
 impl std::error::Error for ParseError {}
 
 fn parse_value(token: &str) -> Result<Value, ParseError> {
-    let parse_error = || ParseError {
-        offending_token: token.to_string(),
-    };
+    let parse_error = || ParseError::InvalidToken(token.to_string());
 
     lazy_static! {
         static ref INTEGER_RE: Regex = Regex::new("^([+-]?)[0-9]+$").unwrap();
-        static ref HEX_RE: Regex = Regex::new("^0x[a-fA-F0-9]+$").unwrap();
-        static ref BIN_RE: Regex = Regex::new("^0b[0-1]+$").unwrap();
-        static ref FLOAT_RE: Regex = Regex::new("^[0-9]+\\.[0-9]+$").unwrap();
+        // Digits may be grouped with underscores like in the output.
+        static ref HEX_RE: Regex = Regex::new("^0x[a-fA-F0-9][a-fA-F0-9_]*$").unwrap();
+        static ref BIN_RE: Regex = Regex::new("^0b[0-1][0-1_]*$").unwrap();
+        // Suffixes like in assembler listings. Hex numbers start with
+        // a digit, so they can't be confused with words. A capital `B`
+        // is a byte size, not a binary number.
+        static ref HEX_SUFFIX_RE: Regex = Regex::new("^[0-9][a-fA-F0-9]*[hH]$").unwrap();
+        static ref BIN_SUFFIX_RE: Regex = Regex::new("^[0-1]+b$").unwrap();
+        static ref OCT_SUFFIX_RE: Regex = Regex::new("^[0-7]+[oO]$").unwrap();
+        static ref FLOAT_RE: Regex =
+            Regex::new("^[+-]?[0-9]+(\\.[0-9]+)?([eE][+-]?[0-9]+)?$").unwrap();
+        static ref DMS_RE: Regex =
+            Regex::new("^([+-]?)([0-9]+)°(([0-5]?[0-9])')?(([0-5]?[0-9](\\.[0-9]+)?)\")?$")
+                .unwrap();
+        static ref HMS_RE: Regex =
+            Regex::new("^([+-]?)([0-9]+):([0-5][0-9]):([0-5][0-9](\\.[0-9]+)?)$").unwrap();
     }
 
     if INTEGER_RE.is_match(token) {
-        Ok(Value::Integer(
-            i64::from_str(token).map_err(|_| parse_error())?,
+        // Integers that are too large for i64 are big integers, which
+        // only the overflow promote mode accepts.
+        Ok(Value::from(
+            BigInt::from_str(token).map_err(|_| parse_error())?,
         ))
     } else if HEX_RE.is_match(token) {
         Ok(Value::Integer(
-            i64::from_str_radix(&token[2..], 16).map_err(|_| parse_error())?,
+            i64::from_str_radix(&token[2..].replace('_', ""), 16).map_err(|_| parse_error())?,
         ))
     } else if BIN_RE.is_match(token) {
         Ok(Value::Integer(
-            i64::from_str_radix(&token[2..], 2).map_err(|_| parse_error())?,
+            i64::from_str_radix(&token[2..].replace('_', ""), 2).map_err(|_| parse_error())?,
         ))
+    } else if let Some(c) = HMS_RE.captures(token) {
+        // Times are converted to seconds.
+        sexagesimal(&c[1], &c[2], &c[3], &c[4]).ok_or_else(parse_error)
+    } else if let Some(radix) = [
+        (&*HEX_SUFFIX_RE, 16),
+        (&*BIN_SUFFIX_RE, 2),
+        (&*OCT_SUFFIX_RE, 8),
+    ]
+    .iter()
+    .find(|(re, _)| re.is_match(token))
+    .map(|(_, radix)| *radix)
+    {
+        Ok(Value::Integer(
+            i64::from_str_radix(&token[..token.len() - 1], radix).map_err(|_| parse_error())?,
+        ))
+    } else if let Some(money) = money::parse(token) {
+        Ok(money)
+    } else if let Some(bytes) = parse_byte_size(token) {
+        Ok(bytes)
+    } else if let Some(mode) = parse_permissions(token) {
+        Ok(Value::Integer(mode))
+    } else if let Some(address) = parse_ipv4(token) {
+        Ok(Value::Integer(address))
+    } else if let Some(c) = DMS_RE.captures(token) {
+        // Angles are converted to decimal degrees.
+        let part = |i| c.get(i).map_or(Ok(0.0), |m| f64::from_str(m.as_str()));
+        let degrees = part(2).map_err(|_| parse_error())?
+            + part(4).map_err(|_| parse_error())? / 60.0
+            + part(6).map_err(|_| parse_error())? / 3600.0;
+
+        Ok(Value::Float(if &c[1] == "-" { -degrees } else { degrees }))
     } else if FLOAT_RE.is_match(token) {
         Ok(Value::Float(
             f64::from_str(token).map_err(|_| parse_error())?,
@@ -57,6 +126,62 @@ fn parse_value(token: &str) -> Result<Value, ParseError> {
     }
 }
 
+/// Parse `token` as an integer in the input base `base`, unless that
+/// is 10. `#` or `d` in front of decimal digits escapes back to
+/// decimal. Digits that are too large for the base are an error, so
+/// they aren't silently read as decimal. `None` for anything else.
+fn parse_in_base(token: &str, base: u32) -> Option<Result<Value, ParseError>> {
+    lazy_static! {
+        static ref DECIMAL_RE: Regex = Regex::new("^[#d]([+-]?[0-9]+)$").unwrap();
+        static ref BASED_RE: Regex = Regex::new("^([+-]?)([0-9a-zA-Z][0-9a-zA-Z_]*)$").unwrap();
+    }
+
+    if base == 10 {
+        return None;
+    }
+    if let Some(c) = DECIMAL_RE.captures(token) {
+        return BigInt::from_str(&c[1]).ok().map(Value::from).map(Ok);
+    }
+
+    let c = BASED_RE.captures(token)?;
+    // Explicit prefixes keep their meaning.
+    if c[2].starts_with("0x") || c[2].starts_with("0b") {
+        return None;
+    }
+
+    let digits = c[2].replace('_', "");
+    let Some(magnitude) = BigInt::parse_bytes(digits.as_bytes(), base) else {
+        // Letters make other literals like `0FFh` or `4KiB`.
+        return digits
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| Err(ParseError::InvalidToken(token.to_string())));
+    };
+
+    Some(Ok(Value::from(if &c[1] == "-" {
+        -magnitude
+    } else {
+        magnitude
+    })))
+}
+
+/// Combine hours (or degrees), minutes and seconds into seconds. The
+/// result is an integer, unless there are fractional seconds.
+fn sexagesimal(sign: &str, hours: &str, minutes: &str, seconds: &str) -> Option<Value> {
+    let whole = i64::from_str(hours)
+        .ok()?
+        .checked_mul(3600)?
+        .checked_add(i64::from_str(minutes).ok()? * 60)?;
+    let sign = if sign == "-" { -1 } else { 1 };
+
+    match i64::from_str(seconds) {
+        Ok(s) => Some(Value::Integer(sign * whole.checked_add(s)?)),
+        Err(_) => Some(Value::Float(
+            sign as f64 * (whole as f64 + f64::from_str(seconds).ok()?),
+        )),
+    }
+}
+
 impl FromStr for Operation {
     type Err = ParseError;
 
@@ -66,6 +191,8 @@ impl FromStr for Operation {
             "-" => Ok(Operation::Subtract),
             "*" => Ok(Operation::Multiply),
             "/" => Ok(Operation::Divide),
+            "divmod" => Ok(Operation::DivMod),
+            "round2" => Ok(Operation::Round2),
             "&" => Ok(Operation::BitAnd),
             "|" => Ok(Operation::BitOr),
             "^" => Ok(Operation::BitXor),
@@ -75,25 +202,1189 @@ impl FromStr for Operation {
             "hex" => Ok(Operation::SetRadix(Radix::Hex)),
             "dec" => Ok(Operation::SetRadix(Radix::Dec)),
             "bin" => Ok(Operation::SetRadix(Radix::Bin)),
+            "ip" => Ok(Operation::SetRadix(Radix::Ip)),
+            "netmask" => Ok(Operation::Netmask),
+            "broadcast" => Ok(Operation::Broadcast),
+            "hosts" => Ok(Operation::Hosts),
+            "in-subnet?" => Ok(Operation::InSubnet),
+            "n=" => Ok(Operation::TvmStore(TvmRegister::N)),
+            "i=" => Ok(Operation::TvmStore(TvmRegister::I)),
+            "pv=" => Ok(Operation::TvmStore(TvmRegister::Pv)),
+            "pmt=" => Ok(Operation::TvmStore(TvmRegister::Pmt)),
+            "fv=" => Ok(Operation::TvmStore(TvmRegister::Fv)),
+            "n?" => Ok(Operation::TvmSolve(TvmRegister::N)),
+            "i?" => Ok(Operation::TvmSolve(TvmRegister::I)),
+            "pv?" => Ok(Operation::TvmSolve(TvmRegister::Pv)),
+            "pmt?" => Ok(Operation::TvmSolve(TvmRegister::Pmt)),
+            "fv?" => Ok(Operation::TvmSolve(TvmRegister::Fv)),
+            "npv" => Ok(Operation::Npv),
+            "irr" => Ok(Operation::Irr),
+            "perm" => Ok(Operation::ShowPermissions),
+            "bytes" => Ok(Operation::ShowBytes),
+            "dump" => Ok(Operation::Dump),
+            "explain" => Ok(Operation::Explain),
+            // The last answer, like on a pocket calculator.
+            "ans" => Ok(Operation::RecallResult(1)),
+            "fromunix" => Ok(Operation::FromUnix),
+            "tounix" => Ok(Operation::ToUnix),
             "swap" => Ok(Operation::Swap),
+            "dup" => Ok(Operation::Dup),
+            "drop" => Ok(Operation::Drop),
+            "clear" => Ok(Operation::Clear),
+            "print" => Ok(Operation::Print),
+            "paste" => Ok(Operation::Paste),
+            "int" => Ok(Operation::ToInteger),
+            "hypot" => Ok(Operation::Hypot),
+            "atan2" => Ok(Operation::Atan2),
+            "logb" => Ok(Operation::LogBase),
+            "exp" => Ok(Operation::Exp),
+            "exp2" => Ok(Operation::Exp2),
+            "expm1" => Ok(Operation::ExpM1),
+            "ln1p" => Ok(Operation::Ln1p),
+            "ulp" => Ok(Operation::Ulp),
+            "nextup" => Ok(Operation::NextUp),
+            "nextdown" => Ok(Operation::NextDown),
+            "isqrt" => Ok(Operation::IntSqrt),
+            "ispow2" => Ok(Operation::IsPowerOfTwo),
+            "log2i" => Ok(Operation::IntLog2),
+            "tohms" => Ok(Operation::ToHms),
+            "todms" => Ok(Operation::ToDms),
+            "torect" => Ok(Operation::ToRect),
+            "topolar" => Ok(Operation::ToPolar),
+            "fromdms" => Ok(Operation::FromDms),
+            "ncr" => Ok(Operation::Combinations),
+            "npr" => Ok(Operation::Permutations),
+            "s+" => Ok(Operation::StatsAdd),
+            "s2+" => Ok(Operation::StatsAddPair),
+            "sn" => Ok(Operation::StatsCount),
+            "smean" => Ok(Operation::StatsMean),
+            "sstd" => Ok(Operation::StatsStdDev),
+            "sclear" => Ok(Operation::StatsClear),
+            "lr" => Ok(Operation::LinearRegression),
+            "predict" => Ok(Operation::Predict),
+            "range" => Ok(Operation::Range),
+            "iota" => Ok(Operation::Iota),
+            "plot" => Ok(Operation::Plot),
+            "spark" => Ok(Operation::Sparkline),
+            "vars" => Ok(Operation::ListVariables),
+            "assert-eq" => Ok(Operation::AssertEq),
+            "assert-near" => Ok(Operation::AssertNear),
+            "==" => Ok(Operation::Equal),
+            "clamp" => Ok(Operation::Clamp),
+            "between?" => Ok(Operation::Between),
+            "!=" => Ok(Operation::NotEqual),
+            "deg" => Ok(Operation::SetAngleMode(AngleMode::Degrees)),
+            "rad" => Ok(Operation::SetAngleMode(AngleMode::Radians)),
+            // Environment variables can't start with a digit, so
+            // these are results.
+            _ if token.len() > 1
+                && token.starts_with('$')
+                && token[1..].bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                Ok(Operation::RecallResult(parse_argument(&token[1..])?))
+            }
+            _ if token.len() > 1 && token.starts_with('$') => {
+                Ok(Operation::PushEnv(token[1..].to_string()))
+            }
             _ => Ok(Operation::Push(parse_value(token)?)),
         }
     }
 }
 
+impl FromStr for RoundingMode {
+    type Err = ParseError;
+
+    fn from_str(token: &str) -> Result<Self, ParseError> {
+        match token {
+            "half-even" => Ok(RoundingMode::HalfEven),
+            "half-up" => Ok(RoundingMode::HalfUp),
+            "toward-zero" => Ok(RoundingMode::TowardZero),
+            "floor" => Ok(RoundingMode::Floor),
+            "ceiling" => Ok(RoundingMode::Ceiling),
+            _ => Err(ParseError::InvalidToken(token.to_string())),
+        }
+    }
+}
+
+impl FromStr for OverflowMode {
+    type Err = ParseError;
+
+    fn from_str(token: &str) -> Result<Self, ParseError> {
+        match token {
+            "error" => Ok(OverflowMode::Error),
+            "promote" => Ok(OverflowMode::Promote),
+            "mask" => Ok(OverflowMode::Mask),
+            _ => Err(ParseError::InvalidToken(token.to_string())),
+        }
+    }
+}
+
+/// The names of the categories in the `catalog`.
+const CATEGORY_NAMES: &[(Category, &str)] = &[
+    (Category::Arithmetic, "arithmetic"),
+    (Category::Bitwise, "bitwise"),
+    (Category::Stack, "stack"),
+    (Category::Memory, "memory"),
+    (Category::Programs, "programs"),
+    (Category::Conversion, "conversion"),
+    (Category::Display, "display"),
+    (Category::Networks, "networks"),
+    (Category::Time, "time"),
+    (Category::Statistics, "statistics"),
+    (Category::Input, "input"),
+    (Category::Testing, "testing"),
+    (Category::Finance, "finance"),
+];
+
+impl FromStr for Category {
+    type Err = ParseError;
+
+    fn from_str(token: &str) -> Result<Self, ParseError> {
+        CATEGORY_NAMES
+            .iter()
+            .find(|(_, name)| *name == token)
+            .map(|(category, _)| *category)
+            .ok_or_else(|| ParseError::InvalidToken(token.to_string()))
+    }
+}
+
+impl std::fmt::Display for Category {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        let (_, name) = CATEGORY_NAMES.iter().find(|(c, _)| c == self).unwrap();
+
+        write!(f, "{}", name)
+    }
+}
+
+impl std::fmt::Display for TvmRegister {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            TvmRegister::N => write!(f, "n"),
+            TvmRegister::I => write!(f, "i"),
+            TvmRegister::Pv => write!(f, "pv"),
+            TvmRegister::Pmt => write!(f, "pmt"),
+            TvmRegister::Fv => write!(f, "fv"),
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Type::Integer => write!(f, "integer"),
+            Type::Big => write!(f, "big integer"),
+            Type::Float => write!(f, "float"),
+            Type::Money => write!(f, "money"),
+        }
+    }
+}
+
+/// An example of every operation for the `catalog`. The first word is
+/// the name of the operation.
+const EXAMPLES: &[&str] = &[
+    "+",
+    "-",
+    "*",
+    "/",
+    "divmod",
+    "round2",
+    "hypot",
+    "atan2",
+    "logb",
+    "exp",
+    "exp2",
+    "expm1",
+    "ln1p",
+    "ulp",
+    "nextup",
+    "nextdown",
+    "isqrt",
+    "ispow2",
+    "log2i",
+    "ncr",
+    "npr",
+    "&",
+    "|",
+    "^",
+    "~",
+    "<<",
+    ">>",
+    "dump",
+    "decode en:0",
+    "swap",
+    "dup",
+    "drop",
+    "clear",
+    "print",
+    "range",
+    "iota",
+    "keep on",
+    "sto x",
+    "sto+ x",
+    "sto- x",
+    "sto* x",
+    "sto/ x",
+    "rcl x",
+    "vars",
+    "snap s",
+    "restore s",
+    "diff s t",
+    "res 1",
+    "$1",
+    "ans",
+    "explain",
+    "def f [ ]",
+    "run f",
+    "plotf f",
+    "integ f",
+    "deriv f",
+    "solve f",
+    "solvein f",
+    "int",
+    "rounding half-even",
+    "overflow error",
+    "demote on",
+    "tohms",
+    "todms",
+    "fromdms",
+    "torect",
+    "topolar",
+    "dec",
+    "hex",
+    "bin",
+    "ip",
+    "inbase 16",
+    "prec 0",
+    "group 0",
+    "pad 0",
+    "frac on",
+    "si on",
+    "types on",
+    "deg",
+    "rad",
+    "plot",
+    "spark",
+    "bytes",
+    "perm",
+    "catalog",
+    "netmask",
+    "broadcast",
+    "hosts",
+    "in-subnet?",
+    "fromunix",
+    "tounix",
+    "tz local",
+    "s+",
+    "s2+",
+    "sn",
+    "smean",
+    "sstd",
+    "sclear",
+    "lr",
+    "predict",
+    "readf FILE",
+    "paste",
+    "$NAME",
+    "col 2",
+    "assert-eq",
+    "assert-near",
+    "==",
+    "!=",
+    "clamp",
+    "between?",
+    "eps 1e-9",
+    "pmt=",
+    "pmt?",
+    "npv",
+    "irr",
+];
+
+/// The names of all operations by category.
+pub fn catalog() -> BTreeMap<Category, Vec<&'static str>> {
+    let mut catalog = BTreeMap::<Category, Vec<&str>>::new();
+
+    for example in EXAMPLES {
+        if let Ok([op]) = parse(example).as_deref() {
+            let name = example.split_whitespace().next().unwrap_or(example);
+
+            catalog.entry(op.category()).or_default().push(name);
+        }
+    }
+
+    catalog
+}
+
+/// The number formats of locales by language or language and
+/// territory. The first match wins.
+const LOCALES: &[(&str, char, Option<char>)] = &[
+    ("de_CH", '.', Some('\'')),
+    ("en", '.', Some(',')),
+    ("de", ',', Some('.')),
+    ("es", ',', Some('.')),
+    ("it", ',', Some('.')),
+    ("nl", ',', Some('.')),
+    ("pt", ',', Some('.')),
+    ("fr", ',', Some(' ')),
+    ("pl", ',', Some(' ')),
+    ("ru", ',', Some(' ')),
+    ("sv", ',', Some(' ')),
+];
+
+impl FromStr for Locale {
+    type Err = ParseError;
+
+    /// Parse a locale name like `de`, `de_DE` or `de_DE.UTF-8`. `C`
+    /// and `POSIX` are the plain default.
+    fn from_str(name: &str) -> Result<Self, ParseError> {
+        let name = name.split('.').next().unwrap_or(name);
+
+        if name == "C" || name == "POSIX" {
+            return Ok(Locale::default());
+        }
+
+        LOCALES
+            .iter()
+            .find(|(prefix, _, _)| name == *prefix || name.starts_with(&format!("{}_", prefix)))
+            .map(|&(_, decimal, group)| Locale { decimal, group })
+            .ok_or_else(|| ParseError::InvalidToken(name.to_string()))
+    }
+}
+
+impl FromStr for Zone {
+    type Err = ParseError;
+
+    fn from_str(token: &str) -> Result<Self, ParseError> {
+        match token {
+            "local" => Ok(Zone::Local),
+            _ => token
+                .parse()
+                .map(Zone::Named)
+                .map_err(|_| ParseError::InvalidToken(token.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Zone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Zone::Local => write!(f, "local"),
+            Zone::Named(tz) => write!(f, "{}", tz.name()),
+        }
+    }
+}
+
+impl std::fmt::Display for OverflowMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            OverflowMode::Error => write!(f, "error"),
+            OverflowMode::Promote => write!(f, "promote"),
+            OverflowMode::Mask => write!(f, "mask"),
+        }
+    }
+}
+
+impl std::fmt::Display for RoundingMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            RoundingMode::HalfEven => write!(f, "half-even"),
+            RoundingMode::HalfUp => write!(f, "half-up"),
+            RoundingMode::TowardZero => write!(f, "toward-zero"),
+            RoundingMode::Floor => write!(f, "floor"),
+            RoundingMode::Ceiling => write!(f, "ceiling"),
+        }
+    }
+}
+
+impl std::fmt::Display for Radix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Radix::Hex => write!(f, "hex"),
+            Radix::Dec => write!(f, "dec"),
+            Radix::Bin => write!(f, "bin"),
+            Radix::Ip => write!(f, "ip"),
+        }
+    }
+}
+
+impl std::fmt::Display for AngleMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            AngleMode::Degrees => write!(f, "deg"),
+            AngleMode::Radians => write!(f, "rad"),
+        }
+    }
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Operation::Push(v) => write!(f, "{}", format_literal(v)),
+            Operation::Add => write!(f, "+"),
+            Operation::Subtract => write!(f, "-"),
+            Operation::Multiply => write!(f, "*"),
+            Operation::Divide => write!(f, "/"),
+            Operation::DivMod => write!(f, "divmod"),
+            Operation::Round2 => write!(f, "round2"),
+            Operation::BitAnd => write!(f, "&"),
+            Operation::BitOr => write!(f, "|"),
+            Operation::BitXor => write!(f, "^"),
+            Operation::BitNot => write!(f, "~"),
+            Operation::LeftShift => write!(f, "<<"),
+            Operation::RightShift => write!(f, ">>"),
+            Operation::SetRadix(radix) => write!(f, "{}", radix),
+            Operation::Swap => write!(f, "swap"),
+            Operation::Dup => write!(f, "dup"),
+            Operation::Drop => write!(f, "drop"),
+            Operation::Clear => write!(f, "clear"),
+            Operation::Print => write!(f, "print"),
+            Operation::ReadFile(path) => write!(f, "readf {}", path),
+            Operation::Decode(spec) => write!(f, "decode {}", spec),
+            Operation::PushEnv(name) => write!(f, "${}", name),
+            Operation::RecallResult(n) => write!(f, "${}", n),
+            Operation::AssertEq => write!(f, "assert-eq"),
+            Operation::AssertNear => write!(f, "assert-near"),
+            Operation::Equal => write!(f, "=="),
+            Operation::NotEqual => write!(f, "!="),
+            Operation::Clamp => write!(f, "clamp"),
+            Operation::Between => write!(f, "between?"),
+            Operation::SetTolerance(eps) => write!(f, "eps {}", eps),
+            Operation::PushConstant(name) => write!(f, "{}", name),
+            Operation::Snapshot(name) => write!(f, "snap {}", name),
+            Operation::Restore(name) => write!(f, "restore {}", name),
+            Operation::Diff(names) => write!(f, "diff {} {}", names.0, names.1),
+            Operation::Paste => write!(f, "paste"),
+            Operation::SetColumn(column) => write!(f, "col {}", column),
+            Operation::SetInputBase(base) => write!(f, "inbase {}", base),
+            Operation::SetPrecision(digits) => write!(f, "prec {}", digits),
+            Operation::SetDigitGroup(size) => write!(f, "group {}", size),
+            Operation::SetPadding(bits) => write!(f, "pad {}", bits),
+            Operation::ToInteger => write!(f, "int"),
+            Operation::SetRounding(mode) => write!(f, "rounding {}", mode),
+            Operation::SetOverflow(mode) => write!(f, "overflow {}", mode),
+            Operation::SetDemotion(on) => write!(f, "demote {}", if *on { "on" } else { "off" }),
+            Operation::SetFractionDisplay(on) => {
+                write!(f, "frac {}", if *on { "on" } else { "off" })
+            }
+            Operation::SetSiDisplay(on) => write!(f, "si {}", if *on { "on" } else { "off" }),
+            Operation::SetTypeDisplay(on) => {
+                write!(f, "types {}", if *on { "on" } else { "off" })
+            }
+            Operation::SetKeep(on) => write!(f, "keep {}", if *on { "on" } else { "off" }),
+            Operation::Hypot => write!(f, "hypot"),
+            Operation::Atan2 => write!(f, "atan2"),
+            Operation::LogBase => write!(f, "logb"),
+            Operation::SetAngleMode(mode) => write!(f, "{}", mode),
+            Operation::Exp => write!(f, "exp"),
+            Operation::Exp2 => write!(f, "exp2"),
+            Operation::ExpM1 => write!(f, "expm1"),
+            Operation::Ln1p => write!(f, "ln1p"),
+            Operation::Ulp => write!(f, "ulp"),
+            Operation::IntSqrt => write!(f, "isqrt"),
+            Operation::IsPowerOfTwo => write!(f, "ispow2"),
+            Operation::IntLog2 => write!(f, "log2i"),
+            Operation::NextUp => write!(f, "nextup"),
+            Operation::NextDown => write!(f, "nextdown"),
+            Operation::ToHms => write!(f, "tohms"),
+            Operation::ToDms => write!(f, "todms"),
+            Operation::Netmask => write!(f, "netmask"),
+            Operation::Broadcast => write!(f, "broadcast"),
+            Operation::Hosts => write!(f, "hosts"),
+            Operation::InSubnet => write!(f, "in-subnet?"),
+            Operation::TvmStore(register) => write!(f, "{}=", register),
+            Operation::TvmSolve(register) => write!(f, "{}?", register),
+            Operation::Npv => write!(f, "npv"),
+            Operation::Irr => write!(f, "irr"),
+            Operation::ShowPermissions => write!(f, "perm"),
+            Operation::ShowBytes => write!(f, "bytes"),
+            Operation::Dump => write!(f, "dump"),
+            Operation::Explain => write!(f, "explain"),
+            Operation::Catalog(None) => write!(f, "catalog"),
+            Operation::Catalog(Some(category)) => write!(f, "catalog {}", category),
+            Operation::SetZone(zone) => write!(f, "tz {}", zone),
+            Operation::FromUnix => write!(f, "fromunix"),
+            Operation::ToUnix => write!(f, "tounix"),
+            Operation::ToRect => write!(f, "torect"),
+            Operation::ToPolar => write!(f, "topolar"),
+            Operation::FromDms => write!(f, "fromdms"),
+            Operation::Combinations => write!(f, "ncr"),
+            Operation::Permutations => write!(f, "npr"),
+            Operation::StatsAdd => write!(f, "s+"),
+            Operation::StatsAddPair => write!(f, "s2+"),
+            Operation::StatsCount => write!(f, "sn"),
+            Operation::StatsMean => write!(f, "smean"),
+            Operation::StatsStdDev => write!(f, "sstd"),
+            Operation::StatsClear => write!(f, "sclear"),
+            Operation::LinearRegression => write!(f, "lr"),
+            Operation::Predict => write!(f, "predict"),
+            Operation::Range => write!(f, "range"),
+            Operation::Iota => write!(f, "iota"),
+            Operation::Plot => write!(f, "plot"),
+            Operation::Sparkline => write!(f, "spark"),
+            Operation::Define(name, ops) => {
+                write!(f, "def {} [", name)?;
+                for op in ops {
+                    write!(f, " {}", op)?;
+                }
+                write!(f, " ]")
+            }
+            Operation::Block(ops) => {
+                write!(f, "(")?;
+                for op in ops {
+                    write!(f, " {}", op)?;
+                }
+                write!(f, " )")
+            }
+            Operation::Run(name) => write!(f, "run {}", name),
+            Operation::PlotFunction(name) => write!(f, "plotf {}", name),
+            Operation::Integrate(name) => write!(f, "integ {}", name),
+            Operation::Derive(name) => write!(f, "deriv {}", name),
+            Operation::Solve(name) => write!(f, "solve {}", name),
+            Operation::SolveIn(name) => write!(f, "solvein {}", name),
+            Operation::Store(name) => write!(f, "sto {}", name),
+            Operation::StoreWith(name, op) => write!(f, "sto{} {}", op, name),
+            Operation::Recall(name) => write!(f, "rcl {}", name),
+            Operation::ListVariables => write!(f, "vars"),
+        }
+    }
+}
+
+/// Parse the argument of a command.
+fn parse_argument<T: FromStr>(token: &str) -> Result<T, ParseError> {
+    T::from_str(token).map_err(|_| ParseError::InvalidToken(token.to_string()))
+}
+
+/// Parse a byte size like `1.5GiB` or `3MB` into bytes.
+fn parse_byte_size(token: &str) -> Option<Value> {
+    lazy_static! {
+        static ref BYTES_RE: Regex =
+            Regex::new("^([0-9]+(\\.[0-9]+)?)(?:([kKMGTPE])(i?))?B$").unwrap();
+    }
+
+    let c = BYTES_RE.captures(token)?;
+    let prefix = c.get(3).map_or("", |p| p.as_str());
+    let base: f64 = if c.get(4).is_some_and(|i| !i.as_str().is_empty()) {
+        1024.0
+    } else {
+        1000.0
+    };
+    let exponent = match prefix.to_ascii_uppercase().as_str() {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        "P" => 5,
+        _ => 6,
+    };
+    let bytes = f64::from_str(&c[1]).ok()? * base.powi(exponent);
+
+    // Whole bytes are integers.
+    if bytes.fract() == 0.0 && bytes < i64::MAX as f64 {
+        Some(Value::Integer(bytes as i64))
+    } else {
+        Some(Value::Float(bytes))
+    }
+}
+
+/// Parse permissions like `rwxr-x---` into the file mode. The file
+/// type of `ls -l` output, like the `d` in `drwxr-xr-x`, is ignored.
+fn parse_permissions(token: &str) -> Option<i64> {
+    lazy_static! {
+        static ref PERM_RE: Regex =
+            Regex::new("^[-dlcbps]?([r-][w-][xsS-][r-][w-][xsS-][r-][w-][xtT-])$").unwrap();
+    }
+
+    let bits = PERM_RE.captures(token)?.get(1)?.as_str();
+
+    Some(bits.chars().enumerate().fold(0, |mode, (i, c)| {
+        // The bit for r, w or x at this position.
+        let bit = 1 << (8 - i);
+        // The setuid, setgid or sticky bit of this class.
+        let special = 1 << (11 - i / 3);
+
+        mode | match c {
+            'r' | 'w' | 'x' => bit,
+            's' | 't' => bit | special,
+            'S' | 'T' => special,
+            _ => 0,
+        }
+    }))
+}
+
+/// Parse a subnet in CIDR notation into address and prefix length.
+fn parse_cidr(token: &str) -> Option<(i64, i64)> {
+    let (address, prefix) = token.split_once('/')?;
+    let prefix = prefix.parse().ok().filter(|p| (0..=32).contains(p))?;
+
+    Some((parse_ipv4(address)?, prefix))
+}
+
+/// Parse the argument of a command that turns something on or off.
+fn parse_switch(token: &str) -> Result<bool, ParseError> {
+    match token {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(ParseError::InvalidToken(token.to_string())),
+    }
+}
+
+/// Parse a variable name. Names look like identifiers in most
+/// programming languages.
+fn parse_name(token: &str) -> Result<String, ParseError> {
+    lazy_static! {
+        static ref NAME_RE: Regex = Regex::new("^[a-zA-Z_][a-zA-Z0-9_]*$").unwrap();
+    }
+
+    if NAME_RE.is_match(token) {
+        Ok(token.to_string())
+    } else {
+        Err(ParseError::InvalidToken(token.to_string()))
+    }
+}
+
+/// Format a value so that parsing it yields the same value again.
+pub fn format_literal(v: &Value) -> String {
+    match v {
+        Value::Integer(i) => format!("{}", i),
+        Value::Big(b) => format!("{}", b),
+        // The debug format always has a decimal point or exponent, so
+        // floats stay floats.
+        Value::Float(f) => format!("{:?}", f),
+        Value::Money { .. } => v.to_string(),
+    }
+}
+
+/// A range of bytes in a line of input.
+pub type Span = Range<usize>;
+
+/// Split a line into whitespace-separated tokens and their position.
+fn tokens(input: &str) -> impl Iterator<Item = (Span, &str)> + Clone {
+    input.split_whitespace().map(move |token| {
+        let start = token.as_ptr() as usize - input.as_ptr() as usize;
+
+        (start..start + token.len(), token)
+    })
+}
+
+/// The part of `input` from the first to the last of `tokens`, which
+/// are slices of it, and where that part starts.
+fn enclosing<'a>(input: &'a str, tokens: &[&str]) -> (usize, &'a str) {
+    let offset = |t: &str| t.as_ptr() as usize - input.as_ptr() as usize;
+
+    match (tokens.first(), tokens.last()) {
+        (Some(first), Some(last)) => {
+            let start = offset(first);
+            (start, &input[start..offset(last) + last.len()])
+        }
+        _ => (0, ""),
+    }
+}
+
 /// Parse a single line of input into a sequence of calculator
 /// operations.
 pub fn parse(input: &str) -> Result<Vec<Operation>, ParseError> {
+    parse_spanned(input)
+        .map(|ops| ops.into_iter().map(|(op, _)| op).collect())
+        .map_err(|(e, _)| e)
+}
+
+/// Like [parse], but also returns where each operation or error
+/// is in the input.
+pub fn parse_spanned(input: &str) -> Result<Vec<(Operation, Span)>, (ParseError, Span)> {
+    parse_spanned_with(input, 10, OverflowMode::Error, |_| false)
+}
+
+/// Like [parse_spanned], but bare integer literals are read in
+/// `input_base` until `inbase` changes it, and words for which
+/// `is_constant` returns true push the constant of that name.
+/// Built-in operations win over constants. Integer literals beyond 64
+/// bits are only accepted when `overflow` (or `overflow` in the input)
+/// promotes.
+pub fn parse_spanned_with(
+    input: &str,
+    input_base: u32,
+    overflow: OverflowMode,
+    is_constant: impl Fn(&str) -> bool,
+) -> Result<Vec<(Operation, Span)>, (ParseError, Span)> {
+    parse_tokens(input, input_base, overflow, &is_constant)
+}
+
+/// The implementation of [parse_spanned_with]. Blocks parse their
+/// contents recursively, which needs a single type for `is_constant`.
+fn parse_tokens(
+    input: &str,
+    mut input_base: u32,
+    mut overflow: OverflowMode,
+    is_constant: &dyn Fn(&str) -> bool,
+) -> Result<Vec<(Operation, Span)>, (ParseError, Span)> {
+    let mut tokens = tokens(input).peekable();
+    let mut ops = vec![];
+    // Stack-effect comments with the number of operations before
+    // them. They are checked against the operations after them, up to
+    // the next comment or the end of the line.
+    let mut comments = vec![];
+
+    while let Some((span, token)) = tokens.next() {
+        // Parentheses with `--` are a comment like `( a b -- c )`
+        // instead of a block.
+        if token == "(" {
+            let comment: Vec<_> = tokens
+                .clone()
+                .map_while(|(s, t)| (t != "(" && t != ")").then_some((s, t)))
+                .collect();
+            let closed = tokens.clone().nth(comment.len()).filter(|(_, t)| *t == ")");
+
+            if let (Some((end, _)), Some(dashes)) =
+                (closed, comment.iter().position(|(_, t)| *t == "--"))
+            {
+                let declared = StackEffect {
+                    pops: dashes,
+                    pushes: comment.len() - dashes - 1,
+                };
+                let span = span.start..end.end;
+
+                comments.push((ops.len(), declared, input[span.clone()].to_string(), span));
+                tokens.nth(comment.len());
+                continue;
+            }
+        }
+
+        // The category of the catalog is optional.
+        if token == "catalog" {
+            let category = tokens.next_if(|(_, t)| t.parse::<Category>().is_ok());
+            let end = category.as_ref().map_or(span.end, |(s, _)| s.end);
+
+            ops.push((
+                Operation::Catalog(category.and_then(|(_, t)| t.parse().ok())),
+                span.start..end,
+            ));
+            continue;
+        }
+
+        // Subnets like 10.0.0.0/8 push the address and the prefix
+        // length.
+        if let Some((address, prefix)) = parse_cidr(token) {
+            ops.push((Operation::Push(Value::Integer(address)), span.clone()));
+            ops.push((Operation::Push(Value::Integer(prefix)), span));
+            continue;
+        }
+
+        let mut end = span.end;
+        // Where in a block or definition parsing failed.
+        let mut inner = None;
+        // Commands that take the next token as their argument.
+        let mut argument = || match tokens.next() {
+            Some((arg_span, arg)) => {
+                end = arg_span.end;
+                Ok(arg)
+            }
+            None => Err(ParseError::MissingArgument(token.to_string())),
+        };
+
+        let op = match token {
+            "readf" => argument().map(|a| Operation::ReadFile(a.to_string())),
+            "decode" => argument().map(|a| Operation::Decode(a.to_string())),
+            "prec" => argument()
+                .and_then(parse_argument)
+                .map(Operation::SetPrecision),
+            "group" => argument()
+                .and_then(parse_argument)
+                .map(Operation::SetDigitGroup),
+            "pad" => argument()
+                .and_then(parse_argument)
+                .map(Operation::SetPadding),
+            "inbase" => argument().and_then(parse_argument).and_then(|base: u32| {
+                if (2..=36).contains(&base) {
+                    input_base = base;
+                    Ok(Operation::SetInputBase(base))
+                } else {
+                    Err(ParseError::InvalidToken(base.to_string()))
+                }
+            }),
+            "rounding" => argument().and_then(str::parse).map(Operation::SetRounding),
+            "overflow" => argument().and_then(str::parse).map(|mode| {
+                overflow = mode;
+                Operation::SetOverflow(mode)
+            }),
+            "demote" => argument()
+                .and_then(parse_switch)
+                .map(Operation::SetDemotion),
+            "frac" => argument()
+                .and_then(parse_switch)
+                .map(Operation::SetFractionDisplay),
+            "si" => argument()
+                .and_then(parse_switch)
+                .map(Operation::SetSiDisplay),
+            "types" => argument()
+                .and_then(parse_switch)
+                .map(Operation::SetTypeDisplay),
+            "keep" => argument().and_then(parse_switch).map(Operation::SetKeep),
+            "eps" => argument().and_then(parse_argument).and_then(|eps: f64| {
+                if eps >= 0.0 {
+                    Ok(Operation::SetTolerance(eps))
+                } else {
+                    Err(ParseError::InvalidToken(eps.to_string()))
+                }
+            }),
+            "col" => argument()
+                .and_then(parse_argument)
+                .map(Operation::SetColumn),
+            "tz" => argument().and_then(str::parse).map(Operation::SetZone),
+            "sto" => argument().and_then(parse_name).map(Operation::Store),
+            "sto+" | "sto-" | "sto*" | "sto/" => Operation::from_str(&token[3..]).and_then(|op| {
+                argument()
+                    .and_then(parse_name)
+                    .map(|name| Operation::StoreWith(name, Box::new(op)))
+            }),
+            "rcl" => argument().and_then(parse_name).map(Operation::Recall),
+            "def" => (|| {
+                let name = parse_name(argument()?)?;
+                let mut body = vec![];
+
+                match argument()? {
+                    "[" => {}
+                    t => return Err(ParseError::InvalidToken(t.to_string())),
+                }
+                loop {
+                    match argument()? {
+                        "]" => break,
+                        t => body.push(t),
+                    }
+                }
+
+                let (offset, text) = enclosing(input, &body);
+                let ops =
+                    parse_tokens(text, input_base, overflow, is_constant).map_err(|(e, s)| {
+                        inner = Some(offset + s.start..offset + s.end);
+                        e
+                    })?;
+                Ok(Operation::Define(
+                    name,
+                    ops.into_iter().map(|(op, _)| op).collect(),
+                ))
+            })(),
+            "(" => (|| {
+                let mut body = vec![];
+                let mut depth = 0;
+
+                // Blocks may be nested.
+                loop {
+                    match argument()? {
+                        ")" if depth == 0 => break,
+                        t => {
+                            match t {
+                                "(" => depth += 1,
+                                ")" => depth -= 1,
+                                _ => {}
+                            }
+                            body.push(t)
+                        }
+                    }
+                }
+
+                let (offset, text) = enclosing(input, &body);
+                let ops =
+                    parse_tokens(text, input_base, overflow, is_constant).map_err(|(e, s)| {
+                        inner = Some(offset + s.start..offset + s.end);
+                        e
+                    })?;
+                Ok(Operation::Block(
+                    ops.into_iter().map(|(op, _)| op).collect(),
+                ))
+            })(),
+            "run" => argument().and_then(parse_name).map(Operation::Run),
+            "plotf" => argument().and_then(parse_name).map(Operation::PlotFunction),
+            "integ" => argument().and_then(parse_name).map(Operation::Integrate),
+            "deriv" => argument().and_then(parse_name).map(Operation::Derive),
+            "solve" => argument().and_then(parse_name).map(Operation::Solve),
+            "solvein" => argument().and_then(parse_name).map(Operation::SolveIn),
+            "snap" => argument().and_then(parse_name).map(Operation::Snapshot),
+            "restore" => argument().and_then(parse_name).map(Operation::Restore),
+            "diff" => argument().and_then(parse_name).and_then(|a| {
+                argument()
+                    .and_then(parse_name)
+                    .map(|b| Operation::Diff(Box::new((a, b))))
+            }),
+            "res" => argument()
+                .and_then(parse_argument)
+                .map(Operation::RecallResult),
+            _ => match parse_in_base(token, input_base) {
+                Some(Err(e)) => Err(e),
+                // Literals that start like words, e.g. `dec` in hex,
+                // lose against operations and constants.
+                Some(Ok(v))
+                    if token
+                        .trim_start_matches(['+', '-'])
+                        .starts_with(|c: char| c.is_ascii_digit()) =>
+                {
+                    Ok(Operation::Push(v))
+                }
+                literal => Operation::from_str(token).or_else(|e| {
+                    if is_constant(token) {
+                        Ok(Operation::PushConstant(token.to_string()))
+                    } else {
+                        literal.and_then(Result::ok).map(Operation::Push).ok_or(e)
+                    }
+                }),
+            },
+        };
+
+        let op = op.and_then(|op| match op {
+            Operation::Push(v) => fits(v, token, overflow).map(Operation::Push),
+            op => Ok(op),
+        });
+        let span = span.start..end;
+        ops.push((op.map_err(|e| (e, inner.unwrap_or(span.clone())))?, span));
+    }
+
+    let starts: Vec<_> = comments.iter().map(|(start, ..)| *start).collect();
+
+    for (i, (start, declared, text, span)) in comments.into_iter().enumerate() {
+        let line_end = input[span.end..]
+            .find('\n')
+            .map_or(input.len(), |n| span.end + n);
+        let end = ops[start..]
+            .iter()
+            .position(|(_, s)| s.start > line_end)
+            .map_or(ops.len(), |n| start + n)
+            .min(starts.get(i + 1).copied().unwrap_or(ops.len()));
+
+        // Operations like `run` or `iota` can't be checked.
+        match StackEffect::of(ops[start..end].iter().map(|(op, _)| op)) {
+            Some(actual) if actual != declared => {
+                return Err((
+                    ParseError::WrongStackEffect {
+                        declared: text,
+                        actual,
+                    },
+                    span,
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    Ok(ops)
+}
+
+/// Keep only the `column`th (from 1) tab-separated field of each line
+/// that has tabs, e.g. of rows copied from a spreadsheet. Lines
+/// without tabs are kept as they are.
+pub fn select_column(input: &str, column: usize) -> String {
+    input
+        .lines()
+        .map(|line| {
+            if line.contains('\t') {
+                line.split('\t').nth(column.saturating_sub(1)).unwrap_or("")
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Parse whitespace-separated numbers, e.g. from a data file. Like in
+/// [parse_spanned_with], integers beyond 64 bits need an `overflow`
+/// mode that promotes.
+pub fn parse_values(input: &str, overflow: OverflowMode) -> Result<Vec<Value>, ParseError> {
     input
         .split_whitespace()
-        .map(|t| Operation::from_str(t))
+        .map(|token| parse_value(token).and_then(|v| fits(v, token, overflow)))
         .collect()
 }
 
+/// Reject integer literals that don't fit into 64 bits, unless
+/// `overflow` promotes them to big integers.
+fn fits(v: Value, token: &str, overflow: OverflowMode) -> Result<Value, ParseError> {
+    match v {
+        Value::Big(_) if overflow != OverflowMode::Promote => {
+            Err(ParseError::InvalidToken(token.to_string()))
+        }
+        v => Ok(v),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_underscores() {
+        assert_eq!(parse_value("0xdead_beef"), Ok(Value::Integer(0xdead_beef)));
+        assert_eq!(parse_value("0b1010_0110"), Ok(Value::Integer(0b1010_0110)));
+        assert!(parse_value("0x_ff").is_err());
+    }
+
+    #[test]
+    fn test_catalog() {
+        for example in EXAMPLES {
+            assert_eq!(parse(example).map(|ops| ops.len()), Ok(1), "{}", example);
+        }
+
+        assert_eq!(catalog().len(), CATEGORY_NAMES.len());
+        assert_eq!(
+            parse("catalog time catalog 1").unwrap(),
+            vec![
+                Operation::Catalog(Some(Category::Time)),
+                Operation::Catalog(None),
+                Operation::Push(Value::Integer(1))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_byte_sizes() {
+        assert_eq!(parse_value("1.5GiB"), Ok(Value::Integer(1_610_612_736)));
+        assert_eq!(parse_value("3MB"), Ok(Value::Integer(3_000_000)));
+        assert_eq!(parse_value("4kB"), Ok(Value::Integer(4000)));
+        assert_eq!(parse_value("512B"), Ok(Value::Integer(512)));
+        assert_eq!(parse_value("100B"), Ok(Value::Integer(100)));
+        assert_eq!(parse_value("10B"), Ok(Value::Integer(10)));
+        assert_eq!(parse_value("1010b"), Ok(Value::Integer(10)));
+        assert_eq!(parse_value("1.5B"), Ok(Value::Float(1.5)));
+        assert!(parse_value("3iB").is_err());
+        assert!(parse_value("3XB").is_err());
+    }
+
+    #[test]
+    fn test_permissions() {
+        assert_eq!(parse_value("rwxr-x---"), Ok(Value::Integer(0o750)));
+        assert_eq!(parse_value("-rw-r--r--"), Ok(Value::Integer(0o644)));
+        assert_eq!(parse_value("drwsr-sr-t"), Ok(Value::Integer(0o7755)));
+        assert_eq!(parse_value("rwSr--r-T"), Ok(Value::Integer(0o5644)));
+        assert!(parse_value("rwxr-x--").is_err());
+        assert!(parse_value("wrxr-x---").is_err());
+    }
+
+    #[test]
+    fn test_ipv4() {
+        assert_eq!(parse_value("10.0.0.1"), Ok(Value::Integer(0x0a00_0001)));
+        assert_eq!(
+            parse("10.1.0.0/16 in-subnet?"),
+            Ok(vec![
+                Operation::Push(Value::Integer(0x0a01_0000)),
+                Operation::Push(Value::Integer(16)),
+                Operation::InSubnet
+            ])
+        );
+        assert!(parse("10.1.0.0/33").is_err());
+        assert!(parse("10.1.0/16").is_err());
+    }
+
+    #[test]
+    fn test_suffixes() {
+        assert_eq!(parse_value("0FFh"), Ok(Value::Integer(255)));
+        assert_eq!(parse_value("10H"), Ok(Value::Integer(16)));
+        assert_eq!(parse_value("1010b"), Ok(Value::Integer(10)));
+        assert_eq!(parse_value("777o"), Ok(Value::Integer(511)));
+        assert!(parse_value("FFh").is_err());
+        assert!(parse_value("129b").is_err());
+        assert!(parse_value("8o").is_err());
+    }
+
+    #[test]
+    fn test_input_base() {
+        let push = |i| Operation::Push(Value::Integer(i));
+        let parse_in = |line, base| {
+            parse_spanned_with(line, base, OverflowMode::Error, |name| name == "c")
+                .map(|ops| ops.into_iter().map(|(op, _)| op).collect::<Vec<_>>())
+                .map_err(|(e, _)| e)
+        };
+
+        assert_eq!(
+            parse_in("ff 10 -1f #10 d10 0x10 1.5", 16),
+            Ok(vec![
+                push(255),
+                push(16),
+                push(-31),
+                push(10),
+                push(10),
+                push(16),
+                Operation::Push(Value::Float(1.5))
+            ])
+        );
+        // Words win over literals that start like them.
+        assert_eq!(
+            parse_in("dec c 0dec", 16),
+            Ok(vec![
+                Operation::SetRadix(Radix::Dec),
+                Operation::PushConstant("c".to_string()),
+                push(0xdec)
+            ])
+        );
+        assert_eq!(
+            parse_in("10 inbase 2 10 def f [ 11 ]", 8),
+            Ok(vec![
+                push(8),
+                Operation::SetInputBase(2),
+                push(2),
+                Operation::Define("f".to_string(), vec![push(3)])
+            ])
+        );
+        // Digits beyond the base are not read as decimal.
+        assert_eq!(
+            parse_in("17 19", 8),
+            Err(ParseError::InvalidToken("19".to_string()))
+        );
+        assert_eq!(
+            parse_in("101 5", 2),
+            Err(ParseError::InvalidToken("5".to_string()))
+        );
+        assert_eq!(
+            parse_in("101 #5 d5", 2),
+            Ok(vec![push(5), push(5), push(5)])
+        );
+        assert_eq!(parse_in("0FFh 4KiB", 8), Ok(vec![push(255), push(4096)]));
+        assert_eq!(
+            parse("10 d10"),
+            Err(ParseError::InvalidToken("d10".to_string()))
+        );
+        assert_eq!(
+            parse("inbase 37"),
+            Err(ParseError::InvalidToken("37".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_hms() {
+        assert_eq!(parse_value("1:30:15"), Ok(Value::Integer(5415)));
+        assert_eq!(parse_value("-0:00:01"), Ok(Value::Integer(-1)));
+        assert_eq!(parse_value("0:01:02.5"), Ok(Value::Float(62.5)));
+        assert!(parse_value("1:60:00").is_err());
+        assert!(parse_value("1:30").is_err());
+    }
+
+    #[test]
+    fn test_dms() {
+        assert_eq!(parse_value("1°30'36\""), Ok(Value::Float(1.51)));
+        assert_eq!(parse_value("-12°30'"), Ok(Value::Float(-12.5)));
+        assert_eq!(parse_value("90°"), Ok(Value::Float(90.0)));
+        assert_eq!(parse_value("0°0'4.5\""), Ok(Value::Float(0.00125)));
+        assert!(parse_value("1°60'").is_err());
+        assert!(parse_value("1°30").is_err());
+    }
+
+    #[test]
+    fn test_spans() {
+        assert_eq!(
+            parse_spanned(" 1  sto x +"),
+            Ok(vec![
+                (Operation::Push(Value::Integer(1)), 1..2),
+                (Operation::Store("x".to_string()), 4..9),
+                (Operation::Add, 10..11)
+            ])
+        );
+        assert_eq!(
+            parse_spanned("1 2 foo"),
+            Err((ParseError::InvalidToken("foo".to_string()), 4..7))
+        );
+        assert_eq!(
+            parse_spanned("1 prec x"),
+            Err((ParseError::InvalidToken("x".to_string()), 2..8))
+        );
+    }
+
     #[test]
     fn test_single() {
         assert_eq!(Operation::from_str("+"), Ok(Operation::Add));
@@ -119,11 +1410,19 @@ mod tests {
             Ok(Operation::Push(Value::Float(16.0)))
         );
 
+        assert_eq!(
+            Operation::from_str("-1.5e-3"),
+            Ok(Operation::Push(Value::Float(-1.5e-3)))
+        );
+
+        assert_eq!(
+            Operation::from_str("1e3"),
+            Ok(Operation::Push(Value::Float(1000.0)))
+        );
+
         assert_eq!(
             Operation::from_str("13x213!"),
-            Err(ParseError {
-                offending_token: "13x213!".to_string()
-            })
+            Err(ParseError::InvalidToken("13x213!".to_string()))
         );
 
         assert_eq!(
@@ -135,6 +1434,16 @@ mod tests {
             Operation::from_str("0b1000"),
             Ok(Operation::Push(Value::Integer(8)))
         );
+
+        assert_eq!(
+            Operation::from_str("$WIDTH"),
+            Ok(Operation::PushEnv("WIDTH".to_string()))
+        );
+        assert_eq!(Operation::from_str("$2"), Ok(Operation::RecallResult(2)));
+        assert_eq!(
+            Operation::from_str("$"),
+            Err(ParseError::InvalidToken("$".to_string()))
+        );
     }
 
     #[test]
@@ -149,11 +1458,306 @@ mod tests {
             ])
         );
 
+        assert_eq!(
+            parse("1 2 + 0xff hex swap <<")
+                .unwrap()
+                .iter()
+                .map(|op| op.to_string())
+                .collect::<Vec<_>>(),
+            vec!["1", "2", "+", "255", "hex", "swap", "<<"]
+        );
+
         assert_eq!(
             parse("1 2 xxzz! 3"),
-            Err(ParseError {
-                offending_token: "xxzz!".to_string()
-            })
-        )
+            Err(ParseError::InvalidToken("xxzz!".to_string()))
+        );
+
+        assert_eq!(
+            parse("readf data.txt +"),
+            Ok(vec![
+                Operation::ReadFile("data.txt".to_string()),
+                Operation::Add
+            ])
+        );
+        assert_eq!(parse("prec 6"), Ok(vec![Operation::SetPrecision(6)]));
+        assert_eq!(parse("group 4"), Ok(vec![Operation::SetDigitGroup(4)]));
+        assert_eq!(parse("pad 32"), Ok(vec![Operation::SetPadding(32)]));
+        assert_eq!(
+            parse("prec -1"),
+            Err(ParseError::InvalidToken("-1".to_string()))
+        );
+
+        assert_eq!(
+            parse("rounding half-even int"),
+            Ok(vec![
+                Operation::SetRounding(RoundingMode::HalfEven),
+                Operation::ToInteger
+            ])
+        );
+        assert_eq!(
+            parse("overflow promote"),
+            Ok(vec![Operation::SetOverflow(OverflowMode::Promote)])
+        );
+        assert_eq!(
+            parse("99999999999999999999"),
+            Err(ParseError::InvalidToken("99999999999999999999".to_string()))
+        );
+        assert_eq!(
+            parse("overflow promote 99999999999999999999"),
+            Ok(vec![
+                Operation::SetOverflow(OverflowMode::Promote),
+                Operation::Push(Value::from(BigInt::from(10).pow(20) - 1))
+            ])
+        );
+        assert_eq!(
+            parse("overflow mask"),
+            Ok(vec![Operation::SetOverflow(OverflowMode::Mask)])
+        );
+        assert_eq!(
+            parse("demote on frac on si on demote off"),
+            Ok(vec![
+                Operation::SetDemotion(true),
+                Operation::SetFractionDisplay(true),
+                Operation::SetSiDisplay(true),
+                Operation::SetDemotion(false)
+            ])
+        );
+        assert_eq!(
+            parse("demote yes"),
+            Err(ParseError::InvalidToken("yes".to_string()))
+        );
+
+        assert_eq!(
+            parse("tz Europe/Berlin tz local"),
+            Ok(vec![
+                Operation::SetZone(Zone::Named(chrono_tz::Europe::Berlin)),
+                Operation::SetZone(Zone::Local)
+            ])
+        );
+        assert_eq!(
+            parse("tz Mars/Olympus"),
+            Err(ParseError::InvalidToken("Mars/Olympus".to_string()))
+        );
+
+        assert_eq!(
+            parse("sto x_1 rcl x_1"),
+            Ok(vec![
+                Operation::Store("x_1".to_string()),
+                Operation::Recall("x_1".to_string())
+            ])
+        );
+        assert_eq!(
+            parse("sto+ total sto/ total"),
+            Ok(vec![
+                Operation::StoreWith("total".to_string(), Box::new(Operation::Add)),
+                Operation::StoreWith("total".to_string(), Box::new(Operation::Divide))
+            ])
+        );
+        assert_eq!(parse("res 3"), Ok(vec![Operation::RecallResult(3)]));
+        assert_eq!(parse("ans"), Ok(vec![Operation::RecallResult(1)]));
+        assert_eq!(
+            parse("snap a restore a"),
+            Ok(vec![
+                Operation::Snapshot("a".to_string()),
+                Operation::Restore("a".to_string())
+            ])
+        );
+        assert_eq!(
+            parse("sto 1x"),
+            Err(ParseError::InvalidToken("1x".to_string()))
+        );
+
+        assert_eq!(
+            parse("1 readf"),
+            Err(ParseError::MissingArgument("readf".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_blocks() {
+        assert_eq!(
+            parse_spanned("1 ( 2 ( 3 ) + ) *"),
+            Ok(vec![
+                (Operation::Push(Value::Integer(1)), 0..1),
+                (
+                    Operation::Block(vec![
+                        Operation::Push(Value::Integer(2)),
+                        Operation::Block(vec![Operation::Push(Value::Integer(3))]),
+                        Operation::Add
+                    ]),
+                    2..15
+                ),
+                (Operation::Multiply, 16..17)
+            ])
+        );
+        assert_eq!(
+            parse("( 1 2"),
+            Err(ParseError::MissingArgument("(".to_string()))
+        );
+        assert!(parse("1 )").is_err());
+    }
+
+    #[test]
+    fn test_stack_effect_comments() {
+        assert_eq!(
+            parse_spanned("( a b -- c ) +"),
+            Ok(vec![(Operation::Add, 13..14)])
+        );
+        assert_eq!(
+            parse("def sq [ ( x -- x^2 ) dup * ] ( -- ) ( -- n ) 3 iota"),
+            parse("def sq [ dup * ] 3 iota")
+        );
+        assert_eq!(
+            parse_spanned("1 ( a -- b c ) dup dup"),
+            Err((
+                ParseError::WrongStackEffect {
+                    declared: "( a -- b c )".to_string(),
+                    actual: StackEffect { pops: 1, pushes: 3 }
+                },
+                2..14
+            ))
+        );
+        assert!(matches!(
+            parse("def f [ ( a b -- c ) drop ]"),
+            Err(ParseError::WrongStackEffect { .. })
+        ));
+        // Comments end at the next comment or line.
+        assert!(parse("( -- a ) 1 ( -- b ) 2").is_ok());
+        assert!(parse("( -- x ) 5\n( -- y ) 6").is_ok());
+        assert!(parse("( -- x ) 5\n6 ( a -- ) drop").is_ok());
+        assert_eq!(
+            parse_spanned("( -- x ) 5 6\n7"),
+            Err((
+                ParseError::WrongStackEffect {
+                    declared: "( -- x )".to_string(),
+                    actual: StackEffect { pops: 0, pushes: 2 }
+                },
+                0..8
+            ))
+        );
+        // Without `--`, it is a block.
+        assert_eq!(
+            parse("( a )"),
+            Err(ParseError::InvalidToken("a".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_definitions() {
+        let square = Operation::Define(
+            "sq".to_string(),
+            vec![
+                Operation::Store("x".to_string()),
+                Operation::Recall("x".to_string()),
+                Operation::Recall("x".to_string()),
+                Operation::Multiply,
+            ],
+        );
+
+        assert_eq!(
+            parse_spanned("def sq [ sto x rcl x rcl x * ] 3 run sq"),
+            Ok(vec![
+                (square.clone(), 0..30),
+                (Operation::Push(Value::Integer(3)), 31..32),
+                (Operation::Run("sq".to_string()), 33..39)
+            ])
+        );
+        assert_eq!(square.to_string(), "def sq [ sto x rcl x rcl x * ]");
+        assert_eq!(
+            parse("plotf sq"),
+            Ok(vec![Operation::PlotFunction("sq".to_string())])
+        );
+
+        assert_eq!(
+            parse("def sq [ 1"),
+            Err(ParseError::MissingArgument("def".to_string()))
+        );
+        assert_eq!(
+            parse("def sq 1 ]"),
+            Err(ParseError::InvalidToken("1".to_string()))
+        );
+        assert_eq!(
+            parse("def sq [ foo ]"),
+            Err(ParseError::InvalidToken("foo".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_constants() {
+        let is_constant = |name: &str| name == "f_cpu" || name == "swap";
+
+        assert_eq!(
+            parse_spanned_with("f_cpu 2 / swap", 10, OverflowMode::Error, is_constant),
+            Ok(vec![
+                (Operation::PushConstant("f_cpu".to_string()), 0..5),
+                (Operation::Push(Value::Integer(2)), 6..7),
+                (Operation::Divide, 8..9),
+                (Operation::Swap, 10..14)
+            ])
+        );
+        assert_eq!(
+            parse_spanned_with("f_mem", 10, OverflowMode::Error, is_constant),
+            Err((ParseError::InvalidToken("f_mem".to_string()), 0..5))
+        );
+
+        // Constants work in definitions like everywhere else.
+        assert_eq!(
+            parse_spanned_with("def f [ f_cpu * ]", 10, OverflowMode::Error, is_constant),
+            Ok(vec![(
+                Operation::Define(
+                    "f".to_string(),
+                    vec![
+                        Operation::PushConstant("f_cpu".to_string()),
+                        Operation::Multiply
+                    ]
+                ),
+                0..17
+            )])
+        );
+        // Errors inside point at the token, not at the definition.
+        assert_eq!(
+            parse_spanned_with("def f [ 2 f_mem * ]", 10, OverflowMode::Error, is_constant),
+            Err((ParseError::InvalidToken("f_mem".to_string()), 10..15))
+        );
+        assert_eq!(
+            parse_spanned_with("1 ( ( f_mem ) )", 10, OverflowMode::Error, is_constant),
+            Err((ParseError::InvalidToken("f_mem".to_string()), 6..11))
+        );
+    }
+
+    #[test]
+    fn test_literals() {
+        for v in &[
+            Value::Integer(-5),
+            Value::Float(16.0),
+            Value::Float(-0.1),
+            Value::Float(1e300),
+            Value::Float(2.5e-10),
+        ] {
+            assert_eq!(parse_value(&format_literal(v)), Ok(v.clone()));
+        }
+    }
+
+    #[test]
+    fn test_values() {
+        assert_eq!(
+            parse_values("1\n0x10  2.5\n", OverflowMode::Error),
+            Ok(vec![
+                Value::Integer(1),
+                Value::Integer(16),
+                Value::Float(2.5)
+            ])
+        );
+        assert_eq!(
+            parse_values("1 +", OverflowMode::Error),
+            Err(ParseError::InvalidToken("+".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_select_column() {
+        assert_eq!(select_column("a\t1\tx\nb\t2\n+", 2), "1\n2\n+");
+        assert_eq!(select_column("a\t1\nb", 3), "\nb");
+        assert_eq!(parse("col 2"), Ok(vec![Operation::SetColumn(2)]));
     }
 }
@@ -0,0 +1,210 @@
+//! # Prompt Templates
+//!
+//! The prompt is rendered from a template with placeholders in
+//! braces, e.g. `{radix} {stack} | `.
+//!
+//! Stack values that are new or modified since the previous prompt
+//! can be highlighted, which makes lines with many operations easier
+//! to follow.
+
+use clac::calc::Calculator;
+use clac::types::Value;
+
+pub const DEFAULT_TEMPLATE: &str = "{stack} | ";
+
+/// Bold and green for changed values.
+const HIGHLIGHT_START: &str = "\x1b[1;32m";
+const HIGHLIGHT_END: &str = "\x1b[0m";
+
+/// A placeholder in a prompt template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    /// The stack, optionally only the given number of values on top.
+    Stack(Option<usize>),
+    Depth,
+    Radix,
+    Angle,
+}
+
+/// A parsed prompt template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl std::str::FromStr for Template {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<Self, String> {
+        let mut parts = vec![];
+        let mut rest = template;
+
+        while let Some(open) = rest.find('{') {
+            let close = rest[open..]
+                .find('}')
+                .ok_or_else(|| format!("Unclosed placeholder in: {}", template))?
+                + open;
+
+            parts.push(Part::Text(rest[..open].to_string()));
+            parts.push(match &rest[open + 1..close] {
+                "stack" => Part::Stack(None),
+                "depth" => Part::Depth,
+                "radix" => Part::Radix,
+                "angle" => Part::Angle,
+                p => match p.strip_prefix("stack:").map(str::parse) {
+                    Some(Ok(n)) => Part::Stack(Some(n)),
+                    _ => return Err(format!("Unknown placeholder: {{{}}}", p)),
+                },
+            });
+
+            rest = &rest[close + 1..];
+        }
+
+        parts.push(Part::Text(rest.to_string()));
+        Ok(Template { parts })
+    }
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        DEFAULT_TEMPLATE.parse().unwrap()
+    }
+}
+
+/// The number of values on top of `after` that are new or modified
+/// compared to `before`.
+pub fn changed_values(before: &[Value], after: &[Value]) -> usize {
+    let unchanged = before.iter().zip(after).take_while(|(b, a)| b == a).count();
+
+    after.len() - unchanged
+}
+
+/// Format the `n` values on top of the stack and highlight the
+/// `changed` values on top.
+fn format_highlighted(calc: &Calculator, n: usize, changed: usize) -> String {
+    let stack = calc.stack();
+    let skip = stack.len().saturating_sub(n);
+    let first_changed = stack.len().saturating_sub(changed);
+    let mut tokens: Vec<String> = stack
+        .iter()
+        .enumerate()
+        .skip(skip)
+        .map(|(i, v)| {
+            if i >= first_changed {
+                format!(
+                    "{}{}{}",
+                    HIGHLIGHT_START,
+                    calc.format_annotated(v),
+                    HIGHLIGHT_END
+                )
+            } else {
+                calc.format_annotated(v)
+            }
+        })
+        .collect();
+
+    if skip > 0 {
+        tokens.insert(0, "...".to_string());
+    }
+
+    tokens.join(" ")
+}
+
+impl Template {
+    /// Render the prompt and highlight the `changed` values on top of
+    /// the stack.
+    pub fn render(&self, calc: &Calculator, changed: usize) -> String {
+        let depth = calc.stack().len();
+
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(t) => t.clone(),
+                Part::Stack(n) => format_highlighted(calc, n.unwrap_or(depth), changed),
+                Part::Depth => depth.to_string(),
+                Part::Radix => calc.radix().to_string(),
+                Part::Angle => calc.angle_mode().to_string(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clac::calc::CalculatorError;
+    use clac::types::{Operation, Radix};
+
+    #[test]
+    fn test_render() {
+        let calc = Calculator::new()
+            .push(Value::Integer(1))
+            .push(Value::Integer(2))
+            .push(Value::Integer(3));
+
+        assert_eq!(Template::default().render(&calc, 0), "1 2 3 | ");
+        assert_eq!(
+            "[{radix} {angle}] {depth}: {stack:2} > "
+                .parse::<Template>()
+                .unwrap()
+                .render(&calc, 0),
+            "[dec rad] 3: ... 2 3 > "
+        );
+        assert_eq!(
+            "{stack:5}".parse::<Template>().unwrap().render(&calc, 0),
+            "1 2 3"
+        );
+    }
+
+    #[test]
+    fn test_highlight() {
+        let before = [Value::Integer(1), Value::Integer(2), Value::Integer(3)];
+        let after = [Value::Integer(1), Value::Integer(5), Value::Integer(4)];
+
+        assert_eq!(changed_values(&before, &after), 2);
+        assert_eq!(changed_values(&before, &before), 0);
+        assert_eq!(changed_values(&before, &before[..1]), 0);
+        assert_eq!(changed_values(&[], &after), 3);
+
+        let calc = Calculator::new()
+            .push(Value::Integer(1))
+            .push(Value::Integer(2));
+
+        assert_eq!(
+            Template::default().render(&calc, 1),
+            "1 \x1b[1;32m2\x1b[0m | "
+        );
+        assert_eq!(
+            "{stack:1}".parse::<Template>().unwrap().render(&calc, 5),
+            "... \x1b[1;32m2\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn test_types() -> Result<(), CalculatorError> {
+        let calc = Calculator::new()
+            .push(Value::Integer(42))
+            .push(Value::Float(3.5))
+            .apply(Operation::SetTypeDisplay(true))?;
+
+        assert_eq!(Template::default().render(&calc, 0), "42:i64 3.5:f64 | ");
+
+        let calc = calc
+            .push(Value::Integer(0x1234))
+            .apply(Operation::SetRadix(Radix::Hex))?;
+        assert_eq!(
+            "{stack:2}".parse::<Template>().unwrap().render(&calc, 0),
+            "... 3.5:f64 0x1234:u16"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!("{stack".parse::<Template>().is_err());
+        assert!("{foo}".parse::<Template>().is_err());
+        assert!("{stack:x}".parse::<Template>().is_err());
+    }
+}
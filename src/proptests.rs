@@ -0,0 +1,227 @@
+//! # Property-Based Tests
+//!
+//! These tests check invariants of the calculator engine for random
+//! inputs instead of a few handpicked examples.
+
+use proptest::prelude::*;
+use std::convert::TryFrom;
+
+use crate::calc::Calculator;
+use num_bigint::BigInt;
+
+use crate::types::{
+    AngleMode, Operation, OverflowMode, Radix, RoundingMode, TvmRegister, Value, Zone,
+};
+
+fn value() -> impl Strategy<Value = Value> {
+    prop_oneof![
+        any::<i64>().prop_map(Value::Integer),
+        // Small integers hit the interesting cases of shifts and
+        // combinatorics more often.
+        (-70i64..70).prop_map(Value::Integer),
+        any::<f64>().prop_map(Value::Float),
+    ]
+}
+
+/// All operations that don't talk to the outside world.
+fn operation() -> impl Strategy<Value = Operation> {
+    prop_oneof![
+        value().prop_map(Operation::Push),
+        prop_oneof![
+            Just(Operation::Add),
+            Just(Operation::Subtract),
+            Just(Operation::Multiply),
+            Just(Operation::Divide),
+            Just(Operation::BitAnd),
+            Just(Operation::BitNot),
+            Just(Operation::BitOr),
+            Just(Operation::BitXor),
+            Just(Operation::LeftShift),
+            Just(Operation::RightShift),
+            Just(Operation::SetRadix(Radix::Bin)),
+            Just(Operation::SetRadix(Radix::Hex)),
+            Just(Operation::SetInputBase(16)),
+            Just(Operation::Swap),
+            Just(Operation::Dup),
+            Just(Operation::Drop),
+            Just(Operation::Clear),
+            Just(Operation::Print),
+            Just(Operation::ToInteger),
+            Just(Operation::SetRounding(RoundingMode::HalfEven)),
+            Just(Operation::SetRounding(RoundingMode::Floor)),
+            Just(Operation::SetOverflow(OverflowMode::Promote)),
+            Just(Operation::SetOverflow(OverflowMode::Mask)),
+            Just(Operation::SetDemotion(true)),
+            Just(Operation::SetFractionDisplay(true)),
+            Just(Operation::SetSiDisplay(true)),
+            Just(Operation::SetTypeDisplay(true)),
+            Just(Operation::SetKeep(true)),
+        ],
+        prop_oneof![
+            Just(Operation::Hypot),
+            Just(Operation::Atan2),
+            Just(Operation::LogBase),
+            Just(Operation::SetAngleMode(AngleMode::Degrees)),
+            Just(Operation::Exp),
+            Just(Operation::Exp2),
+            Just(Operation::ExpM1),
+            Just(Operation::Ln1p),
+            Just(Operation::Ulp),
+            Just(Operation::IntSqrt),
+            Just(Operation::IsPowerOfTwo),
+            Just(Operation::IntLog2),
+            Just(Operation::NextUp),
+            Just(Operation::NextDown),
+            Just(Operation::ToHms),
+            Just(Operation::ToDms),
+            Just(Operation::ToRect),
+            Just(Operation::ToPolar),
+            Just(Operation::SetRadix(Radix::Ip)),
+            Just(Operation::Netmask),
+            Just(Operation::Broadcast),
+            Just(Operation::Hosts),
+            Just(Operation::InSubnet),
+            Just(Operation::ShowPermissions),
+            Just(Operation::ShowBytes),
+            Just(Operation::Dump),
+            Just(Operation::Explain),
+            Just(Operation::Catalog(None)),
+            Just(Operation::RecallResult(1)),
+            Just(Operation::Snapshot("s".to_string())),
+            Just(Operation::Restore("s".to_string())),
+            Just(Operation::Diff(Box::new((
+                "s".to_string(),
+                "t".to_string()
+            )))),
+            Just(Operation::FromUnix),
+            Just(Operation::ToUnix),
+            Just(Operation::SetZone(Zone::Named(chrono_tz::UTC))),
+            Just(Operation::Decode("a:3:0,b:63:4".to_string())),
+            Just(Operation::FromDms),
+            Just(Operation::Combinations),
+            Just(Operation::Permutations),
+        ],
+        prop_oneof![
+            Just(Operation::StatsAdd),
+            Just(Operation::StatsAddPair),
+            Just(Operation::StatsCount),
+            Just(Operation::StatsMean),
+            Just(Operation::StatsStdDev),
+            Just(Operation::LinearRegression),
+            Just(Operation::Predict),
+            Just(Operation::TvmStore(TvmRegister::N)),
+            Just(Operation::TvmSolve(TvmRegister::Fv)),
+            Just(Operation::Npv),
+            Just(Operation::Irr),
+            Just(Operation::Plot),
+            Just(Operation::Sparkline),
+            Just(Operation::Range),
+            Just(Operation::AssertEq),
+            Just(Operation::AssertNear),
+            Just(Operation::Equal),
+            Just(Operation::NotEqual),
+            Just(Operation::Clamp),
+            Just(Operation::Between),
+            Just(Operation::SetTolerance(1e-9)),
+            Just(Operation::DivMod),
+            Just(Operation::Round2),
+            Just(Operation::Iota),
+            Just(Operation::Define(
+                "f".to_string(),
+                vec![Operation::Push(Value::Integer(2)), Operation::Multiply]
+            )),
+            Just(Operation::Run("f".to_string())),
+            Just(Operation::Block(vec![
+                Operation::Push(Value::Integer(3)),
+                Operation::Iota,
+                Operation::Add
+            ])),
+            (0usize..20).prop_map(Operation::SetPrecision),
+            (0usize..9).prop_map(Operation::SetDigitGroup),
+            (0usize..70).prop_map(Operation::SetPadding),
+            (0usize..4).prop_map(Operation::SetColumn),
+        ],
+    ]
+}
+
+/// The exact result of an integer operation, if there is one.
+type ExactOp = fn(i128, i128) -> Option<i128>;
+
+/// The exact result of an integer operation with big integers.
+type ExactBigOp = fn(BigInt, BigInt) -> BigInt;
+
+proptest! {
+    #[test]
+    fn apply_never_panics(ops in prop::collection::vec(operation(), 0..32)) {
+        let mut calc = Calculator::new();
+
+        for op in ops {
+            // Errors are fine, as long as nothing panics.
+            if let Ok(new_calc) = calc.apply(op) {
+                calc = new_calc;
+            }
+
+            // Displaying the result must not panic either.
+            let _ = calc.to_string();
+        }
+    }
+
+    #[test]
+    fn push_then_pop_is_identity(stack in prop::collection::vec(value(), 0..8), v in value()) {
+        let mut calc = Calculator::new();
+        for s in &stack {
+            calc.push_mut(s.clone());
+        }
+
+        let mut pushed = calc.push(v.clone());
+        let popped = pushed.pop_mut().unwrap();
+
+        // Compare bit patterns, because NaN != NaN.
+        prop_assert_eq!(format!("{:?}", popped), format!("{:?}", v));
+        prop_assert_eq!(format!("{:?}", pushed.stack()), format!("{:?}", calc.stack()));
+    }
+
+    #[test]
+    fn integer_ops_never_overflow_silently(a in any::<i64>(), b in any::<i64>()) {
+        let ops: [(Operation, ExactOp); 4] = [
+            (Operation::Add, |a, b| Some(a + b)),
+            (Operation::Subtract, |a, b| Some(a - b)),
+            (Operation::Multiply, |a, b| Some(a * b)),
+            (Operation::Divide, |a, b| if b == 0 { None } else { Some(a / b) }),
+        ];
+
+        for (op, exact) in ops.iter() {
+            let result = Calculator::new()
+                .push(Value::Integer(a))
+                .push(Value::Integer(b))
+                .apply(op.clone());
+            let expected = exact(i128::from(a), i128::from(b))
+                .and_then(|r| i64::try_from(r).ok());
+
+            match result {
+                Ok(calc) => prop_assert_eq!(Some(calc.stack().to_vec()), expected.map(|r| vec![Value::Integer(r)])),
+                Err(_) => prop_assert_eq!(expected, None),
+            }
+        }
+    }
+
+    #[test]
+    fn promoted_integer_ops_are_exact(a in any::<i64>(), b in any::<i64>(), c in any::<i64>()) {
+        let ops: [(Operation, ExactBigOp); 3] = [
+            (Operation::Add, |a, b| a + b),
+            (Operation::Subtract, |a, b| a - b),
+            (Operation::Multiply, |a, b| a * b),
+        ];
+
+        for (op, exact) in ops.iter() {
+            // Apply twice, so big integers are used as input, too.
+            let result = Calculator::new()
+                .apply(Operation::SetOverflow(OverflowMode::Promote))
+                .and_then(|calc| calc.push(Value::Integer(a)).push(Value::Integer(b)).apply(op.clone()))
+                .and_then(|calc| calc.push(Value::Integer(c)).apply(op.clone()));
+            let expected = exact(exact(BigInt::from(a), BigInt::from(b)), BigInt::from(c));
+
+            prop_assert_eq!(result.unwrap().stack().to_vec(), vec![Value::from(expected)]);
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! # Pipe Protocol
+//!
+//! With `--pipe-proto`, other programs drive the calculator through
+//! stdin and stdout without parsing text output. Each frame is a
+//! 4-byte big-endian length followed by that many bytes of CBOR.
+//!
+//! A request is a text string with a line of operations, e.g.
+//! `"1 2 +"`. The response is a map with the resulting `stack`, the
+//! `output` of operations like `dump`, if any, and an `error`, if the
+//! line failed. A failed line leaves the stack unchanged.
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use ciborium::value::Value as Cbor;
+use num_bigint::Sign;
+
+use clac::calc::Calculator;
+use clac::parser::format_literal;
+use clac::types::Value;
+
+/// Frames larger than this are refused instead of allocated.
+const MAX_FRAME: usize = 16 << 20;
+
+/// Read the next frame. Returns `None` if the input ends between
+/// frames.
+pub fn read_frame(r: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut length = [0u8; 4];
+
+    match r.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Frame of {} bytes is too large", length),
+        ));
+    }
+
+    let mut frame = vec![0; length];
+    r.read_exact(&mut frame)?;
+
+    Ok(Some(frame))
+}
+
+/// Write a frame with its length prefix.
+pub fn write_frame(w: &mut impl Write, frame: &[u8]) -> io::Result<()> {
+    let length = u32::try_from(frame.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Frame too large"))?;
+
+    w.write_all(&length.to_be_bytes())?;
+    w.write_all(frame)?;
+    w.flush()
+}
+
+/// Get the line of operations from a request.
+fn decode_request(frame: &[u8]) -> Result<String, String> {
+    match ciborium::de::from_reader(frame) {
+        Ok(Cbor::Text(line)) => Ok(line),
+        Ok(_) => Err("Request is not a text string".to_string()),
+        Err(e) => Err(format!("Invalid CBOR: {}", e)),
+    }
+}
+
+/// Represent a value in CBOR. Big integers are bignums, money is its
+/// literal, e.g. `"19.99EUR"`.
+fn encode_value(v: &Value) -> Cbor {
+    match v {
+        Value::Integer(i) => Cbor::from(*i),
+        Value::Float(f) => Cbor::Float(*f),
+        Value::Big(b) => match b.sign() {
+            Sign::Minus => Cbor::Tag(3, Box::new(Cbor::Bytes((-b - 1u32).to_bytes_be().1))),
+            _ => Cbor::Tag(2, Box::new(Cbor::Bytes(b.to_bytes_be().1))),
+        },
+        Value::Money { .. } => Cbor::Text(format_literal(v)),
+    }
+}
+
+/// Build the response to a request.
+fn encode_response(stack: &[Value], output: Vec<String>, error: Option<String>) -> Vec<u8> {
+    let mut map = vec![(
+        Cbor::from("stack"),
+        Cbor::Array(stack.iter().map(encode_value).collect()),
+    )];
+
+    if !output.is_empty() {
+        map.push((
+            Cbor::from("output"),
+            Cbor::Array(output.into_iter().map(Cbor::Text).collect()),
+        ));
+    }
+    if let Some(error) = error {
+        map.push((Cbor::from("error"), Cbor::Text(error)));
+    }
+
+    let mut frame = vec![];
+    ciborium::ser::into_writer(&Cbor::Map(map), &mut frame)
+        .expect("writing to a vector can't fail");
+    frame
+}
+
+/// Evaluate the line of a request frame and return the response.
+fn respond(calc: &mut Calculator, frame: &[u8]) -> Vec<u8> {
+    let mut new_calc = calc.clone();
+
+    let error = decode_request(frame)
+        .and_then(|line| new_calc.eval(&line).map(|_| ()).map_err(|e| e.to_string()));
+
+    match error {
+        Ok(()) => {
+            let output = new_calc.take_output();
+
+            *calc = new_calc;
+            encode_response(calc.stack(), output, None)
+        }
+        Err(e) => encode_response(calc.stack(), vec![], Some(e)),
+    }
+}
+
+/// Answer requests from stdin until it ends.
+pub fn run(mut calc: Calculator) -> io::Result<Calculator> {
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+
+    while let Some(frame) = read_frame(&mut stdin)? {
+        write_frame(&mut stdout, &respond(&mut calc, &frame))?;
+    }
+
+    Ok(calc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_bigint::BigInt;
+
+    fn request(line: &str) -> Vec<u8> {
+        let mut frame = vec![];
+        ciborium::ser::into_writer(&Cbor::from(line), &mut frame).unwrap();
+        frame
+    }
+
+    fn response(frame: &[u8]) -> Vec<(String, Cbor)> {
+        match ciborium::de::from_reader(frame).unwrap() {
+            Cbor::Map(map) => map
+                .into_iter()
+                .map(|(k, v)| (k.into_text().unwrap(), v))
+                .collect(),
+            v => panic!("not a map: {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_frames() {
+        let mut buffer = vec![];
+
+        write_frame(&mut buffer, b"abc").unwrap();
+        assert_eq!(buffer, b"\0\0\0\x03abc");
+
+        let mut input = &buffer[..];
+        assert_eq!(read_frame(&mut input).unwrap(), Some(b"abc".to_vec()));
+        assert_eq!(read_frame(&mut input).unwrap(), None);
+        assert!(read_frame(&mut &b"\0\0\0\x03a"[..]).is_err());
+    }
+
+    #[test]
+    fn test_respond() {
+        let mut calc = Calculator::new();
+
+        assert_eq!(
+            response(&respond(&mut calc, &request("1 2.5 1EUR"))),
+            vec![(
+                "stack".to_string(),
+                Cbor::Array(vec![Cbor::from(1), Cbor::Float(2.5), Cbor::from("1.00EUR")])
+            )]
+        );
+
+        let failed = response(&respond(&mut calc, &request("+ +")));
+        assert_eq!(failed.len(), 2);
+        assert_eq!(failed[0].1.as_array().unwrap().len(), 3);
+        assert_eq!(failed[1].0, "error");
+
+        assert_eq!(
+            response(&respond(&mut calc, b"\x01"))[1],
+            (
+                "error".to_string(),
+                Cbor::from("Request is not a text string")
+            )
+        );
+    }
+
+    #[test]
+    fn test_bignum() {
+        let big = BigInt::from(1) << 64u32;
+
+        assert_eq!(
+            encode_value(&Value::Big(big.clone())),
+            Cbor::Tag(2, Box::new(Cbor::Bytes(vec![1, 0, 0, 0, 0, 0, 0, 0, 0])))
+        );
+        assert_eq!(
+            encode_value(&Value::Big(-big)),
+            Cbor::Tag(3, Box::new(Cbor::Bytes(vec![0xff; 8])))
+        );
+    }
+}
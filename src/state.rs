@@ -0,0 +1,83 @@
+//! # Stack Persistence
+//!
+//! With `--persist`, the stack survives between invocations. It is
+//! stored as one literal per line, like `:save` writes it, either in a
+//! global state file or in `.clac-stack` in the current directory.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use clac::parser::{format_literal, parse_values};
+use clac::types::{OverflowMode, Value};
+
+use crate::cli::Scope;
+
+/// The name of the state file for [Scope::Directory].
+const DIRECTORY_FILE: &str = ".clac-stack";
+
+/// The state file for the scope.
+pub fn path(scope: Scope) -> Option<PathBuf> {
+    match scope {
+        Scope::Directory => Some(PathBuf::from(DIRECTORY_FILE)),
+        Scope::Global => {
+            let state = std::env::var_os("XDG_STATE_HOME")
+                .map(PathBuf::from)
+                .or_else(|| {
+                    std::env::var_os("HOME")
+                        .map(|home| PathBuf::from(home).join(".local").join("state"))
+                })?;
+
+            Some(state.join("clac").join("stack"))
+        }
+    }
+}
+
+/// Read a saved stack. A missing file is an empty stack. Big integers
+/// saved in the overflow promote mode are read back as they are.
+pub fn load(path: &Path) -> anyhow::Result<Vec<Value>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(parse_values(&contents, OverflowMode::Promote)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Write the stack to a file as one literal per line.
+pub fn save(path: &Path, stack: &[Value]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut file = std::fs::File::create(path)?;
+
+    for v in stack {
+        writeln!(file, "{}", format_literal(v))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_bigint::BigInt;
+
+    #[test]
+    fn test_save_load() {
+        let path = std::env::temp_dir()
+            .join(format!("clac-state-{}", std::process::id()))
+            .join("stack");
+        let stack = vec![
+            Value::Integer(-3),
+            Value::Float(0.5),
+            Value::from(BigInt::from(1) << 64),
+        ];
+
+        assert_eq!(load(&path).unwrap(), vec![]);
+
+        save(&path, &stack).unwrap();
+        assert_eq!(load(&path).unwrap(), stack);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+//! # Statistics Registers
+//!
+//! Like the Σ registers of HP calculators, this accumulates data
+//! points without keeping them around. We use Welford's method
+//! instead of plain sums, because the textbook formula for the
+//! variance cancels catastrophically for data with a large mean.
+
+/// Running statistics over data points that are either single values
+/// or (x, y) pairs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Statistics {
+    /// The number of data points.
+    n: u64,
+    /// How many of the data points had a y value.
+    pairs: u64,
+
+    mean_x: f64,
+    mean_y: f64,
+
+    /// Sums of squared differences from the mean.
+    m2_x: f64,
+    m2_y: f64,
+
+    /// Sum of products of differences from the means.
+    c_xy: f64,
+}
+
+impl Statistics {
+    /// Add a data point.
+    pub fn add(&mut self, x: f64, y: Option<f64>) {
+        self.n += 1;
+
+        let dx = x - self.mean_x;
+        self.mean_x += dx / self.n as f64;
+        self.m2_x += dx * (x - self.mean_x);
+
+        if let Some(y) = y {
+            self.pairs += 1;
+
+            let dy = y - self.mean_y;
+            self.mean_y += dy / self.pairs as f64;
+            self.m2_y += dy * (y - self.mean_y);
+            self.c_xy += dx * (y - self.mean_y);
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.n
+    }
+
+    /// The mean of all x values.
+    pub fn mean(&self) -> Option<f64> {
+        (self.n > 0).then_some(self.mean_x)
+    }
+
+    /// The sample standard deviation of all x values.
+    pub fn std_dev(&self) -> Option<f64> {
+        (self.n > 1).then(|| (self.m2_x / (self.n - 1) as f64).sqrt())
+    }
+
+    /// The slope and intercept of the least squares line through all
+    /// data points. All of them need to be pairs.
+    pub fn linear_regression(&self) -> Option<(f64, f64)> {
+        if self.n < 2 || self.pairs != self.n || self.m2_x == 0.0 {
+            return None;
+        }
+
+        let slope = self.c_xy / self.m2_x;
+
+        Some((slope, self.mean_y - slope * self.mean_x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single() {
+        let mut stats = Statistics::default();
+
+        assert_eq!(stats.mean(), None);
+        assert_eq!(stats.std_dev(), None);
+
+        for x in &[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            stats.add(*x, None);
+        }
+
+        assert_eq!(stats.count(), 8);
+        assert_eq!(stats.mean(), Some(5.0));
+        assert_eq!(stats.std_dev(), Some((32.0f64 / 7.0).sqrt()));
+    }
+
+    #[test]
+    fn test_regression() {
+        let mut stats = Statistics::default();
+
+        stats.add(1.0, Some(3.0));
+        assert_eq!(stats.linear_regression(), None);
+
+        stats.add(2.0, Some(5.0));
+        stats.add(3.0, Some(7.0));
+        assert_eq!(stats.linear_regression(), Some((2.0, 1.0)));
+
+        // Single values have no y to fit.
+        stats.add(4.0, None);
+        assert_eq!(stats.linear_regression(), None);
+    }
+
+    #[test]
+    fn test_large_mean() {
+        let mut stats = Statistics::default();
+
+        for x in &[1e9 + 4.0, 1e9 + 7.0, 1e9 + 13.0, 1e9 + 16.0] {
+            stats.add(*x, None);
+        }
+
+        assert_eq!(stats.mean(), Some(1e9 + 10.0));
+        assert_eq!(stats.std_dev(), Some(30f64.sqrt()));
+    }
+}
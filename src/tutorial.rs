@@ -0,0 +1,158 @@
+//! # Tutorial
+//!
+//! `--tutorial` walks through the basics of RPN one step at a time.
+//! Each step asks for some input and only moves on once the stack
+//! looks as expected. The lessons are the [STEPS] table.
+
+use std::io;
+
+use clac::calc::Calculator;
+
+use crate::input::Input;
+
+/// A lesson step.
+struct Step {
+    /// What to learn and what to type.
+    text: &'static str,
+    /// The input that solves the step.
+    input: &'static str,
+    /// The stack after the step, as `format_stack` shows it.
+    stack: &'static str,
+}
+
+const STEPS: &[Step] = &[
+    Step {
+        text: "Numbers go on a stack. Type two numbers separated by a space: 2 3",
+        input: "2 3",
+        stack: "2 3",
+    },
+    Step {
+        text: "Operators take their arguments from the top of the stack.\n\
+               Add the two numbers: +",
+        input: "+",
+        stack: "5",
+    },
+    Step {
+        text: "The result stays on the stack for the next operation.\n\
+               Multiply it by 4: 4 *",
+        input: "4 *",
+        stack: "20",
+    },
+    Step {
+        text: "For - and /, the order matters: the top value is subtracted\n\
+               from the one below. Subtract 20 from 100: 100 swap -",
+        input: "100 swap -",
+        stack: "80",
+    },
+    Step {
+        text: "drop removes the top value and clear removes all of them.\n\
+               Clear the stack: clear",
+        input: "clear",
+        stack: "",
+    },
+    Step {
+        text: "There are no parentheses, the order of operations says it all.\n\
+               Calculate (1 + 2) * 3: 1 2 + 3 *",
+        input: "1 2 + 3 *",
+        stack: "9",
+    },
+    Step {
+        text: "sto NAME takes the top value into a variable and rcl NAME\n\
+               brings it back. Square the 9: sto x rcl x rcl x *",
+        input: "sto x rcl x rcl x *",
+        stack: "81",
+    },
+];
+
+/// An empty stack is shown as nothing at all otherwise.
+fn show(stack: &str) -> &str {
+    if stack.is_empty() {
+        "(empty)"
+    } else {
+        stack
+    }
+}
+
+/// Evaluate `line` for `step`. Returns whether the step is solved and
+/// what to tell the user. A line that doesn't solve the step is undone.
+fn attempt(calc: &mut Calculator, step: &Step, line: &str) -> (bool, Vec<String>) {
+    let mut attempt = calc.clone();
+
+    if let Err(e) = attempt.eval(line) {
+        return (false, vec![format!("Error: {}", e)]);
+    }
+
+    let mut lines = attempt.take_output();
+    let stack = attempt.format_stack(" ");
+
+    if stack == step.stack {
+        *calc = attempt;
+        lines.push(format!("Stack: {}", show(&stack)));
+        (true, lines)
+    } else {
+        lines.push(format!(
+            "The stack would be {}, but should be {}. Try again: {}",
+            show(&stack),
+            show(step.stack),
+            step.input
+        ));
+        (false, lines)
+    }
+}
+
+/// Run the lessons on stdin and stdout.
+pub fn run() -> io::Result<()> {
+    let mut input = Input::new();
+    let mut calc = Calculator::new();
+
+    println!("Welcome to clac! Press Ctrl-D to leave the tutorial at any time.");
+
+    for (n, step) in STEPS.iter().enumerate() {
+        println!("\n{}/{}: {}", n + 1, STEPS.len(), step.text);
+
+        loop {
+            let line = match input.read_line("> ")? {
+                Some(line) => line,
+                None => return Ok(()),
+            };
+            let (solved, lines) = attempt(&mut calc, step, &line);
+
+            for line in lines {
+                println!("{}", line);
+            }
+            if solved {
+                break;
+            }
+        }
+    }
+
+    println!("\nThat's it! :help lists all operations.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steps() {
+        let mut calc = Calculator::new();
+
+        for step in STEPS {
+            assert!(attempt(&mut calc, step, step.input).0, "{}", step.input);
+        }
+    }
+
+    #[test]
+    fn test_attempt() {
+        let mut calc = Calculator::new();
+
+        assert!(!attempt(&mut calc, &STEPS[0], "2 4").0);
+        assert!(!attempt(&mut calc, &STEPS[0], "2 +").0);
+        assert!(calc.stack().is_empty());
+        assert_eq!(
+            attempt(&mut calc, &STEPS[0], "2 3"),
+            (true, vec!["Stack: 2 3".to_string()])
+        );
+    }
+}
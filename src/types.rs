@@ -3,29 +3,166 @@
 //! This module contains all types that are used to describe
 //! calculator operations.
 
-use std::convert::From;
+use std::convert::{From, TryFrom};
+
+use num_bigint::{BigInt, Sign};
+use num_traits::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+
+use crate::money;
 
 /// A value on the stack of the calculator.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Integer(i64),
     Float(f64),
+    /// An integer that doesn't fit into [Value::Integer]. These only
+    /// appear in the [OverflowMode::Promote] mode.
+    Big(BigInt),
+    /// An exact decimal amount of money in a currency like `EUR`.
+    Money {
+        amount: Decimal,
+        currency: String,
+    },
 }
 
 impl Value {
     pub fn is_float(&self) -> bool {
-        if let Value::Float(_) = self {
-            true
-        } else {
-            false
-        }
+        matches!(self, Value::Float(_))
     }
 
     pub fn is_integer(&self) -> bool {
-        if let Value::Integer(_) = self {
-            true
-        } else {
-            false
+        matches!(self, Value::Integer(_))
+    }
+
+    pub fn is_big(&self) -> bool {
+        matches!(self, Value::Big(_))
+    }
+
+    pub fn is_money(&self) -> bool {
+        matches!(self, Value::Money { .. })
+    }
+
+    /// Roughly how many bytes the value takes up on the stack.
+    pub fn size(&self) -> usize {
+        std::mem::size_of::<Value>()
+            + match self {
+                Value::Big(b) => (b.bits() as usize).div_ceil(8),
+                Value::Money { currency, .. } => currency.len(),
+                _ => 0,
+            }
+    }
+
+    /// The type operator overloads are chosen by.
+    pub fn type_of(&self) -> Type {
+        match self {
+            Value::Integer(_) => Type::Integer,
+            Value::Big(_) => Type::Big,
+            Value::Float(_) => Type::Float,
+            Value::Money { .. } => Type::Money,
+        }
+    }
+
+    /// Turn floats with an exact integer value into integers, if they
+    /// fit.
+    pub fn demoted(self) -> Value {
+        match self {
+            // 2^63 is exactly representable, i64::MAX isn't.
+            Value::Float(f)
+                if f.fract() == 0.0 && f >= i64::MIN as f64 && f < -(i64::MIN as f64) =>
+            {
+                Value::Integer(f as i64)
+            }
+            v => v,
+        }
+    }
+}
+
+/// The types of values as far as operator overloading is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type {
+    Integer,
+    Big,
+    Float,
+    Money,
+}
+
+impl Type {
+    /// Whether values of this type are plain numbers, which can be
+    /// promoted to a common [Kind].
+    pub fn is_number(self) -> bool {
+        self != Type::Money
+    }
+}
+
+/// The kinds of numbers in the order they are promoted in binary
+/// operations: integers become big integers and anything becomes a
+/// float.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    Integer,
+    Big,
+    Float,
+}
+
+/// Conversion of the operands of binary operations to a common kind.
+///
+/// A new number type plugs into the existing operations by getting a
+/// place in [Kind] and a conversion in [Coerce::promote_to].
+pub trait Coerce: Sized {
+    fn kind(&self) -> Kind;
+
+    /// Convert to `kind`. Conversions to a lower kind saturate or
+    /// truncate like the [From] conversions.
+    fn promote_to(self, kind: Kind) -> Self;
+
+    /// Convert both values to the higher of their kinds.
+    fn coerce_pair(self, other: Self) -> (Self, Self) {
+        let kind = self.kind().max(other.kind());
+
+        (self.promote_to(kind), other.promote_to(kind))
+    }
+}
+
+impl Coerce for Value {
+    /// Money counts as float. Operations that keep the currency have
+    /// to handle it before coercion.
+    fn kind(&self) -> Kind {
+        match self {
+            Value::Integer(_) => Kind::Integer,
+            Value::Big(_) => Kind::Big,
+            Value::Float(_) | Value::Money { .. } => Kind::Float,
+        }
+    }
+
+    fn promote_to(self, kind: Kind) -> Self {
+        match kind {
+            Kind::Integer => Value::Integer(self.into()),
+            // Not Value::from, which would demote small big integers
+            // again.
+            Kind::Big => Value::Big(self.into()),
+            Kind::Float => Value::Float(self.into()),
+        }
+    }
+}
+
+impl From<BigInt> for Value {
+    /// Big integers that fit into an [i64] become normal integers.
+    fn from(b: BigInt) -> Self {
+        match i64::try_from(&b) {
+            Ok(i) => Value::Integer(i),
+            Err(_) => Value::Big(b),
+        }
+    }
+}
+
+impl From<Value> for BigInt {
+    fn from(v: Value) -> Self {
+        match v {
+            Value::Integer(i) => BigInt::from(i),
+            Value::Float(f) => BigInt::from_f64(f).unwrap_or_default(),
+            Value::Big(b) => b,
+            Value::Money { amount, .. } => amount.trunc().to_string().parse().unwrap_or_default(),
         }
     }
 }
@@ -35,6 +172,24 @@ impl From<Value> for i64 {
         match v {
             Value::Integer(i) => i,
             Value::Float(f) => f as i64,
+            // Saturate like the float conversion does.
+            Value::Big(b) => {
+                if b.sign() == Sign::Minus {
+                    i64::MIN
+                } else {
+                    i64::MAX
+                }
+            }
+            Value::Money { amount, .. } => {
+                amount
+                    .trunc()
+                    .to_i64()
+                    .unwrap_or(if amount.is_sign_negative() {
+                        i64::MIN
+                    } else {
+                        i64::MAX
+                    })
+            }
         }
     }
 }
@@ -47,6 +202,8 @@ impl From<Value> for f64 {
             Value::Integer(i) => i as f64,
 
             Value::Float(f) => f,
+            Value::Big(b) => b.to_f64().unwrap_or(f64::NAN),
+            Value::Money { amount, .. } => money::to_f64(&amount),
         }
     }
 }
@@ -56,6 +213,8 @@ impl std::fmt::Display for Value {
         match self {
             Value::Integer(i) => write!(f, "{}", i),
             Value::Float(fl) => write!(f, "{}", fl),
+            Value::Big(b) => write!(f, "{}", b),
+            Value::Money { amount, currency } => write!(f, "{}", money::format(amount, currency)),
         }
     }
 }
@@ -65,16 +224,135 @@ pub enum Radix {
     Bin,
     Dec,
     Hex,
+    /// Dotted quads for integers that are IPv4 addresses.
+    Ip,
+}
+
+/// What happens when integer arithmetic overflows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowMode {
+    /// Fail with an error.
+    Error,
+    /// Continue with big integers.
+    Promote,
+    /// Mask shift amounts to 0..=63 like most CPUs do. Everything
+    /// else fails like in [OverflowMode::Error].
+    Mask,
+}
+
+/// How floats are rounded when they are converted to integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// Round to nearest, ties to even (banker's rounding).
+    HalfEven,
+    /// Round to nearest, ties away from zero.
+    HalfUp,
+    /// Cut off the fractional part.
+    TowardZero,
+    Floor,
+    Ceiling,
+}
+
+impl RoundingMode {
+    /// Round a float to an integral float.
+    pub fn round(self, f: f64) -> f64 {
+        match self {
+            RoundingMode::HalfEven => f.round_ties_even(),
+            RoundingMode::HalfUp => f.round(),
+            RoundingMode::TowardZero => f.trunc(),
+            RoundingMode::Floor => f.floor(),
+            RoundingMode::Ceiling => f.ceil(),
+        }
+    }
+}
+
+/// The unit of angles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AngleMode {
+    Radians,
+    Degrees,
+}
+
+/// The time zone for converting Unix timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    /// The time zone of the system.
+    Local,
+    /// A zone from the IANA database, like `Europe/Berlin`.
+    Named(chrono_tz::Tz),
+}
+
+/// How decimal numbers are written in the output. Input is not
+/// affected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locale {
+    /// Separates the integer part from the fraction.
+    pub decimal: char,
+    /// Separates groups of three digits in the integer part, if any.
+    pub group: Option<char>,
+}
+
+impl Default for Locale {
+    /// Plain numbers like in the input.
+    fn default() -> Self {
+        Locale {
+            decimal: '.',
+            group: None,
+        }
+    }
+}
+
+/// The registers of time value of money calculations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TvmRegister {
+    /// The number of periods.
+    N,
+    /// The interest rate per period in percent.
+    I,
+    /// The present value.
+    Pv,
+    /// The payment per period.
+    Pmt,
+    /// The future value.
+    Fv,
+}
+
+/// How large values and the stack may grow, so careless or hostile
+/// input can't exhaust memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// The most bits of a big integer.
+    pub max_bits: u64,
+    /// The most values on the stack.
+    pub max_values: usize,
+    /// The most bytes all values on the stack take up together.
+    pub max_bytes: usize,
+}
+
+impl Default for Limits {
+    /// Generous enough to never get in the way of calculations by
+    /// hand.
+    fn default() -> Self {
+        Limits {
+            max_bits: 1 << 20,
+            max_values: 1_000_000,
+            max_bytes: 256 << 20,
+        }
+    }
 }
 
 /// An operation that can be run on a calculator.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Operation {
     Push(Value),
     Add,
     Subtract,
     Multiply,
     Divide,
+    /// Integer division that pushes quotient and remainder.
+    DivMod,
+    /// Round to two decimals, e.g. cents.
+    Round2,
     BitAnd,
     BitNot,
     BitOr,
@@ -82,5 +360,527 @@ pub enum Operation {
     LeftShift,
     RightShift,
     SetRadix(Radix),
+    /// Read bare integer literals in this base from 2 to 36.
+    SetInputBase(u32),
     Swap,
+    /// Push all numbers from a file.
+    ReadFile(String),
+    /// Push the value of an environment variable.
+    PushEnv(String),
+    /// Apply the contents of the clipboard.
+    Paste,
+    /// Only read this column of tab-separated input or all of it for
+    /// 0.
+    SetColumn(usize),
+    /// Set the significant digits for displaying floats.
+    SetPrecision(usize),
+    /// Group hex and binary digits in groups of this size or not at
+    /// all for 0.
+    SetDigitGroup(usize),
+    /// Pad hex and binary output with zeros to this many bits.
+    SetPadding(usize),
+    /// Convert to integer using the rounding mode.
+    ToInteger,
+    SetRounding(RoundingMode),
+    /// Choose what happens on integer overflow.
+    SetOverflow(OverflowMode),
+    /// Turn on or off demoting integral floats to integers.
+    SetDemotion(bool),
+    /// Turn on or off displaying floats as fractions.
+    SetFractionDisplay(bool),
+    /// Turn on or off displaying floats with SI prefixes.
+    SetSiDisplay(bool),
+    /// Turn on or off showing the type of each value in the prompt.
+    SetTypeDisplay(bool),
+    /// Turn on or off keeping the argument of unary functions.
+    SetKeep(bool),
+    Hypot,
+    Atan2,
+    LogBase,
+    SetAngleMode(AngleMode),
+    Exp,
+    Exp2,
+    ExpM1,
+    Ln1p,
+    /// The gap between a float and the next one away from zero.
+    Ulp,
+    /// The next larger float.
+    NextUp,
+    /// The next smaller float.
+    NextDown,
+    /// The largest integer whose square is at most the argument.
+    IntSqrt,
+    /// Whether an integer is a power of two.
+    IsPowerOfTwo,
+    /// The integer part of the binary logarithm.
+    IntLog2,
+    /// Convert seconds to hours in the H.MMSS format.
+    ToHms,
+    /// Convert polar coordinates (r θ) to rectangular (x y).
+    ToRect,
+    /// Convert rectangular coordinates (x y) to polar (r θ).
+    ToPolar,
+    /// The netmask of a prefix length.
+    Netmask,
+    /// The broadcast address of an address and prefix length.
+    Broadcast,
+    /// The number of usable hosts in a subnet with a prefix length.
+    Hosts,
+    /// Whether an address is in the subnet given by network address
+    /// and prefix length.
+    InSubnet,
+    /// Show the value on top of the stack as a byte size.
+    ShowBytes,
+    /// Show the value on top of the stack.
+    Print,
+    /// Push the value on top of the stack again.
+    Dup,
+    /// Remove the value on top of the stack.
+    Drop,
+    /// Remove all values from the stack.
+    Clear,
+    /// Show the last line as an infix expression.
+    Explain,
+    /// Show the bytes of the integer on top of the stack in both byte
+    /// orders.
+    Dump,
+    /// Show the value on top of the stack as file permissions.
+    ShowPermissions,
+    /// Show the fields of the value on top of the stack as given by
+    /// an inline specification or a TOML file.
+    Decode(String),
+    /// Set the time zone for Unix timestamps.
+    SetZone(Zone),
+    /// Show the Unix timestamp on top of the stack as date and time.
+    FromUnix,
+    /// Convert year, month, day, hour, minute and second to a Unix
+    /// timestamp.
+    ToUnix,
+    /// Convert decimal degrees to the D.MMSS format.
+    ToDms,
+    /// Convert degrees in the D.MMSS format to decimal degrees.
+    FromDms,
+    Combinations,
+    Permutations,
+    StatsAdd,
+    StatsAddPair,
+    StatsCount,
+    StatsMean,
+    StatsStdDev,
+    StatsClear,
+    LinearRegression,
+    Predict,
+    /// Push the numbers from start to stop (inclusive) with a step.
+    Range,
+    /// Push the numbers from 1 to n.
+    Iota,
+    /// Show the stack as a bar chart.
+    Plot,
+    /// Show the stack as a sparkline.
+    Sparkline,
+    /// Store a sequence of operations under a name.
+    Define(String, Vec<Operation>),
+    /// Apply operations on a fresh stack and push what is left on it.
+    Block(Vec<Operation>),
+    /// Apply the operations stored under a name.
+    Run(String),
+    /// Plot the operations stored under a name as a function of the
+    /// top of the stack. Takes start, stop and the number of steps.
+    PlotFunction(String),
+    /// Integrate a definition as a function between two values.
+    Integrate(String),
+    /// Differentiate a definition as a function at a value.
+    Derive(String),
+    /// Find a root of a definition as a function near a guess.
+    Solve(String),
+    /// Find a root of a definition as a function between two values.
+    SolveIn(String),
+    /// Move the top of the stack into a variable.
+    Store(String),
+    /// Combine the top of the stack into a variable with an
+    /// arithmetic operation, e.g. `sto+ name`.
+    StoreWith(String, Box<Operation>),
+    /// Push the value of a variable.
+    Recall(String),
+    /// Show all variables.
+    ListVariables,
+    /// Fail unless the two values on top of the stack are equal.
+    AssertEq,
+    /// Fail unless two values differ by at most a tolerance.
+    AssertNear,
+    /// Push 1 if two values are equal within the tolerance, else 0.
+    Equal,
+    /// Push 1 if two values differ by more than the tolerance, else 0.
+    NotEqual,
+    /// Limit a value to a range.
+    Clamp,
+    /// Push 1 if a value is in a range, else 0.
+    Between,
+    /// Set the tolerance for comparing floats.
+    SetTolerance(f64),
+    /// Save a copy of the whole stack under a name.
+    Snapshot(String),
+    /// Replace the stack with a saved copy.
+    Restore(String),
+    /// Show how the stack of the second snapshot differs from the
+    /// first. The names are boxed to keep operations small.
+    Diff(Box<(String, String)>),
+    /// Push a user-defined constant.
+    PushConstant(String),
+    /// Push the result of an earlier line. 1 is the latest result.
+    RecallResult(usize),
+    /// List the operations of a category or of all categories.
+    Catalog(Option<Category>),
+    /// Set a time value of money register.
+    TvmStore(TvmRegister),
+    /// Compute a time value of money register from the others.
+    TvmSolve(TvmRegister),
+    /// The net present value of the cash flows on the stack at the
+    /// interest rate on top of it.
+    Npv,
+    /// The internal rate of return of the cash flows on the stack.
+    Irr,
+}
+
+/// The groups of operations in the `catalog`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+    Arithmetic,
+    Bitwise,
+    Stack,
+    /// Variables, snapshots and earlier results.
+    Memory,
+    Programs,
+    Conversion,
+    Display,
+    Networks,
+    Time,
+    Statistics,
+    Input,
+    Testing,
+    Finance,
+}
+
+/// How many values an operation takes from the stack and how many it
+/// puts back. Operations that only look at the top of the stack take
+/// and put back one value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackEffect {
+    pub pops: usize,
+    pub pushes: usize,
+}
+
+impl StackEffect {
+    /// The stack effect of running `ops` one after another, unless
+    /// one of them has no fixed stack effect.
+    pub fn of<'a>(ops: impl IntoIterator<Item = &'a Operation>) -> Option<StackEffect> {
+        let mut depth = 0i64;
+        let mut lowest = 0i64;
+
+        for op in ops {
+            let effect = op.stack_effect()?;
+
+            depth -= effect.pops as i64;
+            lowest = lowest.min(depth);
+            depth += effect.pushes as i64;
+        }
+
+        Some(StackEffect {
+            pops: -lowest as usize,
+            pushes: (depth - lowest) as usize,
+        })
+    }
+}
+
+impl Operation {
+    /// The stack effect of the operation, unless it depends on the
+    /// values or on the state of the calculator, like `iota` or `run`.
+    pub fn stack_effect(&self) -> Option<StackEffect> {
+        let (pops, pushes) = match self {
+            Operation::Push(_)
+            | Operation::PushEnv(_)
+            | Operation::PushConstant(_)
+            | Operation::Recall(_)
+            | Operation::RecallResult(_)
+            | Operation::StatsCount
+            | Operation::StatsMean
+            | Operation::StatsStdDev
+            | Operation::TvmSolve(_) => (0, 1),
+
+            Operation::Add
+            | Operation::Subtract
+            | Operation::Multiply
+            | Operation::Divide
+            | Operation::BitAnd
+            | Operation::BitOr
+            | Operation::BitXor
+            | Operation::LeftShift
+            | Operation::RightShift
+            | Operation::Hypot
+            | Operation::Atan2
+            | Operation::LogBase
+            | Operation::Broadcast
+            | Operation::Combinations
+            | Operation::Permutations
+            | Operation::Equal
+            | Operation::NotEqual => (2, 1),
+
+            Operation::DivMod | Operation::Swap | Operation::ToRect | Operation::ToPolar => (2, 2),
+
+            Operation::Round2
+            | Operation::BitNot
+            | Operation::ToInteger
+            | Operation::Exp
+            | Operation::Exp2
+            | Operation::ExpM1
+            | Operation::Ln1p
+            | Operation::Derive(_)
+            | Operation::Solve(_)
+            | Operation::Ulp
+            | Operation::NextUp
+            | Operation::NextDown
+            | Operation::IntSqrt
+            | Operation::IsPowerOfTwo
+            | Operation::IntLog2
+            | Operation::ToHms
+            | Operation::ToDms
+            | Operation::FromDms
+            | Operation::Netmask
+            | Operation::Hosts
+            | Operation::Predict
+            | Operation::ShowBytes
+            | Operation::Print
+            | Operation::Dump
+            | Operation::ShowPermissions
+            | Operation::Decode(_)
+            | Operation::FromUnix => (1, 1),
+
+            Operation::Dup => (1, 2),
+            Operation::Store(_)
+            | Operation::StoreWith(_, _)
+            | Operation::StatsAdd
+            | Operation::TvmStore(_)
+            | Operation::Drop => (1, 0),
+            Operation::StatsAddPair | Operation::AssertEq => (2, 0),
+            Operation::AssertNear | Operation::PlotFunction(_) => (3, 0),
+            Operation::Integrate(_) | Operation::SolveIn(_) => (2, 1),
+            Operation::InSubnet | Operation::Clamp | Operation::Between => (3, 1),
+            Operation::ToUnix => (6, 1),
+            Operation::LinearRegression => (0, 2),
+
+            Operation::SetRadix(_)
+            | Operation::SetInputBase(_)
+            | Operation::SetPrecision(_)
+            | Operation::SetDigitGroup(_)
+            | Operation::SetPadding(_)
+            | Operation::SetRounding(_)
+            | Operation::SetOverflow(_)
+            | Operation::SetDemotion(_)
+            | Operation::SetFractionDisplay(_)
+            | Operation::SetSiDisplay(_)
+            | Operation::SetTypeDisplay(_)
+            | Operation::SetKeep(_)
+            | Operation::SetColumn(_)
+            | Operation::SetTolerance(_)
+            | Operation::SetAngleMode(_)
+            | Operation::SetZone(_)
+            | Operation::Explain
+            | Operation::Catalog(_)
+            | Operation::Plot
+            | Operation::Sparkline
+            | Operation::StatsClear
+            | Operation::ListVariables
+            | Operation::Define(_, _)
+            | Operation::Snapshot(_)
+            | Operation::Diff(_) => (0, 0),
+
+            Operation::ReadFile(_)
+            | Operation::Paste
+            | Operation::Range
+            | Operation::Iota
+            | Operation::Block(_)
+            | Operation::Run(_)
+            | Operation::Restore(_)
+            | Operation::Npv
+            | Operation::Irr
+            | Operation::Clear => return None,
+        };
+
+        Some(StackEffect { pops, pushes })
+    }
+
+    /// How many values the operation takes at least. Unlike with
+    /// [stack_effect](Operation::stack_effect), this is also known for
+    /// some operations that push a varying number of values.
+    pub fn min_pops(&self) -> usize {
+        match self {
+            Operation::Range => 3,
+            Operation::Iota => 1,
+            op => op.stack_effect().map_or(0, |effect| effect.pops),
+        }
+    }
+
+    /// Whether the operation replaces the top of the stack with a
+    /// function of it, like `exp`. Operations that only show the top
+    /// of the stack are not unary functions.
+    pub fn is_unary(&self) -> bool {
+        let shows = matches!(
+            self,
+            Operation::ShowBytes
+                | Operation::Print
+                | Operation::Dump
+                | Operation::ShowPermissions
+                | Operation::Decode(_)
+                | Operation::FromUnix
+        );
+
+        !shows && self.stack_effect() == Some(StackEffect { pops: 1, pushes: 1 })
+    }
+
+    pub fn category(&self) -> Category {
+        match self {
+            Operation::Add
+            | Operation::Subtract
+            | Operation::Multiply
+            | Operation::Divide
+            | Operation::DivMod
+            | Operation::Round2
+            | Operation::Hypot
+            | Operation::Atan2
+            | Operation::LogBase
+            | Operation::Exp
+            | Operation::Exp2
+            | Operation::ExpM1
+            | Operation::Ln1p
+            | Operation::Ulp
+            | Operation::NextUp
+            | Operation::NextDown
+            | Operation::IntSqrt
+            | Operation::IsPowerOfTwo
+            | Operation::IntLog2
+            | Operation::Clamp
+            | Operation::Combinations
+            | Operation::Permutations => Category::Arithmetic,
+
+            Operation::BitAnd
+            | Operation::BitOr
+            | Operation::BitXor
+            | Operation::BitNot
+            | Operation::LeftShift
+            | Operation::RightShift
+            | Operation::Dump
+            | Operation::Decode(_) => Category::Bitwise,
+
+            Operation::Push(_)
+            | Operation::PushConstant(_)
+            | Operation::Swap
+            | Operation::Dup
+            | Operation::Drop
+            | Operation::Clear
+            | Operation::Print
+            | Operation::SetKeep(_)
+            | Operation::Range
+            | Operation::Iota => Category::Stack,
+
+            Operation::Store(_)
+            | Operation::StoreWith(_, _)
+            | Operation::Recall(_)
+            | Operation::ListVariables
+            | Operation::Snapshot(_)
+            | Operation::Restore(_)
+            | Operation::Diff(_)
+            | Operation::RecallResult(_)
+            | Operation::Explain => Category::Memory,
+
+            Operation::Define(_, _)
+            | Operation::Block(_)
+            | Operation::Run(_)
+            | Operation::PlotFunction(_)
+            | Operation::Integrate(_)
+            | Operation::Derive(_)
+            | Operation::Solve(_)
+            | Operation::SolveIn(_) => Category::Programs,
+
+            Operation::ToInteger
+            | Operation::SetRounding(_)
+            | Operation::SetOverflow(_)
+            | Operation::SetDemotion(_)
+            | Operation::ToHms
+            | Operation::ToDms
+            | Operation::FromDms
+            | Operation::ToRect
+            | Operation::ToPolar => Category::Conversion,
+
+            Operation::SetRadix(_)
+            | Operation::SetInputBase(_)
+            | Operation::SetPrecision(_)
+            | Operation::SetDigitGroup(_)
+            | Operation::SetPadding(_)
+            | Operation::SetFractionDisplay(_)
+            | Operation::SetSiDisplay(_)
+            | Operation::SetTypeDisplay(_)
+            | Operation::SetAngleMode(_)
+            | Operation::Plot
+            | Operation::Sparkline
+            | Operation::ShowBytes
+            | Operation::ShowPermissions
+            | Operation::Catalog(_) => Category::Display,
+
+            Operation::Netmask | Operation::Broadcast | Operation::Hosts | Operation::InSubnet => {
+                Category::Networks
+            }
+
+            Operation::FromUnix | Operation::ToUnix | Operation::SetZone(_) => Category::Time,
+
+            Operation::StatsAdd
+            | Operation::StatsAddPair
+            | Operation::StatsCount
+            | Operation::StatsMean
+            | Operation::StatsStdDev
+            | Operation::StatsClear
+            | Operation::LinearRegression
+            | Operation::Predict => Category::Statistics,
+
+            Operation::TvmStore(_) | Operation::TvmSolve(_) | Operation::Npv | Operation::Irr => {
+                Category::Finance
+            }
+
+            Operation::ReadFile(_)
+            | Operation::Paste
+            | Operation::PushEnv(_)
+            | Operation::SetColumn(_) => Category::Input,
+
+            Operation::AssertEq
+            | Operation::AssertNear
+            | Operation::Equal
+            | Operation::NotEqual
+            | Operation::Between
+            | Operation::SetTolerance(_) => Category::Testing,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coerce_pair() {
+        assert_eq!(
+            Value::Integer(1).coerce_pair(Value::Float(0.5)),
+            (Value::Float(1.0), Value::Float(0.5))
+        );
+        assert_eq!(
+            Value::Big(BigInt::from(1) << 64usize).coerce_pair(Value::Integer(2)),
+            (
+                Value::Big(BigInt::from(1) << 64usize),
+                Value::Big(BigInt::from(2))
+            )
+        );
+        assert_eq!(
+            Value::Integer(3).coerce_pair(Value::Integer(4)),
+            (Value::Integer(3), Value::Integer(4))
+        );
+        assert_eq!(money::parse("1.25EUR").unwrap().kind(), Kind::Float);
+    }
 }
@@ -0,0 +1,84 @@
+//! # Watch Mode
+//!
+//! `--watch FILE` evaluates a script file from scratch whenever it
+//! changes and shows the resulting stack. This turns any editor into
+//! a live notebook for a calculation.
+
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use std::time::Duration;
+
+use clac::calc::Calculator;
+
+use crate::cli::Options;
+use crate::events::{Event, Events};
+
+/// How often the file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Evaluate a script starting from `calc` and return what to show:
+/// the output of operations followed by the result or the error.
+fn evaluate(options: &Options, calc: &Calculator, script: &str) -> Vec<String> {
+    let mut calc = calc.clone();
+
+    match calc.eval(script) {
+        Ok(_) => {
+            let mut lines = calc.take_output();
+
+            lines.push(crate::format_result(options, &calc));
+            lines
+        }
+        Err(e) => vec![format!("Error: {}", e)],
+    }
+}
+
+/// Evaluate the file starting from `calc` every time it changes.
+/// This only returns on errors.
+pub fn run(options: &Options, calc: &Calculator, path: &Path) -> io::Result<()> {
+    let clear = io::stdout().is_terminal();
+    let events = Events::new();
+
+    events.watch(path, POLL_INTERVAL);
+
+    loop {
+        match events.next() {
+            Event::Changed(path) => {
+                if clear {
+                    print!("\x1b[2J\x1b[H");
+                }
+                for line in evaluate(options, calc, &std::fs::read_to_string(path)?) {
+                    println!("{}", line);
+                }
+            }
+            Event::InputError(e) => return Err(e),
+            // There is no input in watch mode.
+            Event::Line(_) | Event::Eof => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clac::types::Value;
+
+    #[test]
+    fn test_evaluate() {
+        let options = Options::default();
+        let calc = Calculator::default();
+
+        assert_eq!(evaluate(&options, &calc, "1 2 +\n3 *\n"), vec!["9"]);
+        assert_eq!(
+            evaluate(&options, &calc, "1 perm\n2"),
+            vec!["--------x 0001", "1 2"]
+        );
+        assert_eq!(
+            evaluate(&options, &calc.push(Value::Integer(2)), "3 *"),
+            vec!["6"]
+        );
+        assert_eq!(
+            evaluate(&options, &calc, "1\n+"),
+            vec!["Error: Stack Underflow: needs 1 more value in `+` (line 2, column 1)"]
+        );
+    }
+}